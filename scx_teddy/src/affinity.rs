@@ -0,0 +1,66 @@
+// SPDX-License-Identifier: GPL-2.0
+//! --track-affinity: CPU affinity changes of the tracked tasks, e.g. from
+//! sched_setaffinity() mid-run, polled from /proc
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::kernel::monotonic_ns;
+use crate::targets;
+
+// Changes are timestamped to within this much
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// A task's mask as first read, or a later one that differs
+pub struct Change {
+    pub tid: i32,
+    pub timestamp: u64,
+    pub cpus: Vec<usize>,
+}
+
+pub struct AffinityTracker {
+    // Last mask read per tracked TID, None until the next poll reads it
+    masks: HashMap<i32, Option<Vec<usize>>>,
+    last_poll: Instant,
+}
+
+impl AffinityTracker {
+    pub fn new() -> Self {
+        Self { masks: HashMap::new(), last_poll: Instant::now() }
+    }
+
+    /// Poll `tid` from now on, called for every event of a tracked task
+    pub fn track(&mut self, tid: i32) {
+        self.masks.entry(tid).or_insert(None);
+    }
+
+    /// Read every mask again on the next poll, for a period starting over
+    pub fn reset(&mut self) {
+        for mask in self.masks.values_mut() {
+            *mask = None;
+        }
+    }
+
+    /// Once POLL_INTERVAL passed since the last poll, the masks read for the
+    /// first time and those that changed. Exited tasks are dropped.
+    pub fn poll_if_due(&mut self) -> Vec<Change> {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return Vec::new();
+        }
+        self.last_poll = Instant::now();
+
+        let timestamp = monotonic_ns();
+        let mut changes = Vec::new();
+        self.masks.retain(|&tid, mask| {
+            let Some(cpus) = targets::read_allowed_cpus(tid) else {
+                return false;
+            };
+            if mask.as_ref() != Some(&cpus) {
+                changes.push(Change { tid, timestamp, cpus: cpus.clone() });
+                *mask = Some(cpus);
+            }
+            true
+        });
+        changes
+    }
+}
@@ -0,0 +1,114 @@
+// SPDX-License-Identifier: GPL-2.0
+//! Synthetic workload for the `bench` subcommand, run under teddy or the default scheduler
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Iterations per unit of work, roughly a few microseconds
+const WORK_CHUNK: u64 = 10_000;
+
+#[derive(Debug, Clone)]
+pub struct Workload {
+    /// Threads that compute without ever sleeping
+    pub cpu_workers: usize,
+    /// Threads that do one unit of work, then sleep for `sleep`
+    pub sleep_workers: usize,
+    pub sleep: Duration,
+    pub duration: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct BenchResult {
+    // Units of work done by each worker
+    pub cpu_work: Vec<u64>,
+    pub sleep_work: Vec<u64>,
+    pub elapsed: Duration,
+}
+
+// One unit of work, black_box keeps the loop from being optimized away
+fn work_chunk() -> u64 {
+    let mut x = 0u64;
+    for i in 0..WORK_CHUNK {
+        x = x.wrapping_mul(6364136223846793005).wrapping_add(i);
+    }
+    std::hint::black_box(x)
+}
+
+/// Jain's fairness index: 1.0 when every worker progressed equally, down to
+/// 1/n when a single worker did all the work
+pub fn jain_index(work: &[u64]) -> f64 {
+    let sum: f64 = work.iter().map(|&w| w as f64).sum();
+    let sum_sq: f64 = work.iter().map(|&w| (w as f64) * (w as f64)).sum();
+    if sum_sq == 0.0 {
+        return 1.0;
+    }
+    sum * sum / (work.len() as f64 * sum_sq)
+}
+
+impl Workload {
+    /// Run every worker for `duration` and collect their progress
+    pub fn run(&self) -> BenchResult {
+        let stop = AtomicBool::new(false);
+        let start = Instant::now();
+
+        let (cpu_work, sleep_work) = thread::scope(|scope| {
+            let cpu: Vec<_> = (0..self.cpu_workers)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut done = 0;
+                        while !stop.load(Ordering::Relaxed) {
+                            work_chunk();
+                            done += 1;
+                        }
+                        done
+                    })
+                })
+                .collect();
+            let sleepy: Vec<_> = (0..self.sleep_workers)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut done = 0;
+                        while !stop.load(Ordering::Relaxed) {
+                            work_chunk();
+                            done += 1;
+                            thread::sleep(self.sleep);
+                        }
+                        done
+                    })
+                })
+                .collect();
+
+            thread::sleep(self.duration);
+            stop.store(true, Ordering::Relaxed);
+
+            let join = |handles: Vec<thread::ScopedJoinHandle<'_, u64>>| -> Vec<u64> {
+                handles.into_iter().map(|handle| handle.join().unwrap_or(0)).collect()
+            };
+            (join(cpu), join(sleepy))
+        });
+
+        BenchResult {
+            cpu_work,
+            sleep_work,
+            elapsed: start.elapsed(),
+        }
+    }
+}
+
+impl BenchResult {
+    pub fn print(&self, label: &str) {
+        let secs = self.elapsed.as_secs_f64();
+        println!("Benchmark under {} ({:.1} s):", label, secs);
+        for (name, work) in [("CPU-bound", &self.cpu_work), ("Sleep-heavy", &self.sleep_work)] {
+            if work.is_empty() {
+                continue;
+            }
+            let total: u64 = work.iter().sum();
+            println!("  {} workers: {}, total work {} ({:.0}/s), fairness {:.4}",
+                name, work.len(), total, total as f64 / secs, jain_index(work));
+        }
+        let all: Vec<u64> = self.cpu_work.iter().chain(&self.sleep_work).copied().collect();
+        println!("  All workers: total work {}, fairness {:.4}", all.iter().sum::<u64>(), jain_index(&all));
+    }
+}
@@ -14,8 +14,12 @@ typedef signed int s32;
 typedef signed long s64;
 #endif
 
+#define TEDDY_COMM_LEN 16
+
 #define MODE_TID    0  
 #define MODE_TGID   1
+#define MODE_MIXED  2  // Listed in target_tids or target_tgids
+#define MODE_CGROUP 3  // In the cgroup v2 cgroup target_cgroup_id
 
 #define OTHER_DSQ 200
 #define CRITICAL_DSQ 201
@@ -26,30 +30,152 @@ typedef signed long s64;
 
 #define DSQ_NUM 6
 
+/* Frozen targets wait here, never dispatched until thawed */
+#define FROZEN_DSQ 206
+/* Freezes end on their own after this long, well before the sched_ext
+ * watchdog (30s by default) would eject the scheduler over the stall */
+#define FREEZE_MAX_SECS 20
+
 #define DEFAULT_SLICE 100 * 1000
 
+#define TARGET_TIDS_MAX 1024
+
+#define MAX_CPUS 1024
+#define CPU_FILTER_WORDS (MAX_CPUS / 64)
+
+/* How woken targets pick an idle CPU, from the config's dispatch_strategy */
+#define DISPATCH_DEFAULT     0  // scx_bpf_select_cpu_dfl
+#define DISPATCH_ROUND_ROBIN 1  // Rotate the search start across CPUs
+#define DISPATCH_STICKY      2  // Only the CPU the task last ran on
+#define DISPATCH_PACK        3  // Lowest-numbered idle CPU
+
+/* Which idle CPUs the search tries first, from the config's idle_preference */
+#define IDLE_PREFER_NONE      0  // Whichever comes first
+#define IDLE_PREFER_IDLE_CORE 1  // CPUs on a core with every SMT sibling idle
+#define IDLE_PREFER_BUSY_CORE 2  // CPUs whose core already runs something
+
 #define TIER_CRITICAL 0
 #define TIER_INTERACTIVE 1
 #define TIER_NORMAL 2
 #define TIER_OTHER 3
 
+/* Kind of sleep a task entered when it last switched out */
+#define SLEEP_INTERRUPTIBLE 0    // S
+#define SLEEP_UNINTERRUPTIBLE 1  // D
+
 typedef struct target_ctx {
     s32 prio; // 0, 1, 2
     u64 slice; // ns
     u8 config;
     /* | 7 bits NOP | 1 bits ecore |*/
+#define TASK_CONFIG_ECORE (1 << 0)
     u64 runtime_ns;
     u64 start_running;
     u64 sleep_start;
     u64 sleep_end;
+    /* Soft CPU budget, disabled when budget_ns is 0 */
+    u64 budget_ns;
+    u64 window_ns;
+    u64 window_start;
+    u64 window_runtime;
+    u8 throttled;
+    /* Context switches since the last event */
+    u32 nr_preempted;
+    u32 nr_voluntary;
+    /* State residency since the last event */
+    u64 runnable_at;  // When the task last became runnable, 0 while running or asleep
+    u64 wait_ns;      // Runnable but waiting for a CPU
+    u64 sleep_d_ns;
+    u64 sleep_s_ns;
+    u8 sleep_state;
+    s32 stack_id;  // Kernel stack the task went to sleep in, negative if none
+    /* Proportional slice share, 0 uses slice as is */
+    u32 weight;
+    u8 weight_counted;  // Included in the runnable weight total
+    s32 waker_tid;  // Task that made this one runnable, 0 from interrupts
+    u64 woken_at;           // Last wakeup, 0 once the task ran after it
+    u64 wakeup_latency_ns;  // Wakeup to running of the last wakeup
+    /* Keep running past the slice until a task of the same or a higher tier
+     * has waited this long, 0 disables */
+    u64 preempt_latency_ns;
+    u8 frozen;  // Listed in frozen_tids as of the last enqueue
+    u8 runnable_counted;  // Included in nr_runnable_targets
+    /* Syscall tracking for --syscall-sleeps, -1 when none */
+    s32 syscall_nr;     // Syscall in progress
+    s32 sleep_syscall;  // Syscall the task was in when it last went to sleep
+    u32 config_gen;  // target_gen the settings above were read at
 } target_ctx_t;
 
 typedef struct task_event {
     int tid;  // Thread ID (statistics are per-TID)
     int parent;
+    int tgid;
+    unsigned long long timestamp;  // When the event was emitted
     unsigned long long sleep_start;
     unsigned long long sleep_end;
     unsigned long long runtime_ns;
+    unsigned long long wait_ns;     // Runnable but waiting for a CPU
+    unsigned long long sleep_d_ns;  // Uninterruptible sleep
+    unsigned long long sleep_s_ns;  // Interruptible sleep
+    unsigned long long wakeup_latency_ns;  // Wakeup to running, 0 if the task did not sleep
+    unsigned char throttled;
+    unsigned int nr_preempted;  // Switched out while still runnable
+    unsigned int nr_voluntary;  // Switched out to sleep
+    unsigned int cpu;           // CPU the task ran on before this event
+    int stack_id;               // Kernel stack of the sleep that ended, negative if none
+    int waker_tid;              // Who ended the sleep, 0 for interrupts and idle
+    int nice;                   // From static_prio when the event was emitted
+    int sleep_syscall;          // Syscall the sleep that ended happened in, -1 if none or untracked
+    char comm[TEDDY_COMM_LEN];
 } task_event_t;
 
+/* Frames kept per kernel stack for off-CPU flamegraphs */
+#define MAX_STACK_DEPTH 127
+
 #define CONFIG_STOP_RINGBUF 0
+
+/* Why a target task was placed where it was, for --debug-decisions */
+#define DECISION_THROTTLED 0  // Over budget, sent to OTHER_DSQ
+#define DECISION_SYNC      1  // Dispatched to the waker's CPU
+#define DECISION_IDLE      2  // Dispatched straight to an idle CPU
+#define DECISION_WAKEUP    3  // Queued on a tier wakeup DSQ
+#define DECISION_QUEUED    4  // Queued on a tier DSQ
+
+/* dsq of a decision that inserted into a CPU's local DSQ */
+#define DECISION_DSQ_LOCAL 0
+
+typedef struct decision_event {
+    u64 timestamp;
+    u64 slice;
+    s32 tid;
+    u32 reason;
+    u32 dsq;
+    s32 cpu;                    // CPU of a local insert, else the deciding CPU
+} decision_event_t;
+
+/* The load factor is sampled at most this often, from ops.tick() */
+#define LOAD_SAMPLE_NS (10 * 1000 * 1000)
+/* Load factors are stored in thousandths */
+#define LOAD_SCALE 1000
+
+/* Scheduler counters, one copy per CPU in the stats map */
+typedef struct teddy_stats {
+    u64 nr_other;      // Non-target tasks sent to OTHER_DSQ
+    u64 nr_enqueued;   // Target tasks queued on a tier DSQ
+    u64 nr_wakeup;     // ...of which went to a wakeup DSQ
+    u64 nr_direct;     // Target tasks dispatched straight to an idle CPU
+    u64 nr_sync;       // Target tasks dispatched to the waker's CPU
+    u64 nr_throttled;  // Over-budget target tasks sent to OTHER_DSQ
+    u64 nr_ecore_honored;   // on_ecore target runs that landed on an e-core
+    u64 nr_ecore_violated;  // ...and that landed on a p-core
+    u64 nr_dropped;    // Events lost to a full ring buffer
+    u64 nr_preempt_suppressed;  // Slice expiries skipped by preempt_latency_ns
+    /* Runnable targets per managed CPU, times LOAD_SCALE, sampled by ticks */
+    u64 nr_load_samples;
+    u64 load_sum;
+    u64 load_peak;     // Highest sample taken on this CPU
+    /* Idle CPU searches under an idle_preference that found the preferred
+     * kind of CPU, and that fell back to another idle CPU or found none */
+    u64 nr_idle_pref_found;
+    u64 nr_idle_pref_missed;
+} teddy_stats_t;
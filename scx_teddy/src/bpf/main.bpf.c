@@ -9,6 +9,11 @@
 
 char _license[] SEC("license") = "GPL";
 
+/* include/linux/sched.h, not exported through BTF */
+#ifndef TASK_UNINTERRUPTIBLE
+#define TASK_UNINTERRUPTIBLE 0x00000002
+#endif
+
 UEI_DEFINE(uei);
 
 struct {
@@ -18,11 +23,70 @@ struct {
     __type(value, target_ctx_t);
 } task_ctx SEC(".maps");
 
+/*
+ * Per-task settings, double-buffered for config reloads: target_gen picks
+ * the active map. Userspace fills the inactive one completely, then bumps
+ * target_gen, so each lookup sees either the whole old or the whole new
+ * config, never a mix.
+ */
+struct {
+    __uint(type, BPF_MAP_TYPE_HASH);
+    __uint(max_entries, TARGET_TIDS_MAX);
+    __type(key, s32);
+    __type(value, target_ctx_t);
+} target_tids SEC(".maps");
+
+struct {
+    __uint(type, BPF_MAP_TYPE_HASH);
+    __uint(max_entries, TARGET_TIDS_MAX);
+    __type(key, s32);
+    __type(value, target_ctx_t);
+} target_tids_alt SEC(".maps");
+
+/* Every thread of these TGIDs is a target in MODE_MIXED */
+struct {
+    __uint(type, BPF_MAP_TYPE_HASH);
+    __uint(max_entries, TARGET_TIDS_MAX);
+    __type(key, s32);
+    __type(value, u8);
+} target_tgids SEC(".maps");
+
+/* TIDs never scheduled as targets nor reported, even inside the target tgid */
+struct {
+    __uint(type, BPF_MAP_TYPE_HASH);
+    __uint(max_entries, TARGET_TIDS_MAX);
+    __type(key, s32);
+    __type(value, u8);
+} excluded_tids SEC(".maps");
+
+/* TIDs held off the CPU by `scx_teddy freeze`, to when they were frozen
+ * (bpf_ktime_get_ns); userspace only adds managed TIDs */
+struct {
+    __uint(type, BPF_MAP_TYPE_HASH);
+    __uint(max_entries, TARGET_TIDS_MAX);
+    __type(key, s32);
+    __type(value, u64);
+} frozen_tids SEC(".maps");
+
+/* Kernel stacks captured at sched_switch, resized to 1 unless --offcpu-flamegraph */
+struct {
+    __uint(type, BPF_MAP_TYPE_STACK_TRACE);
+    __uint(max_entries, 16384);
+    __type(key, u32);
+    __uint(value_size, MAX_STACK_DEPTH * sizeof(u64));
+} stack_traces SEC(".maps");
+
 struct {
     __uint(type, BPF_MAP_TYPE_RINGBUF);
     __uint(max_entries, 256 * 1024);
 } events SEC(".maps");
 
+/* Placement decisions, resized to one page unless --debug-decisions */
+struct {
+    __uint(type, BPF_MAP_TYPE_RINGBUF);
+    __uint(max_entries, 256 * 1024);
+} decisions SEC(".maps");
+
 struct {
     __uint(type, BPF_MAP_TYPE_ARRAY);
     __uint(max_entries, 1);
@@ -30,12 +94,228 @@ struct {
     __type(value, u32);
 } scheduler_config SEC(".maps");
 
+struct {
+    __uint(type, BPF_MAP_TYPE_PERCPU_ARRAY);
+    __uint(max_entries, 1);
+    __type(key, u32);
+    __type(value, teddy_stats_t);
+} stats SEC(".maps");
+
+#define stat_inc(field)                                         \
+    do {                                                        \
+        u32 __key = 0;                                          \
+        teddy_stats_t *__s = bpf_map_lookup_elem(&stats, &__key); \
+        if (__s)                                                \
+            __s->field++;                                       \
+    } while (0)
+
 s32 target_mode = 0;
 s32 target_single_tid = 0;
 s32 target_single_tgid = 0;
+/* cgroup v2 id of the config's cgroup, 0 when unset; MODE_MIXED matches it too */
+u64 target_cgroup_id = 0;
 
 u64 normal_task_cpu = 0;
 
+/* Config generation: even uses target_tids, odd target_tids_alt.
+ * Bumped by userspace once per reload. */
+u32 target_gen = 0;
+
+static __always_inline target_ctx_t *lookup_target_tid(u32 gen, s32 tid)
+{
+    if (gen & 1)
+        return bpf_map_lookup_elem(&target_tids_alt, &tid);
+    return bpf_map_lookup_elem(&target_tids, &tid);
+}
+
+/* Settings for target tasks without an entry in target_tids */
+u32 default_enabled = 0;
+s32 default_prio = TIER_NORMAL;
+u64 default_slice = DEFAULT_SLICE;
+u8 default_config = 1;
+u64 default_budget_ns = 0;
+u64 default_window_ns = 0;
+u32 default_weight = 0;
+u64 default_preempt_latency_ns = 0;
+
+/* Weights of the weighted target tasks that are currently runnable */
+u64 runnable_weight_total = 0;
+u64 runnable_weighted = 0;
+
+/* Runnable (queued or running) target tasks, for the load factor */
+u64 nr_runnable_targets = 0;
+/* CPUs targets may run on, set by userspace; 0 counts every possible CPU */
+u32 nr_managed_cpus = 0;
+u64 load_sampled_at;
+
+/* While set, every task gets the default (OTHER_DSQ) treatment */
+u32 paused = 0;
+
+/* Set when excluded_tids has entries, saves the lookup otherwise */
+u32 exclude_enabled = 0;
+
+/* Only report events that ran on CPUs set in this mask */
+u32 cpu_filter_enabled = 0;
+u64 cpu_filter[CPU_FILTER_WORDS];
+
+static __always_inline bool cpu_filtered(u32 cpu)
+{
+    if (!cpu_filter_enabled)
+        return false;
+    if (cpu >= MAX_CPUS)
+        return true;
+
+    return !(cpu_filter[cpu / 64] & (1ULL << (cpu % 64)));
+}
+
+/* Targets only run on these CPUs, the first max_cpus online ones from the config */
+u32 managed_cpus_enabled = 0;
+u64 managed_cpus[CPU_FILTER_WORDS];
+u32 managed_cpus_last;  // Highest CPU in the mask
+
+static __always_inline bool cpu_managed(s32 cpu)
+{
+    if (!managed_cpus_enabled)
+        return true;
+    if (cpu < 0 || cpu >= MAX_CPUS)
+        return false;
+
+    return managed_cpus[cpu / 64] & (1ULL << (cpu % 64));
+}
+
+// Neither the task's affinity nor max_cpus rules the CPU out
+static __always_inline bool cpu_allowed(struct task_struct *p, s32 cpu)
+{
+    return cpu_managed(cpu) && bpf_cpumask_test_cpu(cpu, p->cpus_ptr);
+}
+
+// The task's affinity has no CPU in common with max_cpus. Stops at the first
+// shared CPU, which for an unrestricted task is the first managed one.
+static __always_inline bool affinity_outside_managed(struct task_struct *p)
+{
+    u32 nr_cpus = managed_cpus_last + 1;
+    s32 cpu;
+
+    if (!managed_cpus_enabled)
+        return false;
+    if (nr_cpus > MAX_CPUS)
+        nr_cpus = MAX_CPUS;
+    bpf_for(cpu, 0, nr_cpus) {
+        if (cpu_allowed(p, cpu))
+            return false;
+    }
+    return true;
+}
+
+/* Efficiency cores, set by userspace on hybrid machines only */
+u32 ecore_mask_enabled = 0;
+u64 ecore_mask[CPU_FILTER_WORDS];
+
+static __always_inline bool is_ecore(u32 cpu)
+{
+    if (cpu >= MAX_CPUS)
+        return false;
+
+    return ecore_mask[cpu / 64] & (1ULL << (cpu % 64));
+}
+
+u32 debug_decisions = 0;
+u32 decision_sample = 1;   // Log one in this many decisions
+u32 decision_rate = 1000;  // Decisions logged per second, 0 for no limit
+u64 decision_seq;
+u64 decision_window_start;
+u32 decision_window_count;
+
+static void log_decision(struct task_struct *p, u32 reason, u32 dsq, u64 slice, s32 cpu)
+{
+    if (!debug_decisions)
+        return;
+    if (decision_sample > 1 && __sync_fetch_and_add(&decision_seq, 1) % decision_sample)
+        return;
+
+    // Approximate across CPUs, only meant to bound the output
+    u64 now = scx_bpf_now();
+    if (decision_rate) {
+        if (now - decision_window_start >= 1000000000ULL) {
+            decision_window_start = now;
+            decision_window_count = 0;
+        }
+        if (decision_window_count >= decision_rate)
+            return;
+        __sync_fetch_and_add(&decision_window_count, 1);
+    }
+
+    decision_event_t *e = bpf_ringbuf_reserve(&decisions, sizeof(decision_event_t), 0);
+    if (!e)
+        return;
+    e->timestamp = now;
+    e->slice = slice;
+    e->tid = p->pid;
+    e->reason = reason;
+    e->dsq = dsq;
+    e->cpu = cpu;
+    bpf_ringbuf_submit(e, 0);
+}
+
+static __always_inline bool is_excluded(struct task_struct *p)
+{
+    s32 tid = p->pid;
+
+    if (!exclude_enabled)
+        return false;
+    return bpf_map_lookup_elem(&excluded_tids, &tid) != NULL;
+}
+
+// bpf_get_current_cgroup_id() describes the running task, the callbacks
+// need the cgroup of the task being scheduled
+static __always_inline u64 task_cgroup_id(struct task_struct *task)
+{
+    return BPF_CORE_READ(task, cgroups, dfl_cgrp, kn, id);
+}
+
+static __always_inline bool is_target(struct task_struct *task)
+{
+    int tid = task->pid;
+    int tgid = task->tgid;
+
+    bool member = false;
+
+    if (paused)
+        return false;
+
+    switch (target_mode) {
+    case MODE_TID:
+        if (target_single_tid)
+            member = tid == target_single_tid;
+        else
+            member = lookup_target_tid(target_gen, tid) != NULL;
+        break;
+
+    case MODE_TGID:
+        if (target_single_tgid)
+            member = tgid == target_single_tgid;
+        break;
+
+    case MODE_MIXED:
+        member = lookup_target_tid(target_gen, tid) != NULL ||
+                 bpf_map_lookup_elem(&target_tgids, &tgid) != NULL ||
+                 (target_cgroup_id && task_cgroup_id(task) == target_cgroup_id);
+        break;
+
+    case MODE_CGROUP:
+        member = target_cgroup_id && task_cgroup_id(task) == target_cgroup_id;
+        break;
+    }
+
+    return member && !is_excluded(task);
+}
+
+// --mode all: emit events for every task, not only targets
+u32 trace_all = 0;
+u64 trace_min_runtime_ns = 0; // Skip non-target events that ran for less
+u32 trace_sample = 1;         // Emit one in this many non-target events
+u64 trace_seq;
+
 static void data_to_user(struct task_struct *p, target_ctx_t *target_ctx)
 {
     u32 key = CONFIG_STOP_RINGBUF;
@@ -44,16 +324,44 @@ static void data_to_user(struct task_struct *p, target_ctx_t *target_ctx)
     if (*stop_ringbuf)
         goto clear_tracing_data;
 
+    if (!is_target(p)) {
+        if (!trace_all || target_ctx->runtime_ns < trace_min_runtime_ns)
+            goto clear_tracing_data;
+        if (trace_sample > 1 && __sync_fetch_and_add(&trace_seq, 1) % trace_sample)
+            goto clear_tracing_data;
+    }
+
+    u32 cpu = bpf_get_smp_processor_id();
+    if (cpu_filtered(cpu) || is_excluded(p))
+        goto clear_tracing_data;
+
     task_event_t *e = bpf_ringbuf_reserve(&events, sizeof(task_event_t), 0);
-    if (!e)
-        return; // Ring buffer full, drop event
+    if (!e) {
+        stat_inc(nr_dropped); // Ring buffer full, drop event
+        return;
+    }
 
     // Fill event data
     e->tid = p->pid;
     e->parent = p->real_parent->pid;
+    e->tgid = p->tgid;
+    e->timestamp = scx_bpf_now();
     e->sleep_start = target_ctx->sleep_start;
     e->sleep_end = target_ctx->sleep_end;
     e->runtime_ns = target_ctx->runtime_ns;
+    e->wait_ns = target_ctx->wait_ns;
+    e->sleep_d_ns = target_ctx->sleep_d_ns;
+    e->sleep_s_ns = target_ctx->sleep_s_ns;
+    e->wakeup_latency_ns = target_ctx->wakeup_latency_ns;
+    e->throttled = target_ctx->throttled;
+    e->nr_preempted = target_ctx->nr_preempted;
+    e->nr_voluntary = target_ctx->nr_voluntary;
+    e->cpu = cpu;
+    e->stack_id = target_ctx->stack_id;
+    e->waker_tid = target_ctx->waker_tid;
+    e->nice = p->static_prio - 120;  // DEFAULT_PRIO, nice 0
+    e->sleep_syscall = target_ctx->sleep_syscall;
+    bpf_probe_read_kernel_str(e->comm, sizeof(e->comm), p->comm);
 
     // Submit to ring buffer
     bpf_ringbuf_submit(e, 0);
@@ -62,66 +370,333 @@ clear_tracing_data:
     // Clear tracing data
     target_ctx->runtime_ns = 0;
     target_ctx->sleep_end = 0;
+    target_ctx->nr_preempted = 0;
+    target_ctx->nr_voluntary = 0;
+    target_ctx->wait_ns = target_ctx->sleep_d_ns = target_ctx->sleep_s_ns = 0;
+    target_ctx->wakeup_latency_ns = 0;
+}
+
+static __always_inline void weight_add(target_ctx_t *target_ctx)
+{
+    if (!target_ctx->weight || target_ctx->weight_counted)
+        return;
+    __sync_fetch_and_add(&runnable_weight_total, target_ctx->weight);
+    __sync_fetch_and_add(&runnable_weighted, 1);
+    target_ctx->weight_counted = 1;
+}
+
+static __always_inline void weight_sub(target_ctx_t *target_ctx)
+{
+    if (!target_ctx->weight_counted)
+        return;
+    __sync_fetch_and_sub(&runnable_weight_total, target_ctx->weight);
+    __sync_fetch_and_sub(&runnable_weighted, 1);
+    target_ctx->weight_counted = 0;
+}
+
+static __always_inline void runnable_add(target_ctx_t *target_ctx)
+{
+    if (target_ctx->runnable_counted)
+        return;
+    __sync_fetch_and_add(&nr_runnable_targets, 1);
+    target_ctx->runnable_counted = 1;
+}
+
+static __always_inline void runnable_sub(target_ctx_t *target_ctx)
+{
+    if (!target_ctx->runnable_counted)
+        return;
+    __sync_fetch_and_sub(&nr_runnable_targets, 1);
+    target_ctx->runnable_counted = 0;
+}
+
+// Record the current load factor, at most once per LOAD_SAMPLE_NS across CPUs
+static __always_inline void sample_load(void)
+{
+    // Approximate across CPUs, two CPUs may both take a sample now and then
+    u64 now = scx_bpf_now();
+    if (now - load_sampled_at < LOAD_SAMPLE_NS)
+        return;
+    load_sampled_at = now;
+
+    u32 key = 0;
+    teddy_stats_t *s = bpf_map_lookup_elem(&stats, &key);
+    if (!s)
+        return;
+    u32 nr_cpus = nr_managed_cpus ? nr_managed_cpus : scx_bpf_nr_cpu_ids();
+    u64 load = nr_runnable_targets * LOAD_SCALE / (nr_cpus ? nr_cpus : 1);
+    s->nr_load_samples++;
+    s->load_sum += load;
+    if (load > s->load_peak)
+        s->load_peak = load;
+}
+
+/*
+ * Weighted tasks get slice scaled by weight over the average weight of the
+ * runnable weighted tasks, so their slices split time proportionally.
+ */
+static __always_inline u64 task_slice(target_ctx_t *target_ctx)
+{
+    u64 total = runnable_weight_total;
+    u64 nr = runnable_weighted;
+
+    if (!target_ctx->weight || !total || !nr)
+        return target_ctx->slice;
+
+    u64 slice = target_ctx->slice * target_ctx->weight * nr / total;
+    return slice ? slice : 1;
+}
+
+// Copy the task's settings from the config generation `gen`
+static __always_inline void load_settings(struct task_struct *p, target_ctx_t *target_ctx, u32 gen)
+{
+    target_ctx_t *cfg = lookup_target_tid(gen, p->pid);
+    if (cfg) {
+        // Per-task settings written by userspace from the config file
+        target_ctx->slice = cfg->slice;
+        target_ctx->prio = cfg->prio;
+        target_ctx->config = cfg->config;
+        target_ctx->budget_ns = cfg->budget_ns;
+        target_ctx->window_ns = cfg->window_ns;
+        target_ctx->weight = cfg->weight;
+        target_ctx->preempt_latency_ns = cfg->preempt_latency_ns;
+    } else if (default_enabled) {
+        target_ctx->slice = default_slice;
+        target_ctx->prio = default_prio;
+        target_ctx->config = default_config;
+        target_ctx->budget_ns = default_budget_ns;
+        target_ctx->window_ns = default_window_ns;
+        target_ctx->weight = default_weight;
+        target_ctx->preempt_latency_ns = default_preempt_latency_ns;
+    } else {
+        target_ctx->slice = DEFAULT_SLICE;
+        target_ctx->prio = TIER_NORMAL;
+        target_ctx->config = 1;
+        target_ctx->budget_ns = target_ctx->window_ns = 0;
+        target_ctx->weight = 0;
+        target_ctx->preempt_latency_ns = 0;
+    }
+    // Only tiers up to TIER_NORMAL have DSQs, enqueueing into a missing one
+    // would eject the scheduler
+    if (target_ctx->prio < TIER_CRITICAL || target_ctx->prio > TIER_NORMAL)
+        target_ctx->prio = TIER_NORMAL;
+    target_ctx->config_gen = gen;
 }
 
 static target_ctx_t *get_target_storage(struct task_struct *p)
 {
     target_ctx_t *target_ctx;
     target_ctx = bpf_task_storage_get(&task_ctx, p, 0, 0);
+    u32 gen = target_gen;
+
+    // A reload since the settings were read: switch to the new ones, moving
+    // the task's weight in the runnable total along with them
+    if (likely(target_ctx) && unlikely(target_ctx->config_gen != gen)) {
+        bool counted = target_ctx->weight_counted;
+        weight_sub(target_ctx);
+        load_settings(p, target_ctx, gen);
+        if (counted)
+            weight_add(target_ctx);
+    }
 
     if (unlikely(!target_ctx)) {
         target_ctx = bpf_task_storage_get(&task_ctx, p, 0,
                                BPF_LOCAL_STORAGE_GET_F_CREATE);
         if (unlikely(!target_ctx))
             return NULL;
-        s32 key = p->pid;
-        target_ctx->slice = DEFAULT_SLICE;
-        target_ctx->prio = TIER_OTHER;
-        target_ctx->config = 1;
+        load_settings(p, target_ctx, gen);
 
         target_ctx->start_running = target_ctx->sleep_start = target_ctx->sleep_end = target_ctx->runtime_ns = 0;
+        target_ctx->window_start = target_ctx->window_runtime = 0;
+        target_ctx->throttled = 0;
+        target_ctx->nr_preempted = target_ctx->nr_voluntary = 0;
+        target_ctx->runnable_at = target_ctx->wait_ns = 0;
+        target_ctx->sleep_d_ns = target_ctx->sleep_s_ns = 0;
+        target_ctx->sleep_state = SLEEP_INTERRUPTIBLE;
+        target_ctx->stack_id = -1;
+        target_ctx->weight_counted = 0;
+        target_ctx->frozen = 0;
+        target_ctx->runnable_counted = 0;
+        target_ctx->syscall_nr = -1;
+        target_ctx->sleep_syscall = -1;
+        target_ctx->waker_tid = 0;
+        target_ctx->woken_at = target_ctx->wakeup_latency_ns = 0;
     }
 
     return target_ctx;
 }
 
-// Check if task should be traced
-static __always_inline bool is_target(struct task_struct *task)
+// Start a new budget window once the current one has elapsed
+static __always_inline void budget_roll_window(target_ctx_t *target_ctx, u64 now)
 {
-    int tid = task->pid;
-    int tgid = task->tgid;
+    if (!target_ctx->budget_ns)
+        return;
 
-    switch (target_mode) {
-    case MODE_TID:
-        if (target_single_tid)
-            return tid == target_single_tid;
+    if (now - target_ctx->window_start >= target_ctx->window_ns) {
+        target_ctx->window_start = now;
+        target_ctx->window_runtime = 0;
+        target_ctx->throttled = 0;
+    }
+}
 
-    case MODE_TGID:
-        if (target_single_tgid)
-            return tgid == target_single_tgid;
+static __always_inline void budget_charge(target_ctx_t *target_ctx, u64 now, u64 ran)
+{
+    if (!target_ctx->budget_ns)
+        return;
+
+    budget_roll_window(target_ctx, now);
+    target_ctx->window_runtime += ran;
+    if (target_ctx->window_runtime > target_ctx->budget_ns)
+        target_ctx->throttled = 1;
+}
+
+// Over-budget tasks are deprioritized to OTHER_DSQ until their window resets
+static __always_inline bool budget_throttled(target_ctx_t *target_ctx)
+{
+    budget_roll_window(target_ctx, scx_bpf_now());
+    return target_ctx->throttled;
+}
+
+/* When each tier DSQ last became non-empty, indexed from OTHER_DSQ, 0 while empty */
+u64 dsq_waiting_since[DSQ_NUM];
+
+static __always_inline void note_queued(u64 dsq)
+{
+    u64 *since = &dsq_waiting_since[(dsq - OTHER_DSQ) % DSQ_NUM];
+    if (!*since || !scx_bpf_dsq_nr_queued(dsq))
+        *since = scx_bpf_now();
+}
+
+u32 dispatch_strategy = DISPATCH_DEFAULT;
+u32 rr_cpu;  // Where the next DISPATCH_ROUND_ROBIN search starts
+u32 idle_preference = IDLE_PREFER_NONE;
+
+// Claim an allowed idle CPU of the kind idle_preference asks for, trying from
+// `start` upwards and wrapping around. Without SMT every idle CPU is a whole
+// idle core.
+static __always_inline s32 claim_preferred_from(struct task_struct *p, u32 start, u32 nr_cpus)
+{
+    const struct cpumask *idle_cores = scx_bpf_get_idle_smtmask();
+    bool want_idle_core = idle_preference == IDLE_PREFER_IDLE_CORE;
+    s32 found = -1;
+    s32 i;
+
+    bpf_for(i, 0, nr_cpus) {
+        s32 cpu = (start + i) % nr_cpus;
+        if (cpu_allowed(p, cpu) && bpf_cpumask_test_cpu(cpu, idle_cores) == want_idle_core &&
+            scx_bpf_test_and_clear_cpu_idle(cpu)) {
+            found = cpu;
+            break;
+        }
     }
+    scx_bpf_put_idle_cpumask(idle_cores);
+    return found;
+}
+
+// Claim an allowed idle CPU, trying from `start` upwards and wrapping around,
+// preferred ones first
+static __always_inline s32 claim_idle_from(struct task_struct *p, u32 start, u32 nr_cpus)
+{
+    s32 i;
 
-    return false;
+    if (idle_preference != IDLE_PREFER_NONE) {
+        s32 cpu = claim_preferred_from(p, start, nr_cpus);
+        if (cpu >= 0) {
+            stat_inc(nr_idle_pref_found);
+            return cpu;
+        }
+        stat_inc(nr_idle_pref_missed);
+    }
+
+    bpf_for(i, 0, nr_cpus) {
+        s32 cpu = (start + i) % nr_cpus;
+        if (cpu_allowed(p, cpu) && scx_bpf_test_and_clear_cpu_idle(cpu))
+            return cpu;
+    }
+    return -1;
+}
+
+// CPU for a woken target; *is_idle tells whether it was claimed idle
+static s32 strategy_select_cpu(struct task_struct *p, s32 prev_cpu, u64 wake_flags, bool *is_idle)
+{
+    u32 nr_cpus = scx_bpf_nr_cpu_ids();
+    s32 cpu;
+
+    switch (dispatch_strategy) {
+    case DISPATCH_ROUND_ROBIN:
+        cpu = claim_idle_from(p, __sync_fetch_and_add(&rr_cpu, 1), nr_cpus);
+        break;
+    case DISPATCH_STICKY:
+        cpu = cpu_allowed(p, prev_cpu) &&
+              scx_bpf_test_and_clear_cpu_idle(prev_cpu) ? prev_cpu : -1;
+        break;
+    case DISPATCH_PACK:
+        cpu = claim_idle_from(p, 0, nr_cpus);
+        break;
+    default:
+        // The default picker knows nothing of max_cpus or idle_preference,
+        // search from prev_cpu instead
+        if (managed_cpus_enabled || idle_preference != IDLE_PREFER_NONE) {
+            cpu = claim_idle_from(p, prev_cpu, nr_cpus);
+            break;
+        }
+        return scx_bpf_select_cpu_dfl(p, prev_cpu, wake_flags, is_idle);
+    }
+
+    *is_idle = cpu >= 0;
+    return *is_idle ? cpu : prev_cpu;
+}
+
+// Whether p is frozen, kept in target_ctx->frozen. A freeze older than
+// FREEZE_MAX_SECS is dropped here.
+static bool freeze_check(struct task_struct *p, target_ctx_t *target_ctx)
+{
+    s32 tid = p->pid;
+    u64 *since = bpf_map_lookup_elem(&frozen_tids, &tid);
+
+    if (since && bpf_ktime_get_ns() - *since >= FREEZE_MAX_SECS * 1000000000ULL) {
+        bpf_map_delete_elem(&frozen_tids, &tid);
+        since = NULL;
+    }
+    target_ctx->frozen = since != NULL;
+    return target_ctx->frozen;
 }
 
 static __always_inline s32 dispatch_sync_cold(struct task_struct *p, u64 wake_flags)
 {
     u32 cpu = bpf_get_smp_processor_id();
-    if (!bpf_cpumask_test_cpu(cpu, p->cpus_ptr))
+    if (!cpu_allowed(p, cpu))
         return -1;
 
     target_ctx_t *target_ctx = get_target_storage(p);
     if (!target_ctx)
         return -1;
-    scx_bpf_dsq_insert(p, SCX_DSQ_LOCAL_ON | (u64)cpu, target_ctx->slice, wake_flags);
+    u64 slice = task_slice(target_ctx);
+    scx_bpf_dsq_insert(p, SCX_DSQ_LOCAL_ON | (u64)cpu, slice, wake_flags);
+    stat_inc(nr_sync);
+    log_decision(p, DECISION_SYNC, DECISION_DSQ_LOCAL, slice, cpu);
     return (s32)cpu;
 }
 
 s32 BPF_STRUCT_OPS(teddy_select_cpu, struct task_struct *p, s32 prev_cpu,
                    u64 wake_flags)
 {
-    if (!is_target(p)) {
+    if (!is_target(p) || affinity_outside_managed(p)) {
         scx_bpf_dsq_insert(p, OTHER_DSQ, DEFAULT_SLICE, wake_flags);
+        stat_inc(nr_other);
+        return prev_cpu;
+    }
+    target_ctx_t *target_ctx = get_target_storage(p);
+    if (!target_ctx)
+        return prev_cpu;
+    if (budget_throttled(target_ctx)) {
+        scx_bpf_dsq_insert(p, OTHER_DSQ, target_ctx->slice, wake_flags);
+        stat_inc(nr_throttled);
+        log_decision(p, DECISION_THROTTLED, OTHER_DSQ, target_ctx->slice, bpf_get_smp_processor_id());
+        return prev_cpu;
+    }
+    if (freeze_check(p, target_ctx)) {
+        scx_bpf_dsq_insert(p, FROZEN_DSQ, target_ctx->slice, wake_flags);
         return prev_cpu;
     }
     // p is woken by this cpu
@@ -130,14 +705,15 @@ s32 BPF_STRUCT_OPS(teddy_select_cpu, struct task_struct *p, s32 prev_cpu,
         if (sync_cpu >= 0)
             return sync_cpu;
     }
+
     bool is_idle;
-    s32 cpu = scx_bpf_select_cpu_dfl(p, prev_cpu, wake_flags, &is_idle);
+    s32 cpu = strategy_select_cpu(p, prev_cpu, wake_flags, &is_idle);
 
     if (is_idle) {
-        target_ctx_t *target_ctx = get_target_storage(p);
-        if (!target_ctx)
-            return prev_cpu;
-        scx_bpf_dsq_insert(p, SCX_DSQ_LOCAL_ON | (u64)cpu, target_ctx->slice, wake_flags);
+        u64 slice = task_slice(target_ctx);
+        scx_bpf_dsq_insert(p, SCX_DSQ_LOCAL_ON | (u64)cpu, slice, wake_flags);
+        stat_inc(nr_direct);
+        log_decision(p, DECISION_IDLE, DECISION_DSQ_LOCAL, slice, cpu);
         return cpu;
     }
 
@@ -146,39 +722,126 @@ s32 BPF_STRUCT_OPS(teddy_select_cpu, struct task_struct *p, s32 prev_cpu,
 
 void BPF_STRUCT_OPS(teddy_enqueue, struct task_struct *p, u64 enq_flags)
 {
-    if (unlikely(!is_target(p))) {
+    // A target that may not run on any managed CPU would never be dispatched
+    if (unlikely(!is_target(p) || affinity_outside_managed(p))) {
         scx_bpf_dsq_insert(p, OTHER_DSQ, DEFAULT_SLICE, enq_flags);
+        stat_inc(nr_other);
         return;
     }
     target_ctx_t *target_ctx = get_target_storage(p);
     if (!target_ctx)
         return;
+    if (budget_throttled(target_ctx)) {
+        scx_bpf_dsq_insert(p, OTHER_DSQ, target_ctx->slice, enq_flags);
+        stat_inc(nr_throttled);
+        log_decision(p, DECISION_THROTTLED, OTHER_DSQ, target_ctx->slice, bpf_get_smp_processor_id());
+        return;
+    }
+    if (freeze_check(p, target_ctx)) {
+        scx_bpf_dsq_insert(p, FROZEN_DSQ, target_ctx->slice, enq_flags);
+        return;
+    }
+    stat_inc(nr_enqueued);
+    u64 slice = task_slice(target_ctx);
     if ((enq_flags & SCX_ENQ_WAKEUP) && target_ctx->prio != TIER_NORMAL) {
-        scx_bpf_dsq_insert(p, CRITICAL_WAKEUP_DSQ + target_ctx->prio, target_ctx->slice, enq_flags);
+        note_queued(CRITICAL_WAKEUP_DSQ + target_ctx->prio);
+        scx_bpf_dsq_insert(p, CRITICAL_WAKEUP_DSQ + target_ctx->prio, slice, enq_flags);
+        stat_inc(nr_wakeup);
+        log_decision(p, DECISION_WAKEUP, CRITICAL_WAKEUP_DSQ + target_ctx->prio, slice, bpf_get_smp_processor_id());
+        return;
+    }
+
+    note_queued(CRITICAL_DSQ + target_ctx->prio);
+    scx_bpf_dsq_insert(p, CRITICAL_DSQ + target_ctx->prio, slice, enq_flags);
+    log_decision(p, DECISION_QUEUED, CRITICAL_DSQ + target_ctx->prio, slice, bpf_get_smp_processor_id());
+}
+
+// Hand tasks thawed since they were parked in FROZEN_DSQ to this CPU
+static __always_inline void thaw_frozen(s32 cpu)
+{
+    struct task_struct *p;
+
+    if (!scx_bpf_dsq_nr_queued(FROZEN_DSQ))
         return;
+    bpf_for_each(scx_dsq, p, FROZEN_DSQ, 0) {
+        target_ctx_t *target_ctx = bpf_task_storage_get(&task_ctx, p, 0, 0);
+        if (!target_ctx || freeze_check(p, target_ctx) || !cpu_allowed(p, cpu))
+            continue;
+        scx_bpf_dsq_move(BPF_FOR_EACH_ITER, p, SCX_DSQ_LOCAL, 0);
     }
+}
 
-    scx_bpf_dsq_insert(p, CRITICAL_DSQ + target_ctx->prio, target_ctx->slice, enq_flags);
+// Move the head of `dsq` to the local DSQ. The next waiter's own queueing
+// time is unknown, so its wait is counted from now.
+static __always_inline bool move_from(u64 dsq)
+{
+    if (!scx_bpf_dsq_move_to_local(dsq))
+        return false;
+    if (dsq >= CRITICAL_DSQ)
+        dsq_waiting_since[(dsq - OTHER_DSQ) % DSQ_NUM] = scx_bpf_dsq_nr_queued(dsq) ? scx_bpf_now() : 0;
+    return true;
 }
 
 void BPF_STRUCT_OPS(teddy_dispatch, s32 cpu, struct task_struct *prev)
 {
-    if (scx_bpf_dsq_move_to_local(CRITICAL_WAKEUP_DSQ))
+    // CPUs past max_cpus leave the tier DSQs, and so the targets, alone
+    if (!cpu_managed(cpu)) {
+        move_from(OTHER_DSQ);
+        return;
+    }
+    thaw_frozen(cpu);
+    if (move_from(CRITICAL_WAKEUP_DSQ))
         return;
-    else if (scx_bpf_dsq_move_to_local(INTERACTIVE_WAKEUP_DSQ))
+    else if (move_from(INTERACTIVE_WAKEUP_DSQ))
         return;
-    else if (scx_bpf_dsq_move_to_local(CRITICAL_DSQ))
+    else if (move_from(CRITICAL_DSQ))
         return;
-    else if (scx_bpf_dsq_move_to_local(INTERACTIVE_DSQ))
+    else if (move_from(INTERACTIVE_DSQ))
         return;
-    else if (scx_bpf_dsq_move_to_local(NORMAL_DSQ))
+    else if (move_from(NORMAL_DSQ))
         return;
-    else if (scx_bpf_dsq_move_to_local(OTHER_DSQ))
+    else if (move_from(OTHER_DSQ))
         return;
 }
 
+// Longest wait, roughly, among the tier DSQs dispatched before or alongside `prio`
+static __always_inline u64 oldest_wait(s32 prio, u64 now)
+{
+    u64 oldest = 0;
+    s32 tier;
+
+    bpf_for(tier, TIER_CRITICAL, TIER_NORMAL + 1) {
+        if (tier > prio)
+            break;
+        u64 dsqs[2] = { CRITICAL_DSQ + tier, CRITICAL_WAKEUP_DSQ + tier };
+        for (int i = 0; i < (tier == TIER_NORMAL ? 1 : 2); i++) {
+            u64 since = dsq_waiting_since[(dsqs[i] - OTHER_DSQ) % DSQ_NUM];
+            if (since && scx_bpf_dsq_nr_queued(dsqs[i]) && now - since > oldest)
+                oldest = now - since;
+        }
+    }
+    return oldest;
+}
+
+// A target whose slice just ran out keeps the CPU while every task of its tier
+// or above has waited less than its preempt_latency_ns. Ticks also sample the
+// load factor.
 void BPF_STRUCT_OPS(teddy_tick, struct task_struct *p)
 {
+    sample_load();
+    if (p->scx.slice || !is_target(p))
+        return;
+    target_ctx_t *target_ctx = bpf_task_storage_get(&task_ctx, p, 0, 0);
+    if (!target_ctx || !target_ctx->preempt_latency_ns || budget_throttled(target_ctx))
+        return;
+
+    u64 now = scx_bpf_now();
+    u64 waited = oldest_wait(target_ctx->prio, now);
+    // Nobody waiting means no preemption to suppress; the task is picked again
+    if (!waited || waited >= target_ctx->preempt_latency_ns)
+        return;
+    p->scx.slice = task_slice(target_ctx);
+    stat_inc(nr_preempt_suppressed);
 }
 
 /* Initialize the scheduler */
@@ -190,7 +853,7 @@ s32 BPF_STRUCT_OPS_SLEEPABLE(teddy_init)
             return ret;
     }
 
-    return 0;
+    return scx_bpf_create_dsq(FROZEN_DSQ, -1);
 }
 
 void BPF_STRUCT_OPS(teddy_runnable, struct task_struct *p, u64 enq_flags)
@@ -198,7 +861,26 @@ void BPF_STRUCT_OPS(teddy_runnable, struct task_struct *p, u64 enq_flags)
     target_ctx_t *target_ctx = get_target_storage(p);
     if (!target_ctx)
         return;
-    target_ctx->sleep_end = scx_bpf_now();
+    u64 now = scx_bpf_now();
+    target_ctx->sleep_end = now;
+    target_ctx->runnable_at = now;
+    target_ctx->woken_at = now;
+    if (is_target(p)) {
+        weight_add(target_ctx);
+        runnable_add(target_ctx);
+    }
+
+    // Wakeups run in the waker's context
+    if (enq_flags & SCX_ENQ_WAKEUP)
+        target_ctx->waker_tid = (u32)bpf_get_current_pid_tgid();
+
+    // Attribute the sleep that just ended to the state the task entered
+    if (target_ctx->sleep_start != 0 && now > target_ctx->sleep_start) {
+        if (target_ctx->sleep_state == SLEEP_UNINTERRUPTIBLE)
+            target_ctx->sleep_d_ns += now - target_ctx->sleep_start;
+        else
+            target_ctx->sleep_s_ns += now - target_ctx->sleep_start;
+    }
 }
 
 void BPF_STRUCT_OPS(teddy_running, struct task_struct *p)
@@ -207,6 +889,23 @@ void BPF_STRUCT_OPS(teddy_running, struct task_struct *p)
     if (!target_ctx)
         return;
     target_ctx->start_running = scx_bpf_now();
+    if (target_ctx->runnable_at) {
+        target_ctx->wait_ns += target_ctx->start_running - target_ctx->runnable_at;
+        target_ctx->runnable_at = 0;
+    }
+    if (target_ctx->woken_at) {
+        target_ctx->wakeup_latency_ns = target_ctx->start_running - target_ctx->woken_at;
+        target_ctx->woken_at = 0;
+    }
+    budget_roll_window(target_ctx, target_ctx->start_running);
+
+    // Check whether the on_ecore hint held, e.g. it cannot when no e-core is idle
+    if (ecore_mask_enabled && (target_ctx->config & TASK_CONFIG_ECORE) && is_target(p)) {
+        if (is_ecore(bpf_get_smp_processor_id()))
+            stat_inc(nr_ecore_honored);
+        else
+            stat_inc(nr_ecore_violated);
+    }
 }
 
 void BPF_STRUCT_OPS(teddy_stopping, struct task_struct *p, bool runnable)
@@ -216,6 +915,19 @@ void BPF_STRUCT_OPS(teddy_stopping, struct task_struct *p, bool runnable)
     if (!target_ctx)
         return;
     target_ctx->runtime_ns += now - target_ctx->start_running;
+    budget_charge(target_ctx, now, now - target_ctx->start_running);
+
+    if (runnable) {
+        target_ctx->nr_preempted++;
+        target_ctx->runnable_at = now;
+    } else {
+        target_ctx->nr_voluntary++;
+        weight_sub(target_ctx);
+        runnable_sub(target_ctx);
+        target_ctx->sleep_state = (p->__state & TASK_UNINTERRUPTIBLE) ?
+            SLEEP_UNINTERRUPTIBLE : SLEEP_INTERRUPTIBLE;
+        target_ctx->sleep_syscall = target_ctx->syscall_nr;
+    }
 
     if (!runnable) {
         if (target_ctx->sleep_start != 0)
@@ -227,6 +939,12 @@ void BPF_STRUCT_OPS(teddy_stopping, struct task_struct *p, bool runnable)
 
 void BPF_STRUCT_OPS(teddy_exit_task, struct task_struct *p, struct scx_exit_task_args *args)
 {
+    target_ctx_t *target_ctx = bpf_task_storage_get(&task_ctx, p, 0, 0);
+    if (target_ctx) {
+        weight_sub(target_ctx);
+        runnable_sub(target_ctx);
+    }
+
     u32 key = CONFIG_STOP_RINGBUF;
     u32 *stop_ringbuf = bpf_map_lookup_elem(&scheduler_config, &key);
 
@@ -234,14 +952,29 @@ void BPF_STRUCT_OPS(teddy_exit_task, struct task_struct *p, struct scx_exit_task
         goto clear_tracing_data;
 
     task_event_t *e = bpf_ringbuf_reserve(&events, sizeof(task_event_t), 0);
-    if (!e)
+    if (!e) {
+        stat_inc(nr_dropped);
         return;
+    }
 
     e->tid = p->pid;
     e->parent = -1;
+    e->tgid = p->tgid;
+    e->timestamp = scx_bpf_now();
     e->sleep_start = 0;
     e->sleep_end = 0;
     e->runtime_ns = 0;
+    e->wait_ns = e->sleep_d_ns = e->sleep_s_ns = 0;
+    e->wakeup_latency_ns = 0;
+    e->throttled = 0;
+    e->nr_preempted = 0;
+    e->nr_voluntary = 0;
+    e->cpu = bpf_get_smp_processor_id();
+    e->stack_id = -1;
+    e->waker_tid = 0;
+    e->nice = 0;
+    e->sleep_syscall = -1;
+    bpf_probe_read_kernel_str(e->comm, sizeof(e->comm), p->comm);
 
 submit_ringbuf:
     // Submit to ring buffer
@@ -249,6 +982,48 @@ submit_ringbuf:
 clear_tracing_data:
 }
 
+/*
+ * Only loaded with --offcpu-flamegraph. sched_switch fires on prev's stack
+ * after ops.stopping(), so the stack is kept for the event that reports
+ * this sleep once it ends.
+ */
+SEC("tp_btf/sched_switch")
+int BPF_PROG(teddy_sched_switch, bool preempt, struct task_struct *prev,
+             struct task_struct *next)
+{
+    // Preempted or still runnable, not going off-CPU to sleep
+    if (preempt || !prev->__state)
+        return 0;
+
+    target_ctx_t *target_ctx = bpf_task_storage_get(&task_ctx, prev, 0, 0);
+    if (!target_ctx)
+        return 0;
+    target_ctx->stack_id = bpf_get_stackid(ctx, &stack_traces, 0);
+    return 0;
+}
+
+/*
+ * Only loaded with --syscall-sleeps. They fire for every task system-wide,
+ * so they only touch tasks that already have storage.
+ */
+SEC("tp_btf/sys_enter")
+int BPF_PROG(teddy_sys_enter, struct pt_regs *regs, long id)
+{
+    target_ctx_t *target_ctx = bpf_task_storage_get(&task_ctx, bpf_get_current_task_btf(), 0, 0);
+    if (target_ctx)
+        target_ctx->syscall_nr = id;
+    return 0;
+}
+
+SEC("tp_btf/sys_exit")
+int BPF_PROG(teddy_sys_exit, struct pt_regs *regs, long ret)
+{
+    target_ctx_t *target_ctx = bpf_task_storage_get(&task_ctx, bpf_get_current_task_btf(), 0, 0);
+    if (target_ctx)
+        target_ctx->syscall_nr = -1;
+    return 0;
+}
+
 /* Scheduler exit - record exit info */
 void BPF_STRUCT_OPS(teddy_exit, struct scx_exit_info *ei)
 {
@@ -0,0 +1,79 @@
+// SPDX-License-Identifier: GPL-2.0
+//! Resolution of the config's cgroup path to the cgroup v2 id BPF matches on
+
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+// Where systemd mounts cgroup v2 on hybrid v1/v2 systems
+const HYBRID_ROOT: &str = "/sys/fs/cgroup/unified";
+
+// Mount point of the v2 hierarchy, which is the one tasks' dfl_cgrp lives in
+fn v2_root() -> Result<&'static str> {
+    for root in [CGROUP_ROOT, HYBRID_ROOT] {
+        if Path::new(root).join("cgroup.controllers").exists() {
+            return Ok(root);
+        }
+    }
+    bail!("No cgroup v2 hierarchy at {} or {}: cgroup targets need cgroup v2, the v1 \
+        controller hierarchies are not supported (boot with systemd.unified_cgroup_hierarchy=1)",
+        CGROUP_ROOT, HYBRID_ROOT);
+}
+
+// Directory of a cgroup given either absolute or relative to the v2 mount,
+// e.g. "system.slice/foo.service"
+fn locate(path: &str) -> Result<PathBuf> {
+    let root = v2_root()?;
+    let dir = if path.starts_with(root) {
+        PathBuf::from(path)
+    } else if path.starts_with(CGROUP_ROOT) {
+        bail!("cgroup {} is not in the cgroup v2 hierarchy mounted at {}", path, root);
+    } else {
+        Path::new(root).join(path.trim_start_matches('/'))
+    };
+    if !dir.join("cgroup.procs").exists() {
+        bail!("cgroup {} does not exist", dir.display());
+    }
+    Ok(dir)
+}
+
+/// cgroup id (the directory's inode number) of `path`. Only tasks directly
+/// in the cgroup match, so it must be a leaf.
+pub fn resolve(path: &str) -> Result<u64> {
+    let dir = locate(path)?;
+
+    let meta = fs::metadata(&dir).with_context(|| format!("Failed to stat {}", dir.display()))?;
+    let children = fs::read_dir(&dir)
+        .with_context(|| format!("Failed to read {}", dir.display()))?
+        .flatten()
+        .filter(|entry| entry.file_type().is_ok_and(|kind| kind.is_dir()))
+        .count();
+    if children > 0 {
+        bail!("cgroup {} has {} child cgroup(s); tasks in child cgroups are not matched, \
+            point the config at a leaf cgroup", dir.display(), children);
+    }
+    Ok(meta.ino())
+}
+
+/// TIDs currently in the cgroup
+pub fn threads(path: &str) -> Result<Vec<i32>> {
+    let file = locate(path)?.join("cgroup.threads");
+    let threads = fs::read_to_string(&file).with_context(|| format!("Failed to read {}", file.display()))?;
+    Ok(threads.lines().filter_map(|line| line.trim().parse().ok()).collect())
+}
+
+/// cgroup v2 id of the cgroup `tid` is in, as BPF sees it
+pub fn task_cgroup_id(tid: i32) -> Result<u64> {
+    let file = format!("/proc/{}/cgroup", tid);
+    let cgroups = fs::read_to_string(&file).with_context(|| format!("Failed to read {}", file))?;
+    // The v2 hierarchy is the "0::<path>" line
+    let Some(path) = cgroups.lines().find_map(|line| line.strip_prefix("0::")) else {
+        bail!("TID {} is in no cgroup v2 cgroup", tid);
+    };
+    let dir = Path::new(v2_root()?).join(path.trim_start_matches('/'));
+    let meta = fs::metadata(&dir).with_context(|| format!("Failed to stat {}", dir.display()))?;
+    Ok(meta.ino())
+}
@@ -0,0 +1,66 @@
+// SPDX-License-Identifier: GPL-2.0
+//! ANSI highlighting for report figures worth a second look
+
+use std::fmt::Display;
+use std::io::IsTerminal;
+
+const YELLOW: &str = "\x1b[33m";
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const RESET: &str = "\x1b[0m";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ColorChoice {
+    /// Color when stdout is a terminal and NO_COLOR is unset
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Palette {
+    enabled: bool,
+}
+
+impl Palette {
+    pub fn new(choice: ColorChoice) -> Self {
+        let enabled = match choice {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            // https://no-color.org: any non-empty value disables color
+            ColorChoice::Auto => {
+                !matches!(std::env::var_os("NO_COLOR"), Some(v) if !v.is_empty())
+                    && std::io::stdout().is_terminal()
+            }
+        };
+        Self { enabled }
+    }
+
+    fn paint(&self, code: &str, text: impl Display) -> String {
+        if self.enabled {
+            format!("{}{}{}", code, text, RESET)
+        } else {
+            text.to_string()
+        }
+    }
+
+    /// Yellow when `when` holds, for figures over a soft threshold
+    pub fn warn(&self, text: impl Display, when: bool) -> String {
+        if when {
+            self.paint(YELLOW, text)
+        } else {
+            text.to_string()
+        }
+    }
+
+    /// Red, for lines that usually mean something went wrong
+    pub fn alert(&self, text: impl Display) -> String {
+        self.paint(RED, text)
+    }
+
+    /// Green, for checks that passed
+    pub fn good(&self, text: impl Display) -> String {
+        self.paint(GREEN, text)
+    }
+}
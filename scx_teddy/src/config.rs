@@ -0,0 +1,682 @@
+// SPDX-License-Identifier: GPL-2.0
+//! Scheduler config file: which tasks to manage and how
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::bpf_intf;
+use crate::error::TeddyError;
+
+/// Scheduling settings shared by explicit task entries and the default block
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TaskPolicy {
+    pub prio: i32,
+    pub slice: SliceSpec,
+    pub on_ecore: u8,
+    /// `prio` is an offset from `Config::base_prio` rather than a tier
+    #[serde(default)]
+    pub is_relative: bool,
+    /// Runtime allowed per window before the task is deprioritized, 0 disables
+    #[serde(default)]
+    pub budget_ns: u64,
+    #[serde(default)]
+    pub window_ns: u64,
+    /// Share of slice time relative to other weighted runnable targets, unweighted when unset
+    #[serde(default)]
+    pub weight: Option<u32>,
+    /// Run past an expired slice until a task of the same or a higher tier has
+    /// waited this long, 0 preempts at slice expiry as usual. Signed so a
+    /// negative value gets a validation error rather than a parse error.
+    #[serde(default)]
+    pub preempt_latency_ns: i64,
+    /// Named preset the loader filled prio/slice/on_ecore from, where not set explicitly
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub class: Option<String>,
+}
+
+/// `slice` as written: nanoseconds, or a percentage of `Config::base_slice` such as "50%"
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum SliceSpec {
+    Ns(u64),
+    Percent(String),
+}
+
+/// Knobs a `class` stands for
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ClassPreset {
+    pub prio: i32,
+    pub slice: u64,
+    pub on_ecore: u8,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TaskConfig {
+    pub tid: i32,
+    #[serde(flatten)]
+    pub policy: TaskPolicy,
+}
+
+/// Entry of a --seed-config, applied to every thread whose comm matches
+/// once it appears
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SeedTask {
+    pub comm: String,
+    #[serde(flatten)]
+    pub policy: TaskPolicy,
+}
+
+/// Task entries keyed by comm for tasks that may not exist at load time
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SeedConfig {
+    /// Baseline tier that relative priorities in `tasks` are resolved against
+    pub base_prio: Option<i32>,
+    /// Slice in ns that percentage slices in `tasks` are resolved against
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_slice: Option<u64>,
+    pub tasks: Vec<SeedTask>,
+    #[serde(default)]
+    pub classes: BTreeMap<String, ClassPreset>,
+}
+
+// Longest comm the kernel keeps, TASK_COMM_LEN minus the NUL
+const COMM_MAX: usize = 15;
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Config {
+    pub target_mode: i32,
+    pub tgid: Option<i32>,
+    /// cgroup v2 path whose tasks are the targets, instead of a tgid
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cgroup: Option<String>,
+    /// Baseline tier that relative task priorities are resolved against
+    pub base_prio: Option<i32>,
+    /// Slice in ns that percentage task slices are resolved against
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_slice: Option<u64>,
+    pub tasks: Vec<TaskConfig>,
+    /// Applied to target tasks without an entry in `tasks`
+    #[serde(default)]
+    pub default: Option<TaskPolicy>,
+    /// Extra classes, or replacements for the built-in ones of the same name
+    #[serde(default)]
+    pub classes: BTreeMap<String, ClassPreset>,
+    /// How woken targets pick an idle CPU, one of DISPATCH_STRATEGIES
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dispatch_strategy: Option<String>,
+    /// Which idle CPUs a woken target tries first, one of IDLE_PREFERENCES
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idle_preference: Option<String>,
+    /// Confine every target to the first this many online CPUs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_cpus: Option<u32>,
+}
+
+/// Names accepted for `dispatch_strategy`, with the BPF value each stands for
+const DISPATCH_STRATEGIES: &[(&str, u32)] = &[
+    ("default", bpf_intf::DISPATCH_DEFAULT),
+    ("round_robin", bpf_intf::DISPATCH_ROUND_ROBIN),
+    ("sticky", bpf_intf::DISPATCH_STICKY),
+    ("pack", bpf_intf::DISPATCH_PACK),
+];
+
+/// Names accepted for `idle_preference`, with the BPF value each stands for
+const IDLE_PREFERENCES: &[(&str, u32)] = &[
+    ("none", bpf_intf::IDLE_PREFER_NONE),
+    ("idle_core", bpf_intf::IDLE_PREFER_IDLE_CORE),
+    ("busy_core", bpf_intf::IDLE_PREFER_BUSY_CORE),
+];
+
+/// Presets every config can name without defining them
+fn builtin_classes() -> BTreeMap<String, ClassPreset> {
+    let preset = |prio: u32, slice, on_ecore| ClassPreset { prio: prio as i32, slice, on_ecore };
+    BTreeMap::from([
+        ("latency".to_string(), preset(bpf_intf::TIER_CRITICAL, 100_000, 0)),
+        ("throughput".to_string(), preset(bpf_intf::TIER_INTERACTIVE, 2_000_000, 0)),
+        ("background".to_string(), preset(bpf_intf::TIER_NORMAL, 5_000_000, 1)),
+    ])
+}
+
+// Copy the preset of the entry's class into the knobs it doesn't set itself
+fn apply_class(entry: &mut Value, owner: &str, classes: &BTreeMap<String, ClassPreset>, errors: &mut Vec<String>) {
+    let Some(fields) = entry.as_object_mut() else {
+        return;
+    };
+    let Some(class) = fields.get("class").and_then(Value::as_str).map(str::to_string) else {
+        return;
+    };
+    let Some(preset) = classes.get(&class) else {
+        let known: Vec<&str> = classes.keys().map(String::as_str).collect();
+        errors.push(format!("{}: unknown class \"{}\", expected one of {}", owner, class, known.join(", ")));
+        return;
+    };
+
+    if let Ok(Value::Object(knobs)) = serde_json::to_value(preset) {
+        for (knob, value) in knobs {
+            fields.entry(knob).or_insert(value);
+        }
+    }
+}
+
+// Classes are expanded on the raw JSON so TaskPolicy keeps plain, required knobs
+fn expand_classes(value: &mut Value, path: &str) -> Result<(), TeddyError> {
+    let mut classes = builtin_classes();
+    if let Some(custom) = value.get("classes") {
+        let custom: BTreeMap<String, ClassPreset> = serde_json::from_value(custom.clone())
+            .map_err(|source| TeddyError::ConfigParse { path: path.to_string(), source })?;
+        classes.extend(custom);
+    }
+
+    // Anything not shaped like a config is left for serde to report
+    let Some(root) = value.as_object_mut() else {
+        return Ok(());
+    };
+    let mut errors = Vec::new();
+    for (key, entry) in root.iter_mut() {
+        match key.as_str() {
+            "tasks" => {
+                for task in entry.as_array_mut().into_iter().flatten() {
+                    let owner = match (task.get("tid").and_then(Value::as_i64), task.get("comm").and_then(Value::as_str)) {
+                        (Some(tid), _) => format!("TID {}", tid),
+                        (None, Some(comm)) => format!("comm \"{}\"", comm),
+                        _ => "task".to_string(),
+                    };
+                    apply_class(task, &owner, &classes, &mut errors);
+                }
+            }
+            "default" => apply_class(entry, "default", &classes, &mut errors),
+            _ => {}
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(TeddyError::Validation(errors));
+    }
+    Ok(())
+}
+
+/// Total the weights are rescaled to by `Config::normalize_weights`
+pub const NORMALIZED_WEIGHT_SUM: u64 = 10_000;
+
+// Config path that reads stdin, for configs piped in by a generator
+const STDIN_PATH: &str = "-";
+
+/// File of a --config-dir holding the top-level settings
+pub const BASE_FILE: &str = "base.json";
+
+// Raw JSON of a config file, or of stdin for STDIN_PATH
+fn read_value(path: &str) -> Result<Value, TeddyError> {
+    let data = if path == STDIN_PATH {
+        std::io::read_to_string(std::io::stdin())
+    } else {
+        std::fs::read_to_string(path)
+    };
+    let data = data.map_err(|source| TeddyError::ConfigRead {
+        path: path.to_string(),
+        source,
+    })?;
+    serde_json::from_str(&data).map_err(|source| TeddyError::ConfigParse {
+        path: path.to_string(),
+        source,
+    })
+}
+
+pub const PRIO_MIN: i32 = bpf_intf::TIER_CRITICAL as i32;
+pub const PRIO_MAX: i32 = bpf_intf::TIER_NORMAL as i32;
+
+/// Slices a task, class or base_slice may resolve to, 1 us to 1 s
+pub const SLICE_MIN: u64 = 1_000;
+pub const SLICE_MAX: u64 = 1_000_000_000;
+
+fn check_slice(slice: u64) -> Result<u64, String> {
+    if !(SLICE_MIN..=SLICE_MAX).contains(&slice) {
+        return Err(format!("slice {} ns is outside {}..={}", slice, SLICE_MIN, SLICE_MAX));
+    }
+    Ok(slice)
+}
+
+impl SliceSpec {
+    /// Absolute slice in ns, or why it can't be resolved or is out of bounds
+    pub fn resolve(&self, base_slice: Option<u64>) -> Result<u64, String> {
+        let percent = match self {
+            Self::Ns(ns) => return check_slice(*ns),
+            Self::Percent(spec) => spec,
+        };
+        let Some(value) = percent.strip_suffix('%').and_then(|value| value.trim().parse::<f64>().ok()) else {
+            return Err(format!("slice \"{}\" is neither nanoseconds nor a percentage like \"50%\"", percent));
+        };
+        if !value.is_finite() || value <= 0.0 {
+            return Err(format!("slice percentage {} must be positive", percent));
+        }
+        let Some(base) = base_slice else {
+            return Err(format!("percentage slice {} requires base_slice", percent));
+        };
+        check_slice((base as f64 * value / 100.0).round() as u64)
+    }
+}
+
+impl TaskPolicy {
+    /// Priority tier written to the BPF side, clamped to the valid band when relative
+    pub fn resolved_prio(&self, base_prio: Option<i32>) -> i32 {
+        match (self.is_relative, base_prio) {
+            (true, Some(base)) => base.saturating_add(self.prio).clamp(PRIO_MIN, PRIO_MAX),
+            _ => self.prio,
+        }
+    }
+
+    /// Slice in ns written to the BPF side, 0 when it doesn't resolve, which validation rejects
+    pub fn resolved_slice(&self, base_slice: Option<u64>) -> u64 {
+        self.slice.resolve(base_slice).unwrap_or(0)
+    }
+
+    // Problems with these settings, each prefixed with `owner` (a TID or "default")
+    fn validation_errors(&self, owner: &str, base_prio: Option<i32>, base_slice: Option<u64>) -> Vec<String> {
+        let owner = match &self.class {
+            Some(class) => format!("{} (class {})", owner, class),
+            None => owner.to_string(),
+        };
+        let mut errors = Vec::new();
+        macro_rules! invalid {
+            ($($arg:tt)*) => {
+                errors.push(format!("{}: {}", owner, format_args!($($arg)*)))
+            };
+        }
+
+        let prio = self.resolved_prio(base_prio);
+        if self.is_relative && base_prio.is_none() {
+            invalid!("relative prio requires base_prio");
+        } else if !(PRIO_MIN..=PRIO_MAX).contains(&prio) {
+            invalid!("prio {} is outside {}..={}", prio, PRIO_MIN, PRIO_MAX);
+        }
+        if let Err(e) = self.slice.resolve(base_slice) {
+            invalid!("{}", e);
+        }
+        if self.on_ecore > 1 {
+            invalid!("on_ecore must be 0 or 1");
+        }
+        if self.budget_ns > 0 && self.window_ns == 0 {
+            invalid!("budget_ns requires a window_ns");
+        } else if self.budget_ns > self.window_ns {
+            invalid!("budget_ns {} exceeds window_ns {}", self.budget_ns, self.window_ns);
+        }
+        if self.weight == Some(0) {
+            invalid!("weight must be positive");
+        }
+        if self.preempt_latency_ns < 0 {
+            invalid!("preempt_latency_ns must be non-negative, got {}", self.preempt_latency_ns);
+        }
+
+        errors
+    }
+}
+
+impl Config {
+    /// Read and parse a config file, expanding task classes, without validating it.
+    /// A path of `-` reads the JSON from stdin.
+    pub fn parse_file(path: &str) -> Result<Self, TeddyError> {
+        let mut value = read_value(path)?;
+        expand_classes(&mut value, path)?;
+        serde_json::from_value(value).map_err(|source| TeddyError::ConfigParse {
+            path: path.to_string(),
+            source,
+        })
+    }
+
+    /// Merge every *.json in `dir`: BASE_FILE, when present, holds the
+    /// top-level settings and the other files, in name order, only add
+    /// `tasks`. Without it the merged config is in tid mode with no default,
+    /// for use with --tid/--tgid. A TID in two files is an error. The merged
+    /// config is validated like a single file.
+    pub fn load_dir(dir: &str) -> Result<Self, TeddyError> {
+        let read_error = |source| TeddyError::ConfigRead { path: dir.to_string(), source };
+        let mut paths: Vec<std::path::PathBuf> = std::fs::read_dir(dir)
+            .map_err(read_error)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<_, _>>()
+            .map_err(read_error)?;
+        paths.retain(|path| path.extension().is_some_and(|ext| ext == "json"));
+        paths.sort();
+
+        let base_path = std::path::Path::new(dir).join(BASE_FILE);
+        let base_name = base_path.display().to_string();
+        let mut base = if base_path.exists() {
+            read_value(&base_name)?
+        } else {
+            serde_json::json!({ "target_mode": bpf_intf::MODE_TID, "tasks": [] })
+        };
+        let classes = base.get("classes").cloned();
+
+        // Which file each TID came from, to name both sides of a conflict
+        let mut owners: BTreeMap<i64, String> = BTreeMap::new();
+        let mut tasks = Vec::new();
+        let mut errors = Vec::new();
+        for path in paths.iter().filter(|path| **path != base_path) {
+            let name = path.display().to_string();
+            let mut fragment = read_value(&name)?;
+            if let Some(object) = fragment.as_object_mut() {
+                for key in object.keys().filter(|key| *key != "tasks") {
+                    errors.push(format!("{}: only {} may set {}, other files only add tasks", name, BASE_FILE, key));
+                }
+                // Fragment tasks may use the base file's classes
+                if let Some(classes) = &classes {
+                    object.insert("classes".to_string(), classes.clone());
+                }
+            }
+            expand_classes(&mut fragment, &name)?;
+            let fragment_tasks = fragment
+                .get_mut("tasks")
+                .and_then(Value::as_array_mut)
+                .map(std::mem::take)
+                .unwrap_or_default();
+            for task in fragment_tasks {
+                if let Some(tid) = task.get("tid").and_then(Value::as_i64) {
+                    if let Some(owner) = owners.insert(tid, name.clone()) {
+                        errors.push(format!("TID {} is in both {} and {}", tid, owner, name));
+                    }
+                }
+                tasks.push(task);
+            }
+        }
+
+        expand_classes(&mut base, &base_name)?;
+        if let Some(base_tasks) = base.get("tasks").and_then(Value::as_array) {
+            for tid in base_tasks.iter().filter_map(|task| task.get("tid").and_then(Value::as_i64)) {
+                if let Some(owner) = owners.get(&tid) {
+                    errors.push(format!("TID {} is in both {} and {}", tid, base_name, owner));
+                }
+            }
+        }
+        if !errors.is_empty() {
+            return Err(TeddyError::Validation(errors));
+        }
+        if let Some(base_tasks) = base.get_mut("tasks").and_then(Value::as_array_mut) {
+            base_tasks.extend(tasks);
+        }
+        let config: Self = serde_json::from_value(base)
+            .map_err(|source| TeddyError::ConfigParse { path: dir.to_string(), source })?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn load(path: &str) -> Result<Self, TeddyError> {
+        let config = Self::parse_file(path)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<(), TeddyError> {
+        let errors = self.validation_errors();
+        if !errors.is_empty() {
+            return Err(TeddyError::Validation(errors));
+        }
+        Ok(())
+    }
+
+    /// Rescale every set weight, default block included, so they sum to
+    /// NORMALIZED_WEIGHT_SUM. Ratios are kept, rounding down to at least 1.
+    pub fn normalize_weights(&mut self) {
+        let policies = || {
+            self.tasks
+                .iter()
+                .map(|task| &task.policy)
+                .chain(self.default.iter())
+        };
+        let total: u64 = policies().filter_map(|policy| policy.weight).map(u64::from).sum();
+        if total == 0 {
+            return;
+        }
+
+        let policies = self
+            .tasks
+            .iter_mut()
+            .map(|task| &mut task.policy)
+            .chain(self.default.iter_mut());
+        for policy in policies {
+            if let Some(weight) = &mut policy.weight {
+                *weight = (u64::from(*weight) * NORMALIZED_WEIGHT_SUM / total).max(1) as u32;
+            }
+        }
+    }
+
+    /// Why this config schedules no tasks at all, None when it schedules some.
+    /// Valid but almost always a mistake.
+    pub fn empty_reason(&self) -> Option<String> {
+        if self.target_mode as u32 != bpf_intf::MODE_TID || !self.tasks.is_empty() || self.cgroup.is_some() {
+            return None;
+        }
+        Some(match self.tgid {
+            Some(tgid) => format!("tasks is empty and target_mode is {} (tid), so tgid {} is ignored; \
+                did you mean target_mode {} (tgid)?", self.target_mode, tgid, bpf_intf::MODE_TGID),
+            None => "tasks is empty and no tgid is set; add task entries, \
+                or set target_mode 1 with a tgid".to_string(),
+        })
+    }
+
+    /// BPF value of `dispatch_strategy`, the default strategy when unset or unknown
+    pub fn dispatch_strategy_id(&self) -> u32 {
+        let Some(name) = &self.dispatch_strategy else {
+            return bpf_intf::DISPATCH_DEFAULT;
+        };
+        DISPATCH_STRATEGIES
+            .iter()
+            .find(|(known, _)| known == name)
+            .map_or(bpf_intf::DISPATCH_DEFAULT, |&(_, id)| id)
+    }
+
+    /// Name and BPF value of `idle_preference`, "none" when unset or unknown
+    pub fn idle_preference(&self) -> (&str, u32) {
+        self.idle_preference
+            .as_deref()
+            .and_then(|name| IDLE_PREFERENCES.iter().find(|(known, _)| *known == name))
+            .copied()
+            .unwrap_or(IDLE_PREFERENCES[0])
+    }
+
+    /// Every problem found in the config, empty when it is valid
+    pub fn validation_errors(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        macro_rules! invalid {
+            ($($arg:tt)*) => {
+                errors.push(format!($($arg)*))
+            };
+        }
+
+        let mode = self.target_mode as u32;
+        if mode != bpf_intf::MODE_TID && mode != bpf_intf::MODE_TGID {
+            invalid!("target_mode must be {} (tid) or {} (tgid), got {}",
+                bpf_intf::MODE_TID, bpf_intf::MODE_TGID, self.target_mode);
+        }
+        if self.cgroup.is_some() && self.tgid.is_some() {
+            invalid!("cgroup and tgid are mutually exclusive");
+        }
+        if mode == bpf_intf::MODE_TGID && self.tgid.is_none() {
+            invalid!("target_mode {} (tgid) requires a tgid", self.target_mode);
+        }
+        if let Some(base) = self.base_prio {
+            if !(PRIO_MIN..=PRIO_MAX).contains(&base) {
+                invalid!("base_prio {} is outside {}..={}", base, PRIO_MIN, PRIO_MAX);
+            }
+        }
+        if let Some(base) = self.base_slice {
+            if let Err(e) = check_slice(base) {
+                invalid!("base_{}", e);
+            }
+        }
+        if let Some(name) = &self.dispatch_strategy {
+            if !DISPATCH_STRATEGIES.iter().any(|(known, _)| known == name) {
+                let known: Vec<&str> = DISPATCH_STRATEGIES.iter().map(|(known, _)| *known).collect();
+                invalid!("unknown dispatch_strategy \"{}\", expected one of {}", name, known.join(", "));
+            }
+        }
+        if let Some(name) = &self.idle_preference {
+            if !IDLE_PREFERENCES.iter().any(|(known, _)| known == name) {
+                let known: Vec<&str> = IDLE_PREFERENCES.iter().map(|(known, _)| *known).collect();
+                invalid!("unknown idle_preference \"{}\", expected one of {}", name, known.join(", "));
+            }
+        }
+        if self.max_cpus == Some(0) {
+            invalid!("max_cpus must be at least 1");
+        }
+        for (name, preset) in &self.classes {
+            if !(PRIO_MIN..=PRIO_MAX).contains(&preset.prio) {
+                invalid!("class {}: prio {} is outside {}..={}", name, preset.prio, PRIO_MIN, PRIO_MAX);
+            }
+            if let Err(e) = check_slice(preset.slice) {
+                invalid!("class {}: {}", name, e);
+            }
+            if preset.on_ecore > 1 {
+                invalid!("class {}: on_ecore must be 0 or 1", name);
+            }
+        }
+
+        let mut seen = std::collections::BTreeSet::new();
+        for task in &self.tasks {
+            if !seen.insert(task.tid) {
+                invalid!("TID {} has more than one task entry", task.tid);
+            }
+            errors.extend(task.policy.validation_errors(&format!("TID {}", task.tid), self.base_prio, self.base_slice));
+        }
+        if let Some(default) = &self.default {
+            errors.extend(default.validation_errors("default", self.base_prio, self.base_slice));
+        }
+
+        errors
+    }
+}
+
+impl SeedConfig {
+    /// Read, expand classes in and validate a --seed-config file
+    pub fn load(path: &str) -> Result<Self, TeddyError> {
+        let mut value = read_value(path)?;
+        expand_classes(&mut value, path)?;
+        let seed: Self = serde_json::from_value(value).map_err(|source| TeddyError::ConfigParse {
+            path: path.to_string(),
+            source,
+        })?;
+
+        let mut errors = Vec::new();
+        if let Some(base) = seed.base_prio {
+            if !(PRIO_MIN..=PRIO_MAX).contains(&base) {
+                errors.push(format!("base_prio {} is outside {}..={}", base, PRIO_MIN, PRIO_MAX));
+            }
+        }
+        if let Some(base) = seed.base_slice {
+            if let Err(e) = check_slice(base) {
+                errors.push(format!("base_{}", e));
+            }
+        }
+        if seed.tasks.is_empty() {
+            errors.push("tasks is empty, a seed config needs at least one comm entry".to_string());
+        }
+        let mut seen = std::collections::BTreeSet::new();
+        for task in &seed.tasks {
+            let owner = format!("comm \"{}\"", task.comm);
+            if task.comm.is_empty() {
+                errors.push("comm must not be empty".to_string());
+            } else if task.comm.len() > COMM_MAX {
+                errors.push(format!("{}: longer than {} bytes, the kernel truncates comm so it never matches",
+                    owner, COMM_MAX));
+            }
+            if !seen.insert(task.comm.as_str()) {
+                errors.push(format!("{} has more than one task entry", owner));
+            }
+            errors.extend(task.policy.validation_errors(&owner, seed.base_prio, seed.base_slice));
+        }
+
+        if !errors.is_empty() {
+            return Err(TeddyError::Validation(errors));
+        }
+        Ok(seed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(weight: Option<u32>) -> TaskPolicy {
+        TaskPolicy {
+            prio: bpf_intf::TIER_NORMAL as i32,
+            slice: SliceSpec::Ns(bpf_intf::DEFAULT_SLICE as u64),
+            on_ecore: 1,
+            is_relative: false,
+            budget_ns: 0,
+            window_ns: 0,
+            weight,
+            preempt_latency_ns: 0,
+            class: None,
+        }
+    }
+
+    fn config(weights: &[Option<u32>], default: Option<TaskPolicy>) -> Config {
+        Config {
+            target_mode: bpf_intf::MODE_TID as i32,
+            tgid: None,
+            cgroup: None,
+            base_prio: None,
+            base_slice: None,
+            tasks: weights
+                .iter()
+                .enumerate()
+                .map(|(i, &weight)| TaskConfig { tid: 100 + i as i32, policy: policy(weight) })
+                .collect(),
+            default,
+            classes: BTreeMap::new(),
+            dispatch_strategy: None,
+            idle_preference: None,
+            max_cpus: None,
+        }
+    }
+
+    fn weights(config: &Config) -> Vec<Option<u32>> {
+        config.tasks.iter().map(|task| task.policy.weight).collect()
+    }
+
+    #[test]
+    fn resolved_prio_clamps_extreme_offsets() {
+        let mut relative = policy(None);
+        relative.is_relative = true;
+        relative.prio = i32::MAX;
+        assert_eq!(relative.resolved_prio(Some(2)), PRIO_MAX);
+        relative.prio = i32::MIN;
+        assert_eq!(relative.resolved_prio(Some(-2)), PRIO_MIN);
+        relative.prio = -1;
+        assert_eq!(relative.resolved_prio(Some(2)), 1);
+    }
+
+    #[test]
+    fn normalize_weights_keeps_ratios() {
+        let mut config = config(&[Some(1), Some(3), None], Some(policy(Some(4))));
+        config.normalize_weights();
+        assert_eq!(weights(&config), [Some(1250), Some(3750), None]);
+        assert_eq!(config.default.unwrap().weight, Some(5000));
+    }
+
+    #[test]
+    fn normalize_weights_keeps_tiny_weights_at_one() {
+        let mut config = config(&[Some(1), Some(1_000_000)], None);
+        config.normalize_weights();
+        assert_eq!(weights(&config), [Some(1), Some(9999)]);
+    }
+
+    #[test]
+    fn normalize_weights_leaves_unset_and_zero_weights() {
+        let mut unset = config(&[None, None], Some(policy(None)));
+        unset.normalize_weights();
+        assert_eq!(weights(&unset), [None, None]);
+        assert_eq!(unset.default.unwrap().weight, None);
+
+        let mut zero = config(&[Some(0), Some(0)], None);
+        zero.normalize_weights();
+        assert_eq!(weights(&zero), [Some(0), Some(0)]);
+    }
+
+    #[test]
+    fn normalize_weights_gives_a_single_task_the_whole_sum() {
+        let mut config = config(&[Some(7)], None);
+        config.normalize_weights();
+        assert_eq!(weights(&config), [Some(NORMALIZED_WEIGHT_SUM as u32)]);
+    }
+}
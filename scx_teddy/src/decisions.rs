@@ -0,0 +1,43 @@
+// SPDX-License-Identifier: GPL-2.0
+//! Placement decisions of target tasks, logged by the BPF side with --debug-decisions
+
+use crate::bpf_intf;
+
+fn reason_name(reason: u32) -> &'static str {
+    match reason {
+        bpf_intf::DECISION_THROTTLED => "throttled",
+        bpf_intf::DECISION_SYNC => "sync",
+        bpf_intf::DECISION_IDLE => "idle",
+        bpf_intf::DECISION_WAKEUP => "wakeup",
+        bpf_intf::DECISION_QUEUED => "queued",
+        _ => "unknown",
+    }
+}
+
+fn dsq_name(dsq: u32) -> &'static str {
+    match dsq {
+        bpf_intf::DECISION_DSQ_LOCAL => "LOCAL",
+        bpf_intf::OTHER_DSQ => "OTHER_DSQ",
+        bpf_intf::CRITICAL_DSQ => "CRITICAL_DSQ",
+        bpf_intf::INTERACTIVE_DSQ => "INTERACTIVE_DSQ",
+        bpf_intf::NORMAL_DSQ => "NORMAL_DSQ",
+        bpf_intf::CRITICAL_WAKEUP_DSQ => "CRITICAL_WAKEUP_DSQ",
+        bpf_intf::INTERACTIVE_WAKEUP_DSQ => "INTERACTIVE_WAKEUP_DSQ",
+        _ => "unknown",
+    }
+}
+
+/// Ring buffer callback for the decisions map, prints one line per decision
+pub fn print_decision(data: &[u8]) -> i32 {
+    let Ok(decision) = plain::from_bytes::<bpf_intf::decision_event_t>(data) else {
+        return 0;
+    };
+
+    let placement = if decision.dsq == bpf_intf::DECISION_DSQ_LOCAL { "on cpu" } else { "from cpu" };
+    println!("[decision] {:.6}s tid {} {} -> {} slice {}ns {} {}",
+        decision.timestamp as f64 / 1e9, decision.tid, reason_name(decision.reason),
+        dsq_name(decision.dsq), decision.slice, placement, decision.cpu);
+    0
+}
+
+unsafe impl plain::Plain for bpf_intf::decision_event_t {}
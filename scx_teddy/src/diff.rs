@@ -0,0 +1,87 @@
+// SPDX-License-Identifier: GPL-2.0
+//! Per-task comparison of the collected stats against a baseline saved with --merge
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::color::Palette;
+use crate::stats::{ns_to_ms, TaskStats};
+
+// Changes this large in the bad direction are highlighted
+const REGRESSION_PCT: f64 = 10.0;
+
+// Percentile of the scheduling latency compared
+const DIFF_PERCENTILE: f64 = 99.0;
+
+/// How tasks of the two runs are paired up
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum MatchBy {
+    /// Same TID, only meaningful when both runs traced the same process
+    #[default]
+    Tid,
+    /// Same thread name, stable across restarts; threads sharing a name are folded
+    Comm,
+}
+
+// Stats keyed by what MatchBy pairs on
+fn keyed(stats_map: &HashMap<i32, TaskStats>, match_by: MatchBy) -> BTreeMap<String, TaskStats> {
+    let mut keyed: BTreeMap<String, TaskStats> = BTreeMap::new();
+    for (&tid, task_stats) in stats_map.iter() {
+        let key = match match_by {
+            MatchBy::Tid => format!("{} ({})", tid, task_stats.comm),
+            MatchBy::Comm => task_stats.comm.clone(),
+        };
+        keyed
+            .entry(key)
+            .or_insert_with(|| TaskStats::new(task_stats.parent))
+            .merge(task_stats);
+    }
+    keyed
+}
+
+// "old -> new (+delta, +pct%)", highlighted when it moved the bad way by REGRESSION_PCT
+fn fmt_delta(old: f64, new: f64, p: usize, palette: &Palette) -> String {
+    let delta = new - old;
+    let text = if old == 0.0 {
+        format!("{:.p$} -> {:.p$} ({:+.p$})", old, new, delta)
+    } else {
+        format!("{:.p$} -> {:.p$} ({:+.p$}, {:+.1}%)", old, new, delta, delta * 100.0 / old)
+    };
+    let regressed = old > 0.0 && delta * 100.0 / old >= REGRESSION_PCT;
+    palette.warn(text, regressed)
+}
+
+/// Print how avg runtime, p99 scheduling latency and utilization changed per
+/// task. Higher is flagged as worse for all three.
+pub fn print_diff(
+    baseline: &HashMap<i32, TaskStats>,
+    current: &HashMap<i32, TaskStats>,
+    match_by: MatchBy,
+    palette: &Palette,
+    p: usize,
+) {
+    let baseline = keyed(baseline, match_by);
+    let current = keyed(current, match_by);
+
+    let mut matched = 0;
+    for (key, new) in current.iter() {
+        let Some(old) = baseline.get(key) else {
+            continue;
+        };
+        matched += 1;
+        println!("{}:", key);
+        println!("  Avg runtime ms: {}",
+            fmt_delta(ns_to_ms(old.runtime_avg()), ns_to_ms(new.runtime_avg()), p, palette));
+        if let (Some(old_lat), Some(new_lat)) =
+            (old.latency_percentile(DIFF_PERCENTILE), new.latency_percentile(DIFF_PERCENTILE))
+        {
+            println!("  p{} latency us: {}", DIFF_PERCENTILE,
+                fmt_delta(old_lat as f64 / 1000.0, new_lat as f64 / 1000.0, p, palette));
+        }
+        println!("  Utilization %:  {}",
+            fmt_delta(old.utilization() * 100.0, new.utilization() * 100.0, p, palette));
+    }
+
+    let only_current = current.keys().filter(|key| !baseline.contains_key(*key)).count();
+    let only_baseline = baseline.keys().filter(|key| !current.contains_key(*key)).count();
+    println!("{} matched, {} only in this run, {} only in the baseline", matched, only_current, only_baseline);
+}
@@ -0,0 +1,140 @@
+// SPDX-License-Identifier: GPL-2.0
+//! `doctor`: every preflight requirement checked at once, as a checklist
+
+use std::fs;
+
+use crate::color::Palette;
+use crate::kernel::{self, KernelVersion, MIN_VERSION, OPS_NAME, REQUIRED_CONFIG, SCHED_EXT_SYSFS};
+
+// Capability bits in /proc/self/status CapEff
+const CAP_SYS_ADMIN: u32 = 21;
+const CAP_BPF: u32 = 39;
+
+// The BPF ring buffer was added in 5.8
+const RINGBUF_VERSION: (u32, u32) = (5, 8);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Pass,
+    // Failed, but the scheduler can still run without it
+    Warn,
+    Fail,
+}
+
+struct Checklist {
+    palette: Palette,
+    passed: usize,
+    warned: usize,
+    failed: usize,
+}
+
+impl Checklist {
+    fn report(&mut self, outcome: Outcome, what: &str, detail: impl std::fmt::Display) {
+        let tag = match outcome {
+            Outcome::Pass => {
+                self.passed += 1;
+                self.palette.good("[ OK ]")
+            }
+            Outcome::Warn => {
+                self.warned += 1;
+                self.palette.warn("[WARN]", true)
+            }
+            Outcome::Fail => {
+                self.failed += 1;
+                self.palette.alert("[FAIL]")
+            }
+        };
+        println!("{} {}: {}", tag, what, detail);
+    }
+
+    // A requirement: failing it means the scheduler can't run
+    fn critical(&mut self, ok: bool, what: &str, detail: impl std::fmt::Display) {
+        self.report(if ok { Outcome::Pass } else { Outcome::Fail }, what, detail);
+    }
+
+    // Only some features need it
+    fn optional(&mut self, ok: bool, what: &str, detail: impl std::fmt::Display) {
+        self.report(if ok { Outcome::Pass } else { Outcome::Warn }, what, detail);
+    }
+}
+
+// Effective capability set of this process
+fn effective_caps() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let hex = status.lines().find_map(|line| line.strip_prefix("CapEff:"))?;
+    u64::from_str_radix(hex.trim(), 16).ok()
+}
+
+// Filesystem types mounted anywhere, from /proc/mounts
+fn mounted_types() -> Vec<String> {
+    let mounts = fs::read_to_string("/proc/mounts").unwrap_or_default();
+    mounts.lines().filter_map(|line| line.split_whitespace().nth(2)).map(str::to_string).collect()
+}
+
+/// Print the checklist, returning whether every critical check passed
+pub fn run(palette: Palette) -> bool {
+    let mut checks = Checklist { palette, passed: 0, warned: 0, failed: 0 };
+
+    // SAFETY: geteuid has no preconditions
+    let root = unsafe { libc::geteuid() } == 0;
+    let caps = effective_caps().unwrap_or(0);
+    let has = |cap: u32| caps >> cap & 1 != 0;
+    checks.critical(root || has(CAP_SYS_ADMIN) && has(CAP_BPF), "privileges",
+        if root {
+            "running as root".to_string()
+        } else {
+            format!("not root; CAP_SYS_ADMIN {}, CAP_BPF {} (loading a sched_ext scheduler needs both)",
+                if has(CAP_SYS_ADMIN) { "set" } else { "missing" },
+                if has(CAP_BPF) { "set" } else { "missing" })
+        });
+
+    let version = KernelVersion::read();
+    match &version {
+        Ok(version) => {
+            checks.critical((version.major, version.minor) >= MIN_VERSION, "kernel version",
+                format!("{}, sched_ext needs >= {}.{}", version.release, MIN_VERSION.0, MIN_VERSION.1));
+            checks.critical((version.major, version.minor) >= RINGBUF_VERSION, "BPF ring buffer",
+                format!("needs >= {}.{}", RINGBUF_VERSION.0, RINGBUF_VERSION.1));
+        }
+        Err(e) => checks.critical(false, "kernel version", format!("{:#}", e)),
+    }
+
+    match version.ok().and_then(|version| kernel::read_config(&version.release)) {
+        Some((path, options)) => {
+            for &(name, needed_for) in REQUIRED_CONFIG {
+                let value = options.get(name).map(String::as_str);
+                checks.critical(value == Some("y"), name,
+                    format!("{} in {}, needed for {}", value.unwrap_or("not set"), path, needed_for));
+            }
+        }
+        None => checks.optional(false, "kernel config", "no /proc/config.gz or /boot/config-<release>, options not checked"),
+    }
+
+    let sched_ext = std::path::Path::new(SCHED_EXT_SYSFS).exists();
+    checks.critical(sched_ext, "sched_ext available",
+        if sched_ext { format!("{} exists", SCHED_EXT_SYSFS) } else { format!("no {}", SCHED_EXT_SYSFS) });
+    if sched_ext {
+        match kernel::attached_scheduler() {
+            None => checks.critical(true, "no scheduler attached", "sched_ext is free"),
+            Some(name) if name == OPS_NAME => {
+                checks.critical(false, "no scheduler attached", "scx_teddy is already running")
+            }
+            Some(name) => checks.critical(false, "no scheduler attached",
+                format!("{} is attached, stop it first", name)),
+        }
+    }
+
+    let mounts = mounted_types();
+    let mounted = |fs_type: &str| mounts.iter().any(|mounted| mounted == fs_type);
+    checks.optional(mounted("bpf"), "bpffs mounted", "needed to pin maps for `stats` and `explain`");
+    checks.optional(mounted("tracefs"), "tracefs mounted", "needed to read --debug-bpf output from trace_pipe");
+    checks.optional(mounted("debugfs"), "debugfs mounted", "older tools expect tracing under /sys/kernel/debug");
+
+    println!("\n{} passed, {} warning(s), {} failed", checks.passed, checks.warned, checks.failed);
+    if checks.failed > 0 {
+        println!("{}", checks.palette.alert("scx_teddy cannot run until the failed checks are fixed"));
+    } else {
+        println!("{}", checks.palette.good("scx_teddy should be able to run"));
+    }
+    checks.failed == 0
+}
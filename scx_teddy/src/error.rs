@@ -0,0 +1,51 @@
+// SPDX-License-Identifier: GPL-2.0
+//! Error kinds returned by the scheduler's core functions
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum TeddyError {
+    #[error("Failed to read config {path}")]
+    ConfigRead {
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Failed to parse config {path}")]
+    ConfigParse {
+        path: String,
+        #[source]
+        source: serde_json::Error,
+    },
+    #[error("Invalid config: {}", .0.join("; "))]
+    Validation(Vec<String>),
+    #[error("Failed to open BPF object")]
+    BpfOpen(#[source] libbpf_rs::Error),
+    #[error("Failed to resize {map} map")]
+    MapResize {
+        map: &'static str,
+        #[source]
+        source: libbpf_rs::Error,
+    },
+    #[error("{needed} entries for {map} exceed the limit of {max}")]
+    TooManyTargets {
+        map: &'static str,
+        needed: usize,
+        max: usize,
+    },
+    #[error("--ringbuf-pages {pages} of {page_size} bytes each overflow the 4 GiB map size limit")]
+    RingbufTooLarge { pages: u32, page_size: u32 },
+    #[error("Failed to load BPF object")]
+    BpfLoad(#[source] libbpf_rs::Error),
+    #[error("TaskEvent is {rust} bytes but task_event_t is {bpf} bytes, intf.h and main.rs are out of sync")]
+    EventLayout { rust: usize, bpf: usize },
+    #[error("Failed to attach struct_ops")]
+    Attach(#[source] libbpf_rs::Error),
+    #[error("Failed to update {map} for {key}")]
+    MapUpdate {
+        map: &'static str,
+        key: String,
+        #[source]
+        source: libbpf_rs::Error,
+    },
+}
@@ -0,0 +1,270 @@
+// SPDX-License-Identifier: GPL-2.0
+//! `explain <TID>`: a pass/fail checklist of why a task is or isn't scheduled
+//! as a target, read from the maps of a scheduler started with --pin-maps
+
+use anyhow::{anyhow, Context, Result};
+use libbpf_rs::{MapCore, MapFlags, MapHandle};
+
+use crate::bpf_intf;
+use crate::bpf_skel::types::bss;
+use crate::kernel::{self, OPS_NAME};
+use crate::{cgroup, targets, topology};
+
+/// Maps explain reads, pinned besides stats by --pin-maps
+pub const PINNED: &[&str] = &["target_tids", "target_tids_alt", "target_tgids", "excluded_tids", "frozen_tids", "bss"];
+
+unsafe impl plain::Plain for bpf_intf::target_ctx_t {}
+unsafe impl plain::Plain for bss {}
+
+#[derive(Default)]
+struct Checklist {
+    failed: usize,
+}
+
+impl Checklist {
+    fn check(&mut self, ok: bool, what: &str, detail: impl std::fmt::Display) {
+        println!("[{}] {}: {}", if ok { "PASS" } else { "FAIL" }, what, detail);
+        if !ok {
+            self.failed += 1;
+        }
+    }
+}
+
+fn open(pin_dir: &str, name: &str) -> Result<MapHandle> {
+    let path = format!("{}/{}", pin_dir, name);
+    MapHandle::from_pinned_path(&path).with_context(|| {
+        format!("Failed to open {}, is scx_teddy running with --pin-maps {}?", path, pin_dir)
+    })
+}
+
+fn lookup(map: &MapHandle, name: &str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+    map.lookup(key, MapFlags::ANY).with_context(|| format!("Failed to read the {} map", name))
+}
+
+fn read<T: plain::Plain>(bytes: &[u8], name: &str) -> Result<T> {
+    // SAFETY: Plain types are valid for any bit pattern, all-zero included
+    let mut value: T = unsafe { std::mem::zeroed() };
+    plain::copy_from_bytes(&mut value, bytes).map_err(|e| anyhow!("Unexpected {} map value: {:?}", name, e))?;
+    Ok(value)
+}
+
+fn mode_name(mode: i32) -> &'static str {
+    match mode as u32 {
+        bpf_intf::MODE_TID => "tid",
+        bpf_intf::MODE_TGID => "tgid",
+        bpf_intf::MODE_MIXED => "mixed",
+        bpf_intf::MODE_CGROUP => "cgroup",
+        _ => "unknown",
+    }
+}
+
+// Whether the task matches target_mode the way is_target() does, and why
+fn membership(
+    tid: i32,
+    tgid: i32,
+    bss: &bss,
+    in_tids: bool,
+    in_tgids: bool,
+) -> (bool, String) {
+    let cgroup_match = || {
+        if bss.target_cgroup_id == 0 {
+            return (false, "no cgroup target".to_string());
+        }
+        match cgroup::task_cgroup_id(tid) {
+            Ok(id) if id == bss.target_cgroup_id => (true, format!("in the target cgroup (id {})", id)),
+            Ok(id) => (false, format!("cgroup id {} is not the target cgroup {}", id, bss.target_cgroup_id)),
+            Err(e) => (false, format!("{:#}", e)),
+        }
+    };
+
+    match bss.target_mode as u32 {
+        bpf_intf::MODE_TID if bss.target_single_tid != 0 => (
+            tid == bss.target_single_tid,
+            format!("the only target TID is {}", bss.target_single_tid),
+        ),
+        bpf_intf::MODE_TID => (
+            in_tids,
+            if in_tids { "listed in target_tids" } else { "not listed in target_tids" }.to_string(),
+        ),
+        bpf_intf::MODE_TGID => (
+            bss.target_single_tgid != 0 && tgid == bss.target_single_tgid,
+            format!("tgid {}, the target tgid is {}", tgid, bss.target_single_tgid),
+        ),
+        bpf_intf::MODE_MIXED if in_tids => (true, "listed in target_tids".to_string()),
+        bpf_intf::MODE_MIXED if in_tgids => (true, format!("tgid {} is listed in target_tgids", tgid)),
+        bpf_intf::MODE_MIXED => {
+            let (in_cgroup, why) = cgroup_match();
+            let detail = format!("not in target_tids, tgid {} not in target_tgids, {}", tgid, why);
+            (in_cgroup, if in_cgroup { why } else { detail })
+        }
+        bpf_intf::MODE_CGROUP => cgroup_match(),
+        _ => (false, format!("unknown target_mode {}", bss.target_mode)),
+    }
+}
+
+// Settings get_target_storage() gives the task when it is first scheduled
+fn effective_settings(entry: Option<bpf_intf::target_ctx_t>, bss: &bss) -> (&'static str, bpf_intf::target_ctx_t) {
+    if let Some(ctx) = entry {
+        return ("its target_tids entry", ctx);
+    }
+    // SAFETY: target_ctx_t is a plain C struct, all-zero is a valid value
+    let mut ctx: bpf_intf::target_ctx_t = unsafe { std::mem::zeroed() };
+    if bss.default_enabled != 0 {
+        ctx.prio = bss.default_prio;
+        ctx.slice = bss.default_slice;
+        ctx.config = bss.default_config;
+        ctx.budget_ns = bss.default_budget_ns;
+        ctx.window_ns = bss.default_window_ns;
+        ctx.weight = bss.default_weight;
+        ctx.preempt_latency_ns = bss.default_preempt_latency_ns;
+        ("the default settings: the config's default block, or the normal tier without one", ctx)
+    } else {
+        ctx.prio = bpf_intf::TIER_NORMAL as i32;
+        ctx.slice = bpf_intf::DEFAULT_SLICE as u64;
+        ctx.config = 1;
+        ("the built-in fallback, no entry and no default block", ctx)
+    }
+}
+
+fn print_settings(source: &str, ctx: &bpf_intf::target_ctx_t) {
+    println!("\nEffective settings, from {}:", source);
+    println!("  prio {}, slice {} ns, on_ecore {}", ctx.prio, ctx.slice,
+        if ctx.config as u32 & bpf_intf::TASK_CONFIG_ECORE != 0 { "yes" } else { "no" });
+    if ctx.budget_ns > 0 {
+        println!("  budget {} ns per {} ns window", ctx.budget_ns, ctx.window_ns);
+    }
+    if ctx.weight > 0 {
+        println!("  weight {}", ctx.weight);
+    }
+    if ctx.preempt_latency_ns > 0 {
+        println!("  preempt latency {} ns", ctx.preempt_latency_ns);
+    }
+    println!("  (read when the task is first scheduled and again after each config reload)");
+}
+
+// The running scheduler's target state, from its pins
+pub struct Pins {
+    tids: MapHandle,
+    tgids: MapHandle,
+    excluded: MapHandle,
+    pub frozen: MapHandle,
+    bss: bss,
+}
+
+impl Pins {
+    pub fn open(pin_dir: &str) -> Result<Self> {
+        let bss_bytes = lookup(&open(pin_dir, "bss")?, "bss", &0u32.to_ne_bytes())?
+            .ok_or_else(|| anyhow!("The bss map holds no value"))?;
+        let bss: bss = read(&bss_bytes, "bss")?;
+        // The map of the current config generation, see reload_config()
+        let tids = if bss.target_gen & 1 == 0 { "target_tids" } else { "target_tids_alt" };
+        Ok(Self {
+            tids: open(pin_dir, tids)?,
+            tgids: open(pin_dir, "target_tgids")?,
+            excluded: open(pin_dir, "excluded_tids")?,
+            frozen: open(pin_dir, "frozen_tids")?,
+            bss,
+        })
+    }
+
+    fn entry(&self, tid: i32) -> Result<Option<bpf_intf::target_ctx_t>> {
+        lookup(&self.tids, "target_tids", &tid.to_ne_bytes())?
+            .map(|bytes| read::<bpf_intf::target_ctx_t>(&bytes, "target_tids"))
+            .transpose()
+    }
+
+    fn membership(&self, tid: i32, tgid: i32, in_tids: bool) -> Result<(bool, String)> {
+        let in_tgids = lookup(&self.tgids, "target_tgids", &tgid.to_ne_bytes())?.is_some();
+        Ok(membership(tid, tgid, &self.bss, in_tids, in_tgids))
+    }
+
+    fn excluded(&self, tid: i32) -> Result<bool> {
+        Ok(self.bss.exclude_enabled != 0 && lookup(&self.excluded, "excluded_tids", &tid.to_ne_bytes())?.is_some())
+    }
+
+    /// Why the scheduler does not manage `tid`, None when it does
+    pub fn unmanaged_reason(&self, tid: i32) -> Result<Option<String>> {
+        let Some(tgid) = targets::read_tgid(tid) else {
+            return Ok(Some(format!("no /proc/{}, the task is not running", tid)));
+        };
+        let (member, why) = self.membership(tid, tgid, self.entry(tid)?.is_some())?;
+        if !member {
+            return Ok(Some(format!("not in the target set (mode {}): {}", mode_name(self.bss.target_mode), why)));
+        }
+        if self.excluded(tid)? {
+            return Ok(Some("excluded with --exclude".to_string()));
+        }
+        Ok(None)
+    }
+}
+
+/// Print the checklist for `tid`, returning whether every check passed
+pub fn explain(tid: i32, pin_dir: &str) -> Result<bool> {
+    let mut checks = Checklist::default();
+
+    let attached = kernel::attached_scheduler();
+    checks.check(attached.as_deref() == Some(OPS_NAME), "scheduler attached",
+        match attached.as_deref() {
+            None => "no sched_ext scheduler is loaded".to_string(),
+            Some(name) if name == OPS_NAME => format!("sched_ext runs {}", name),
+            Some(name) => format!("sched_ext runs {}, not {}", name, OPS_NAME),
+        });
+
+    let pins = Pins::open(pin_dir)?;
+    let bss = &pins.bss;
+
+    let tgid = targets::read_tgid(tid);
+    let comm = targets::read_comm(tid).unwrap_or_default();
+    checks.check(tgid.is_some(), "task exists",
+        match tgid {
+            Some(tgid) => format!("TID {} ({}), tgid {}", tid, comm, tgid),
+            None => format!("no /proc/{}, the task is not running", tid),
+        });
+    let tgid = tgid.unwrap_or(0);
+
+    checks.check(bss.paused == 0, "scheduler active",
+        if bss.paused != 0 { "paused, no task is a target until SIGUSR1" } else { "not paused" });
+
+    let entry = pins.entry(tid)?;
+    let (member, why) = pins.membership(tid, tgid, entry.is_some())?;
+    checks.check(member, &format!("in the target set (mode {})", mode_name(bss.target_mode)), why);
+
+    let is_excluded = pins.excluded(tid)?;
+    checks.check(!is_excluded, "not excluded",
+        if is_excluded { "listed in excluded_tids (--exclude)" } else { "not listed in excluded_tids" });
+
+    let is_frozen = lookup(&pins.frozen, "frozen_tids", &tid.to_ne_bytes())?.is_some();
+    checks.check(!is_frozen, "not frozen",
+        if is_frozen { format!("held off the CPU by `scx_teddy freeze`, `scx_teddy thaw {}` resumes it", tid) }
+        else { "not listed in frozen_tids".to_string() });
+
+    if bss.managed_cpus_enabled != 0 {
+        let managed = |cpu: usize| cpu < bss.managed_cpus.len() * 64 && bss.managed_cpus[cpu / 64] >> (cpu % 64) & 1 != 0;
+        match targets::read_allowed_cpus(tid) {
+            Some(cpus) => {
+                let overlap: Vec<usize> = cpus.iter().copied().filter(|&cpu| managed(cpu)).collect();
+                checks.check(!overlap.is_empty(), "affinity within max_cpus",
+                    if overlap.is_empty() {
+                        format!("allowed CPUs {} are all outside the managed CPUs, so it runs as a non-target",
+                            topology::format_cpulist(&cpus))
+                    } else {
+                        format!("may run on managed CPUs {}", topology::format_cpulist(&overlap))
+                    });
+            }
+            None => checks.check(false, "affinity within max_cpus", "could not read the task's allowed CPUs"),
+        }
+    }
+
+    if member {
+        let (source, ctx) = effective_settings(entry, bss);
+        print_settings(source, &ctx);
+    }
+
+    println!();
+    if checks.failed == 0 {
+        println!("TID {} is scheduled as a target", tid);
+    } else {
+        println!("TID {} is not scheduled as a target: {} check(s) failed", tid, checks.failed);
+    }
+    Ok(checks.failed == 0)
+}
@@ -0,0 +1,50 @@
+// SPDX-License-Identifier: GPL-2.0
+//! `freeze`/`thaw`: hold a managed task off the CPU without killing it, through
+//! the frozen_tids map a scheduler started with --pin-maps pins
+
+use anyhow::{bail, Context, Result};
+use libbpf_rs::{MapCore, MapFlags};
+
+use crate::bpf_intf;
+use crate::explain::Pins;
+use crate::kernel::monotonic_ns;
+use crate::targets;
+
+/// Stop dispatching `tid` once its current slice ends, failing unless the
+/// scheduler manages it. The freeze ends on its own after FREEZE_MAX_SECS.
+pub fn freeze(tid: i32, pin_dir: &str) -> Result<()> {
+    let pins = Pins::open(pin_dir)?;
+    if let Some(reason) = pins.unmanaged_reason(tid)? {
+        bail!("TID {} is not managed by scx_teddy, {} (see `scx_teddy explain {}`)", tid, reason, tid);
+    }
+    pins.frozen
+        .update(&tid.to_ne_bytes(), &monotonic_ns().to_ne_bytes(), MapFlags::ANY)
+        .with_context(|| format!("Failed to freeze TID {}", tid))?;
+    println!("Froze TID {} ({}) for at most {}s, `scx_teddy thaw {}` resumes it sooner",
+        tid, targets::read_comm(tid).unwrap_or_default(), bpf_intf::FREEZE_MAX_SECS, tid);
+    Ok(())
+}
+
+/// Let `tid` be dispatched again
+pub fn thaw(tid: i32, pin_dir: &str) -> Result<()> {
+    let pins = Pins::open(pin_dir)?;
+    if pins.frozen.lookup(&tid.to_ne_bytes(), MapFlags::ANY)?.is_none() {
+        bail!("TID {} is not frozen", tid);
+    }
+    pins.frozen
+        .delete(&tid.to_ne_bytes())
+        .with_context(|| format!("Failed to thaw TID {}", tid))?;
+    println!("Thawed TID {}", tid);
+    Ok(())
+}
+
+/// TIDs in a frozen_tids map, sorted. Expired freezes the BPF side has not
+/// dropped yet are included.
+pub fn frozen(map: &dyn MapCore) -> Vec<i32> {
+    let mut tids: Vec<i32> = map
+        .keys()
+        .filter_map(|key| key.try_into().ok().map(i32::from_ne_bytes))
+        .collect();
+    tids.sort_unstable();
+    tids
+}
@@ -0,0 +1,99 @@
+// SPDX-License-Identifier: GPL-2.0
+//! Full per-task runtime and scheduling latency distributions for --hist-out,
+//! as bucket boundaries and counts that plotting tools and later runs can
+//! combine without loss
+
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::stats::{Histogram, TaskStats};
+
+// Bumped whenever the layout below changes
+const HIST_FORMAT_VERSION: u64 = 1;
+
+#[derive(Debug, Serialize)]
+struct Bucket {
+    // Bucket covers [low_ns, high_ns)
+    low_ns: u64,
+    high_ns: u64,
+    count: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct ExportedHistogram {
+    count: u64,
+    buckets: Vec<Bucket>,
+}
+
+impl From<&Histogram> for ExportedHistogram {
+    fn from(hist: &Histogram) -> Self {
+        let buckets = hist
+            .buckets
+            .iter()
+            .map(|(&bucket, &count)| {
+                let (low_ns, high_ns) = Histogram::bucket_bounds(bucket);
+                Bucket { low_ns, high_ns, count }
+            })
+            .collect();
+        Self { count: hist.count, buckets }
+    }
+}
+
+#[derive(Debug, Default)]
+struct TaskHists {
+    comm: String,
+    tgid: i32,
+    runtime: Histogram,
+    wakeup_latency: Histogram,
+}
+
+/// Histograms of every task seen over the whole run, across collection periods
+#[derive(Debug, Default)]
+pub struct HistExport {
+    tasks: BTreeMap<i32, TaskHists>,
+}
+
+impl HistExport {
+    /// Fold in the stats of a collection period before they are cleared
+    pub fn add(&mut self, stats_map: &HashMap<i32, TaskStats>) {
+        for (&tid, task_stats) in stats_map.iter() {
+            let task = self.tasks.entry(tid).or_default();
+            task.comm.clone_from(&task_stats.comm);
+            task.tgid = task_stats.tgid;
+            task.runtime.merge(&task_stats.runtime_hist);
+            task.wakeup_latency.merge(&task_stats.wakeup_latency);
+        }
+    }
+
+    pub fn write(&self, path: &str) -> Result<()> {
+        #[derive(Serialize)]
+        struct Task<'a> {
+            comm: &'a str,
+            tgid: i32,
+            histograms: BTreeMap<&'static str, ExportedHistogram>,
+        }
+        #[derive(Serialize)]
+        struct File<'a> {
+            format_version: u64,
+            tasks: BTreeMap<i32, Task<'a>>,
+        }
+
+        let tasks = self
+            .tasks
+            .iter()
+            .map(|(&tid, task)| {
+                let histograms = BTreeMap::from([
+                    ("runtime", ExportedHistogram::from(&task.runtime)),
+                    ("wakeup_latency", ExportedHistogram::from(&task.wakeup_latency)),
+                ]);
+                (tid, Task { comm: &task.comm, tgid: task.tgid, histograms })
+            })
+            .collect();
+        let file = File { format_version: HIST_FORMAT_VERSION, tasks };
+
+        let data = serde_json::to_string_pretty(&file).context("Failed to serialize histograms")?;
+        std::fs::write(path, data).with_context(|| format!("Failed to write histograms to {}", path))
+    }
+}
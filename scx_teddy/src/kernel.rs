@@ -0,0 +1,119 @@
+// SPDX-License-Identifier: GPL-2.0
+//! Kernel version and config checks run before loading BPF, so unsupported
+//! kernels get a precise error instead of an opaque libbpf failure
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+
+use anyhow::{bail, Context, Result};
+
+/// sched_ext was merged in 6.12; the BPF ring buffer (5.8) predates it
+pub const MIN_VERSION: (u32, u32) = (6, 12);
+
+// Options the scheduler cannot load without, with what needs them
+pub const REQUIRED_CONFIG: &[(&str, &str)] = &[
+    ("CONFIG_BPF_SYSCALL", "BPF and its ring buffer"),
+    ("CONFIG_DEBUG_INFO_BTF", "BTF for CO-RE relocations"),
+    ("CONFIG_SCHED_CLASS_EXT", "sched_ext"),
+];
+
+// Exists whenever the running kernel has sched_ext built in
+pub const SCHED_EXT_SYSFS: &str = "/sys/kernel/sched_ext";
+
+/// Name the scheduler registers with sched_ext, see SCX_OPS_DEFINE
+pub const OPS_NAME: &str = "teddy";
+
+/// Same clock as bpf_ktime_get_ns(), which the BPF side compares against
+pub fn monotonic_ns() -> u64 {
+    // SAFETY: clock_gettime only writes the timespec it is given
+    let mut ts: libc::timespec = unsafe { std::mem::zeroed() };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts) };
+    ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
+}
+
+/// Name of the sched_ext scheduler currently attached, None when there is none
+pub fn attached_scheduler() -> Option<String> {
+    let ops = fs::read_to_string(format!("{}/root/ops", SCHED_EXT_SYSFS)).ok()?;
+    Some(ops.trim().to_string()).filter(|name| !name.is_empty())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KernelVersion {
+    pub major: u32,
+    pub minor: u32,
+    /// Full release string, e.g. "6.12.0-1-amd64"
+    pub release: String,
+}
+
+impl KernelVersion {
+    /// Parse "Linux version 6.12.0-1-amd64 (...) ..." as found in /proc/version
+    pub fn parse(proc_version: &str) -> Option<Self> {
+        let release = proc_version.split_whitespace().nth(2)?;
+        let mut numbers = release.split(|c: char| !c.is_ascii_digit());
+        let major = numbers.next()?.parse().ok()?;
+        let minor = numbers.next()?.parse().ok()?;
+        Some(Self { major, minor, release: release.to_string() })
+    }
+
+    pub fn read() -> Result<Self> {
+        let proc_version = fs::read_to_string("/proc/version").context("Failed to read /proc/version")?;
+        Self::parse(&proc_version).with_context(|| format!("Cannot parse /proc/version: {}", proc_version.trim()))
+    }
+}
+
+// CONFIG_* values of the running kernel, from /proc/config.gz (CONFIG_IKCONFIG_PROC)
+// or the distribution's /boot/config-<release>. None when neither is readable.
+pub fn read_config(release: &str) -> Option<(String, HashMap<String, String>)> {
+    let read_gz = |path: &str| -> Option<String> {
+        let file = fs::File::open(path).ok()?;
+        let mut data = String::new();
+        flate2::read::GzDecoder::new(file).read_to_string(&mut data).ok()?;
+        Some(data)
+    };
+    let boot_path = format!("/boot/config-{}", release);
+    let (path, data) = match read_gz("/proc/config.gz") {
+        Some(data) => ("/proc/config.gz".to_string(), data),
+        None => (boot_path.clone(), fs::read_to_string(&boot_path).ok()?),
+    };
+
+    let options = data
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+    Some((path, options))
+}
+
+/// Fail with the exact requirement the running kernel misses
+pub fn check() -> Result<()> {
+    let version = KernelVersion::read()?;
+    let requirement = format!("scx_teddy requires Linux >= {}.{} with CONFIG_SCHED_CLASS_EXT=y",
+        MIN_VERSION.0, MIN_VERSION.1);
+
+    if (version.major, version.minor) < MIN_VERSION {
+        bail!("{}, running {} (pass --skip-kernel-check to try anyway)", requirement, version.release);
+    }
+
+    match read_config(&version.release) {
+        Some((path, options)) => {
+            for &(name, needed_for) in REQUIRED_CONFIG {
+                match options.get(name).map(String::as_str) {
+                    Some("y") => {}
+                    value => bail!("{}: {} is {} in {}, it is needed for {} \
+                        (pass --skip-kernel-check to try anyway)",
+                        requirement, name, value.unwrap_or("not set"), path, needed_for),
+                }
+            }
+        }
+        None => {
+            eprintln!("Warning: no /proc/config.gz or /boot/config-{}, not checking the kernel config",
+                version.release);
+            if !std::path::Path::new(SCHED_EXT_SYSFS).exists() {
+                bail!("{}: {} does not exist on {} (pass --skip-kernel-check to try anyway)",
+                    requirement, SCHED_EXT_SYSFS, version.release);
+            }
+        }
+    }
+    Ok(())
+}
@@ -1,25 +1,36 @@
 // SPDX-License-Identifier: GPL-2.0
 //! scx_teddy - A BPF scheduler based on task runtime characteristics
 
+use std::cell::RefCell;
+use std::collections::HashSet;
 use std::mem::MaybeUninit;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
+use std::process::ExitCode;
 use std::time::Duration;
 use std::time::Instant;
 
-use anyhow::{Context, Result};
-use clap::Parser;
-use serde::{Deserialize, Serialize};
+use anyhow::{bail, Context, Result};
+use clap::{Parser, Subcommand};
 use plain::Plain;
 
 use libbpf_rs::skel::OpenSkel;
 use libbpf_rs::skel::SkelBuilder;
+use libbpf_rs::Link;
 use libbpf_rs::MapCore;
 use libbpf_rs::MapFlags;
+use libbpf_rs::MapHandle;
+use libbpf_rs::MapMut;
+use libbpf_rs::OpenMapMut;
+use libbpf_rs::OpenObject;
+use libbpf_rs::PrintLevel;
 
 mod bpf_skel {
     include!(concat!(env!("OUT_DIR"), "/bpf_skel.rs"));
 }
 
+#[allow(non_camel_case_types, non_upper_case_globals, dead_code)]
 mod bpf_intf {
     include!(concat!(env!("OUT_DIR"), "/intf.rs"));
 }
@@ -27,189 +38,1887 @@ mod bpf_intf {
 #[allow(clippy::wildcard_imports)]
 use bpf_skel::*;
 
-#[derive(Debug, Deserialize, Serialize)]
-struct TaskConfig {
-    tid: i32,
-    prio: i32,
-    slice: u64,
-    on_ecore: u8,
-}
+mod affinity;
+mod bench;
+mod cgroup;
+mod color;
+mod config;
+mod decisions;
+mod diff;
+mod doctor;
+mod error;
+mod explain;
+mod freeze;
+mod hist_export;
+mod kernel;
+mod offcpu;
+mod pidns;
+mod raw_events;
+mod recorder;
+mod saved_stats;
+mod sched_stats;
+mod seed;
+mod shards;
+mod socket;
+mod stats;
+mod summary;
+mod syscalls;
+mod targets;
+mod topology;
+mod trace;
+mod warnings;
+mod window;
 
-#[derive(Debug, Deserialize, Serialize)]
-struct Config {
-    target_mode: i32,
-    tgid: Option<i32>,
-    tasks: Vec<TaskConfig>,
-}
+use color::{ColorChoice, Palette};
+use config::{Config, TaskPolicy};
+use diff::MatchBy;
+use error::TeddyError;
+use offcpu::OffCpu;
+use pidns::PidNamespace;
+use raw_events::RawWriter;
+use sched_stats::{SchedStats, StatsFormat};
+use stats::{merge_into_file, print_report, GroupBy, ReportOptions, SortBy, TaskStats, TimeSeries, TopSleeps, Variance};
+use trace::Trace;
+use window::WindowMode;
 
 #[derive(Parser, Debug)]
 #[command(name = "scx_teddy")]
 #[command(about = "scx_teddy - A BPF scheduler based on task runtime characteristics", long_about = None)]
-struct Args {
+#[command(args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[command(flatten)]
+    run: RunArgs,
+}
+
+#[derive(Subcommand, Debug)]
+#[allow(clippy::large_enum_variant)]
+enum Command {
+    /// Run the scheduler (default when no subcommand is given)
+    Run(RunArgs),
+    /// Check a config file and report every problem, without touching BPF
+    Validate {
+        /// JSON config to check, `-` for stdin
+        config: String,
+        /// Also fail on a config that schedules nothing
+        #[arg(long, default_value_t = false)]
+        strict: bool,
+    },
+    /// Print the counters of an already running scheduler and exit
+    Stats {
+        /// Directory the scheduler pinned its maps in, see --pin-maps
+        #[arg(long, value_name = "DIR", default_value = sched_stats::PIN_DIR)]
+        pin_dir: String,
+    },
+    /// Check every requirement for running the scheduler (privileges, kernel,
+    /// sched_ext, mounts) and print a checklist, exiting 1 if one is not met
+    Doctor {
+        #[arg(long, value_enum, default_value_t = ColorChoice::Auto)]
+        color: ColorChoice,
+    },
+    /// Check why a task is or isn't scheduled as a target by a running
+    /// scheduler started with --pin-maps, exiting 1 if any check fails
+    Explain {
+        tid: i32,
+        /// Directory the scheduler pinned its maps in
+        #[arg(long, value_name = "DIR", default_value = sched_stats::PIN_DIR)]
+        pin_dir: String,
+    },
+    /// Stop dispatching a managed task of a scheduler started with --pin-maps,
+    /// for at most 20s, to inspect it without killing it
+    Freeze {
+        tid: i32,
+        #[arg(long, value_name = "DIR", default_value = sched_stats::PIN_DIR)]
+        pin_dir: String,
+    },
+    /// Resume a task stopped by `freeze`
+    Thaw {
+        tid: i32,
+        #[arg(long, value_name = "DIR", default_value = sched_stats::PIN_DIR)]
+        pin_dir: String,
+    },
+    /// Measure throughput and fairness of a synthetic workload under teddy
+    Bench(BenchArgs),
+    /// Rebuild the report from events saved with --raw-out, without BPF or root
+    Replay(ReplayArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct ReplayArgs {
+    /// CSV written by --raw-out
+    csv: String,
+    #[command(flatten)]
+    report: ReportArgs,
+}
+
+#[derive(clap::Args, Debug)]
+struct BenchArgs {
+    /// CPU-bound worker threads, one per CPU by default
+    #[arg(long)]
+    cpu_workers: Option<usize>,
+    /// Worker threads that sleep after each unit of work
+    #[arg(long, default_value_t = 4)]
+    sleep_workers: usize,
+    /// Sleep of the sleep-heavy workers in microseconds
+    #[arg(long, default_value_t = 1000)]
+    sleep_us: u64,
+    /// Seconds to run the workload for
+    #[arg(short, long, default_value_t = 10)]
+    duration: u64,
+    /// Run under the default scheduler instead, for comparison
+    #[arg(long, default_value_t = false)]
+    baseline: bool,
+    /// Abort if attaching the scheduler takes longer than this many seconds
+    #[arg(long, value_name = "SECONDS", default_value_t = 10)]
+    attach_timeout: u64,
+    /// Load even if the kernel version or config looks unsupported
+    #[arg(long, default_value_t = false)]
+    skip_kernel_check: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct RunArgs {
     /// Verbose output
     #[arg(short, long, default_value_t = false)]
     verbose: bool,
     #[arg(short, long, default_value_t = 600)]
     collect_duration: u64,
+    /// What each period's report covers: that period alone, the last
+    /// --window-intervals periods, or everything since the start
+    #[arg(long, value_enum, default_value_t = WindowMode::Reset)]
+    window_mode: WindowMode,
+    /// Periods a sliding window spans
+    #[arg(long, value_name = "K", default_value_t = 5)]
+    window_intervals: usize,
+    /// Discard events for this many seconds after attaching, so stats start
+    /// from steady state
+    #[arg(long, value_name = "SECONDS", default_value_t = 0)]
+    warmup: u64,
+    /// Track at most this many tasks, bounding memory on fork-heavy targets
+    #[arg(long, value_name = "N")]
+    max_tasks: Option<usize>,
+    /// What to do with new TIDs once --max-tasks are tracked
+    #[arg(long, value_enum, default_value_t = MaxTasksPolicy::IgnoreNew, requires = "max_tasks")]
+    max_tasks_policy: MaxTasksPolicy,
+    /// Worker threads building the per-task stats, sharded by TID, for event
+    /// rates the main thread can't keep up with; 1 keeps them on the main thread
+    #[arg(long, value_name = "N", default_value_t = 1, conflicts_with = "max_tasks")]
+    threads: usize,
+    /// Extend each collection period past --collect-duration until this
+    /// many events were collected
+    #[arg(long, value_name = "N")]
+    min_total_events: Option<u64>,
+    /// Hard cap in seconds on a period extended by --min-total-events
+    #[arg(long, value_name = "SECONDS", requires = "min_total_events")]
+    max_duration: Option<u64>,
+    /// JSON config describing the tasks to schedule, `-` reads it from stdin.
+    /// Its target_mode is deprecated in favour of --tid/--tgid, which override
+    /// it when given
+    #[arg(long)]
+    config: Option<String>,
+    /// Merge the JSON configs in this directory instead: base.json holds the
+    /// top-level settings, every other *.json adds tasks
+    #[arg(long, value_name = "DIR", conflicts_with = "config")]
+    config_dir: Option<String>,
+    /// JSON entries keyed by comm, applied to threads of that name as they
+    /// appear (checked every second), for services not started yet
+    #[arg(long, value_name = "FILE")]
+    seed_config: Option<String>,
+    /// Schedule this TID, repeatable; can be combined with --tgid
+    #[arg(long, value_name = "TID")]
+    tid: Vec<i32>,
+    /// Schedule every thread of this TGID, repeatable; can be combined with --tid
+    #[arg(long, value_name = "TGID")]
+    tgid: Vec<i32>,
+    /// Treat TIDs in --tid/--tgid/--exclude and the config as local to the PID
+    /// namespace of this host PID (e.g. a container's init), and show local TIDs
+    /// in the report. Must run in the host PID namespace.
+    #[arg(long, value_name = "PID")]
+    pid_namespace: Option<i32>,
+    /// Rescale config weights so they sum to a constant, keeping their ratios
+    #[arg(long, default_value_t = false)]
+    normalize_weights: bool,
+    /// Start even if a target tgid does not exist yet, e.g. for a process started later
+    #[arg(long, default_value_t = false)]
+    allow_missing_tgid: bool,
+    /// Run even when the config schedules no tasks, for pass-through testing,
+    /// and exit 0 even if no events were collected
+    #[arg(long, default_value_t = false)]
+    allow_empty: bool,
+    /// Fail the run on warnings about the targets or the data: a missing or
+    /// exited tgid, a config that schedules nothing, no targets, an excluded
+    /// TID outside the tgid, unresolved --seed-config names, a failed config
+    /// reload and a high ring buffer drop rate
+    #[arg(long, default_value_t = false)]
+    strict: bool,
+    /// Merge collected stats into this JSON file, creating it if missing
+    #[arg(long)]
+    merge: Option<String>,
+    /// Compare each period against stats saved by an earlier --merge, printing
+    /// per-task changes
+    #[arg(long, value_name = "FILE", conflicts_with = "no_report")]
+    baseline: Option<String>,
+    /// How --baseline pairs tasks of the two runs
+    #[arg(long, value_enum, default_value_t = MatchBy::Tid, requires = "baseline")]
+    baseline_match: MatchBy,
+    /// Write run/sleep spans as a Chrome Trace Event JSON file on exit, for Perfetto
+    #[arg(long)]
+    trace_out: Option<String>,
+    /// Write every task's runtime and scheduling latency histogram as bucket
+    /// boundaries and counts in JSON on exit
+    #[arg(long, value_name = "PATH")]
+    hist_out: Option<String>,
+    /// Write every task's key runtime, sleep and latency figures over the
+    /// whole run on exit, as CSV if PATH ends in .csv and JSON otherwise
+    #[arg(long, value_name = "PATH")]
+    summary_out: Option<String>,
+    /// Write every collected event as CSV, for `scx_teddy replay`
+    #[arg(long, value_name = "PATH")]
+    raw_out: Option<String>,
+    /// Keep the last N events in memory and print them on SIGUSR2, a flight
+    /// recorder for context around an anomaly
+    #[arg(long, value_name = "N")]
+    ring_capacity: Option<usize>,
+    /// Also print the --ring-capacity events as soon as a task wakes from a
+    /// sleep of at least this many ms
+    #[arg(long, value_name = "MS", requires = "ring_capacity")]
+    ring_dump_sleep: Option<u64>,
+    /// Stream every collected event to the consumers of a Unix domain socket
+    /// at PATH, one JSON object per line
+    #[arg(long, value_name = "PATH")]
+    socket: Option<String>,
+    /// Events to keep while no --socket consumer is connected, sent to the
+    /// first one to connect. 0 drops them
+    #[arg(long, value_name = "N", default_value_t = 0, requires = "socket")]
+    socket_buffer: usize,
+    /// Rotate the --raw-out file to PATH.1, PATH.2, ... once it would exceed
+    /// this many bytes
+    #[arg(long, value_name = "BYTES", requires = "raw_out")]
+    rotate_size: Option<u64>,
+    /// Rotated --raw-out files to keep, older ones are deleted
+    #[arg(long, value_name = "N", default_value_t = 5, requires = "rotate_size")]
+    rotate_keep: u32,
+    /// Write off-CPU time by kernel stack in folded format on exit, for flamegraph.pl
+    #[arg(long, value_name = "PATH")]
+    offcpu_flamegraph: Option<String>,
+    /// Attribute each sleep to the syscall the task blocked in and report the
+    /// top blocking syscalls per task. Hooks every syscall system-wide
+    #[arg(long, default_value_t = false)]
+    syscall_sleeps: bool,
+    /// Poll the CPU affinity of every tracked task once a second and report
+    /// when it changes, e.g. through sched_setaffinity()
+    #[arg(long, default_value_t = false)]
+    track_affinity: bool,
+    /// Report events of the targets only, or of every task system-wide to find
+    /// the one misbehaving. All is costly, pair it with --sample and --top
+    #[arg(long, value_enum, default_value_t = TraceMode::Targeted)]
+    mode: TraceMode,
+    /// With --mode all, drop events of non-target tasks that ran for less
+    /// than this many microseconds
+    #[arg(long, value_name = "US")]
+    min_runtime: Option<u64>,
+    /// With --mode all, only keep one in N events of non-target tasks
+    #[arg(long, value_name = "N")]
+    sample: Option<u32>,
+    /// Only report tasks whose name matches this regex, including threads
+    /// spawned later. Filtered in userspace, so the events are still emitted
+    #[arg(long, value_name = "REGEX")]
+    filter_comm: Option<String>,
+    /// Never schedule or report this TID, e.g. a GC or logger thread; repeatable
+    #[arg(long = "exclude", value_name = "TID")]
+    exclude: Vec<i32>,
+    /// Only collect events that ran on these CPUs, e.g. 0,1,2
+    #[arg(long, value_delimiter = ',')]
+    cpu_filter: Vec<u32>,
+    /// Ring buffer size in pages (power of two), default is 256 KiB
+    #[arg(long)]
+    ringbuf_pages: Option<u32>,
+    /// Seconds between status lines in verbose mode, or JSON counter lines
+    #[arg(long, default_value_t = 5)]
+    stats_interval: u64,
+    /// Print the scheduler counters as text, or as one JSON object per
+    /// --stats-interval
+    #[arg(long, value_enum, default_value_t = StatsFormat::Text)]
+    stats_format: StatsFormat,
+    /// Skip the per-task reports, keeping the machine-readable outputs such
+    /// as --stats-format json, --merge or --trace-out
+    #[arg(long, default_value_t = false)]
+    no_report: bool,
+    /// End the output with one `SUMMARY tasks=N events=N dropped=N duration_ms=N`
+    /// line, a stable format for scripts
+    #[arg(long, default_value_t = false)]
+    summary_line: bool,
+    /// Pin the stats map and the maps `explain` reads (target_tids,
+    /// target_tgids, excluded_tids, bss) under DIR instead of only stats
+    /// under /sys/fs/bpf/scx_teddy. DIR must be on bpffs
+    #[arg(long, value_name = "DIR")]
+    pin_maps: Option<String>,
+    /// Measure and print scx_teddy's own event processing overhead on exit
+    #[arg(long, default_value_t = false)]
+    self_stats: bool,
+    /// Attach but leave every task on the default path until SIGUSR1. SIGUSR1
+    /// then toggles pausing instead of dumping stats
+    #[arg(long, default_value_t = false)]
+    start_paused: bool,
+    /// Reset the collected stats after each SIGUSR1 dump
+    #[arg(long, default_value_t = false)]
+    dump_reset: bool,
+    #[command(flatten)]
+    report: ReportArgs,
+    /// Always match targets through the target_tids map, even for a single TID,
+    /// so targets can be added at runtime (costs one hash lookup per callback)
+    #[arg(long, default_value_t = false)]
+    no_single_opt: bool,
+    /// Load even if the kernel version or config looks unsupported
+    #[arg(long, default_value_t = false)]
+    skip_kernel_check: bool,
+    /// Print libbpf debug output, including the verifier log on load failure
+    #[arg(long, default_value_t = false)]
+    debug_bpf: bool,
+    /// Abort if attaching the scheduler takes longer than this many seconds
+    #[arg(long, value_name = "SECONDS", default_value_t = 10)]
+    attach_timeout: u64,
+    /// Print every enqueue/dispatch decision made for a target task
+    #[arg(long, default_value_t = false)]
+    debug_decisions: bool,
+    /// With --debug-decisions, only print one in N decisions
+    #[arg(long, value_name = "N", default_value_t = 1, requires = "debug_decisions")]
+    decision_sample: u32,
+    /// With --debug-decisions, print at most N decisions per second (0 for no limit)
+    #[arg(long, value_name = "N", default_value_t = 1000, requires = "debug_decisions")]
+    decision_rate: u32,
+    /// Print the detected performance/efficiency core mapping and exit
+    #[arg(long, default_value_t = false)]
+    list_cpus: bool,
+    /// Print the TIDs the config, --tid, --tgid and --exclude resolve to and
+    /// exit without attaching
+    #[arg(long, default_value_t = false)]
+    list_targets: bool,
 }
 
-#[derive(Debug, Clone, Default)]
-struct TaskStats {
-    // Runtime statistics
-    runtime_sum: u64,
-    runtime_sum_sq: f64,  // Sum of squares for variance calculation
-    runtime_min: u64,
-    runtime_max: u64,
-
-    // Sleep statistics
-    sleep_sum: u64,
-    sleep_sum_sq: f64,
-    sleep_min: u64,
-    sleep_max: u64,
-    sleep_count: u64,  // Number of events with sleep
-
-    // Sleep interval statistics (time between sleeps)
-    last_sleep_end: u64,
-    sleep_interval_sum: u64,
-    sleep_interval_sum_sq: f64,
-    sleep_interval_min: u64,
-    sleep_interval_max: u64,
-    sleep_interval_count: u64,
-
-    event_count: u64,
-    parent: i32,
-    exit: u8,
+// How the per-task report is laid out, shared by run and replay
+#[derive(clap::Args, Debug)]
+struct ReportArgs {
+    /// Break runtime and sleep intervals down per CPU in the report
+    #[arg(long, default_value_t = false)]
+    show_cpu: bool,
+    /// Break runtime down per NUMA node in the report, flagging tasks that
+    /// ran on more than one node
+    #[arg(long, default_value_t = false)]
+    show_numa: bool,
+    /// Print a unitless energy score per task: runtime weighted by the max
+    /// frequency of the CPUs it ran on. An estimate, not a measurement
+    #[arg(long, default_value_t = false)]
+    energy: bool,
+    /// Number of longest individual sleeps to report per task, 0 disables
+    #[arg(long, default_value_t = 5)]
+    top_sleeps: usize,
+    /// Also report each task's runtime and sleep per time bucket of this many ms
+    #[arg(long, value_name = "BUCKET_MS")]
+    timeseries: Option<u64>,
+    /// How tasks are grouped in the report
+    #[arg(long, value_enum, default_value_t = GroupBy::Tid)]
+    group_by: GroupBy,
+    /// With --group-by tgid, also list each thread beneath its process
+    #[arg(long, default_value_t = false)]
+    show_threads: bool,
+    /// Order of tasks in the report, largest first
+    #[arg(long, value_enum, default_value_t = SortBy::Id)]
+    sort_by: SortBy,
+    /// Only report the first N tasks after sorting
+    #[arg(long, value_name = "N")]
+    top: Option<usize>,
+    /// Print one aligned line per task instead of the full breakdown
+    #[arg(long, default_value_t = false)]
+    compact: bool,
+    /// Decimals of the floating-point figures in the report
+    #[arg(long, value_name = "N", default_value_t = 2)]
+    precision: usize,
+    /// Wakeup-to-running latency percentiles to report per task
+    #[arg(long, value_delimiter = ',', default_values_t = [50.0, 95.0, 99.0, 99.9])]
+    percentiles: Vec<f64>,
+    /// Whether standard deviations divide by N or by N - 1, to match other tools
+    #[arg(long, value_enum, default_value_t = Variance::Population)]
+    variance: Variance,
+    /// Also report events/s, sleeps/s and runtime as a share of the period
+    #[arg(long, default_value_t = false)]
+    rates: bool,
+    /// Highlight notable report figures with ANSI colors
+    #[arg(long, value_enum, default_value_t = ColorChoice::Auto)]
+    color: ColorChoice,
 }
 
-impl TaskStats {
-    fn new(parent: i32) -> Self {
-        Self {
-            runtime_sum: 0,
-            runtime_sum_sq: 0.0,
-            runtime_min: u64::MAX,
-            runtime_max: 0,
+impl ReportArgs {
+    fn options(&self, no_report: bool, pidns: Option<PidNamespace>) -> Result<ReportOptions> {
+        if let Some(pct) = self.percentiles.iter().find(|&&pct| !(pct > 0.0 && pct <= 100.0)) {
+            bail!("--percentiles must be within (0, 100], got {}", pct);
+        }
+        let numa = if self.show_numa { Some(topology::NumaNodes::detect()?) } else { None };
+        // One node has nothing to break down
+        let numa = match numa {
+            Some(numa) if numa.nr_nodes <= 1 => {
+                eprintln!("Warning: single NUMA node, --show-numa adds nothing to the report");
+                None
+            }
+            numa => numa,
+        };
+        let power_weights = if self.energy { Some(topology::Topology::detect()?.power_weights()) } else { None };
+        Ok(ReportOptions {
+            show_cpu: self.show_cpu,
+            numa,
+            power_weights,
+            group_by: self.group_by,
+            sort_by: self.sort_by,
+            top: self.top,
+            compact: self.compact,
+            rates: self.rates,
+            show_threads: self.show_threads,
+            precision: self.precision,
+            percentiles: self.percentiles.clone(),
+            variance: self.variance,
+            no_report,
+            palette: Palette::new(self.color),
+            pidns,
+        })
+    }
+}
 
-            sleep_sum: 0,
-            sleep_sum_sq: 0.0,
-            sleep_min: u64::MAX,
-            sleep_max: 0,
-            sleep_count: 0,
+// Set by SIGUSR1, the main loop flips the BPF paused flag
+static TOGGLE_PAUSE: AtomicBool = AtomicBool::new(false);
+static DUMP_STATS: AtomicBool = AtomicBool::new(false);
+// Set by SIGUSR2, the main loop prints the --ring-capacity events
+static DUMP_RING: AtomicBool = AtomicBool::new(false);
+// Set by SIGHUP, the main loop reloads the config
+static RELOAD_CONFIG: AtomicBool = AtomicBool::new(false);
 
-            last_sleep_end: 0,
-            sleep_interval_sum: 0,
-            sleep_interval_sum_sq: 0.0,
-            sleep_interval_min: u64::MAX,
-            sleep_interval_max: 0,
-            sleep_interval_count: 0,
+// Set by SIGTERM, stops the main loop like Ctrl+C so every output is finished
+static TERMINATE: AtomicBool = AtomicBool::new(false);
 
-            event_count: 0,
-            parent,
-            exit: 0,
-        }
+// Bumped by the ring buffer callback, which only queues events for the main loop
+static EVENTS_RECEIVED: AtomicU64 = AtomicU64::new(0);
+
+// SIGUSR1 toggles pausing in --start-paused runs and dumps stats otherwise
+extern "C" fn handle_sigusr1_pause(_: libc::c_int) {
+    TOGGLE_PAUSE.store(true, Ordering::Relaxed);
+}
+
+extern "C" fn handle_sigusr1_dump(_: libc::c_int) {
+    DUMP_STATS.store(true, Ordering::Relaxed);
+}
+
+extern "C" fn handle_sigusr2(_: libc::c_int) {
+    DUMP_RING.store(true, Ordering::Relaxed);
+}
+
+extern "C" fn handle_sighup(_: libc::c_int) {
+    RELOAD_CONFIG.store(true, Ordering::Relaxed);
+}
+
+extern "C" fn handle_sigterm(_: libc::c_int) {
+    TERMINATE.store(true, Ordering::Relaxed);
+}
+
+// Handlers must be async-signal-safe, in practice only touching atomics
+fn install_signal(signal: libc::c_int, handler: extern "C" fn(libc::c_int)) {
+    // SAFETY: handler is a valid extern "C" function for the whole program
+    unsafe {
+        libc::signal(signal, handler as libc::sighandler_t);
     }
+}
 
-    fn update(&mut self, runtime_ns: u64, sleep_ns: u64, sleep_end: u64) {
-        self.event_count += 1;
+// UTC wall-clock time as ISO-8601 with milliseconds, e.g. 2024-05-01T12:00:00.123Z,
+// to line verbose output up with other logs
+fn timestamp() -> String {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    let secs = now.as_secs();
+    let (days, rem) = ((secs / 86400) as i64, secs % 86400);
 
-        // Update runtime statistics
-        self.runtime_sum += runtime_ns;
-        self.runtime_sum_sq += (runtime_ns as f64) * (runtime_ns as f64);
-        self.runtime_min = self.runtime_min.min(runtime_ns);
-        self.runtime_max = self.runtime_max.max(runtime_ns);
+    // Civil date from days since the epoch (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
 
-        // Update sleep statistics
-        if sleep_ns > 0 {
-            self.sleep_count += 1;
-            self.sleep_sum += sleep_ns;
-            self.sleep_sum_sq += (sleep_ns as f64) * (sleep_ns as f64);
-            self.sleep_min = self.sleep_min.min(sleep_ns);
-            self.sleep_max = self.sleep_max.max(sleep_ns);
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year, month, day, rem / 3600, rem % 3600 / 60, rem % 60, now.subsec_millis())
+}
 
-            // Update sleep interval statistics
-            if self.last_sleep_end > 0 && sleep_end > self.last_sleep_end {
-                let interval = sleep_end - self.last_sleep_end;
-                self.sleep_interval_count += 1;
-                self.sleep_interval_sum += interval;
-                self.sleep_interval_sum_sq += (interval as f64) * (interval as f64);
-                self.sleep_interval_min = self.sleep_interval_min.min(interval);
-                self.sleep_interval_max = self.sleep_interval_max.max(interval);
-            }
-            self.last_sleep_end = sleep_end;
+// Exit statuses of a run besides 0, 1 for errors and 2 for bad arguments;
+// documented in the README, keep them stable
+const EXIT_NO_EVENTS: u8 = 3;
+const EXIT_WATCHDOG: u8 = 4;  // sched_ext watchdog: a runnable task stalled
+const EXIT_EJECTED: u8 = 5;   // Any other exit forced by the kernel or BPF
+const EXIT_STRICT: u8 = 6;    // --strict and a warning was raised
+
+// Share of events lost to a full ring buffer above which the run warns
+const HIGH_DROP_RATE: f64 = 0.01;
+
+// scx_exit_kind of a watchdog stall, from the kernel's ext.c
+const SCX_EXIT_ERROR_STALL: i32 = 1026;
+
+// Kind and description of the exit the BPF exit callback recorded in uei
+fn ejection(skel: &BpfSkel) -> (i32, String) {
+    let uei = &skel.maps.data_data.as_ref().unwrap().uei;
+    let text = |chars: &[std::ffi::c_char]| -> String {
+        let bytes: Vec<u8> = chars.iter().take_while(|&&c| c != 0).map(|&c| c as u8).collect();
+        String::from_utf8_lossy(&bytes).into_owned()
+    };
+    let (reason, msg) = (text(&uei.reason), text(&uei.msg));
+    let why = if msg.is_empty() { reason } else { format!("{} ({})", reason, msg) };
+    (uei.kind, why)
+}
+
+// Smaller --rotate-size values would rotate every few events
+const MIN_ROTATE_SIZE: u64 = 4096;
+
+// Upper bound on how long shutdown waits for the main loop to notice Ctrl+C
+const POLL_TIMEOUT: Duration = Duration::from_millis(100);
+
+// Time spent by scx_teddy itself collecting events
+#[derive(Debug, Default)]
+struct SelfStats {
+    events: u64,
+    process_time: Duration,
+    polls: u64,
+    wakeups: u64,  // Polls that delivered at least one event
+    poll_time: Duration,
+}
+
+impl SelfStats {
+    fn record_batch(&mut self, elapsed: Duration, events: usize) {
+        self.events += events as u64;
+        self.process_time += elapsed;
+    }
+
+    fn record_poll(&mut self, elapsed: Duration, events: usize) {
+        self.polls += 1;
+        self.poll_time += elapsed;
+        if events > 0 {
+            self.wakeups += 1;
         }
     }
+
+    fn print(&self) {
+        let avg_event_ns = if self.events > 0 {
+            self.process_time.as_nanos() as f64 / self.events as f64
+        } else {
+            0.0
+        };
+        println!("\nOverhead:");
+        println!("  Events processed: {}, avg {:.2} us per event, total {:.2} ms",
+            self.events, avg_event_ns / 1000.0, self.process_time.as_secs_f64() * 1000.0);
+        println!("  Polls: {}, wakeups with events: {}, time in poll {:.2} s",
+            self.polls, self.wakeups, self.poll_time.as_secs_f64());
+    }
 }
 
 #[repr(C)]
+#[derive(Clone, Copy)]
 struct TaskEvent {
     tid: i32,
     parent: i32,
+    tgid: i32,
+    timestamp: u64,
     sleep_start: u64,
     sleep_end: u64,
-    runtime_ns: u64
+    runtime_ns: u64,
+    wait_ns: u64,
+    sleep_d_ns: u64,
+    sleep_s_ns: u64,
+    wakeup_latency_ns: u64,
+    throttled: u8,
+    nr_preempted: u32,
+    nr_voluntary: u32,
+    cpu: u32,
+    stack_id: i32,
+    waker_tid: i32,
+    nice: i32,
+    sleep_syscall: i32,
+    comm: [u8; 16],
 }
 
 unsafe impl Plain for TaskEvent {}
 
-// Process event received from ring buffer
-fn process_event(data: &[u8], stats: &Arc<Mutex<std::collections::HashMap<i32, TaskStats>>>) -> i32 {
-    let event = plain::from_bytes::<TaskEvent>(data).unwrap();
+impl TaskEvent {
+    fn comm(&self) -> std::borrow::Cow<'_, str> {
+        let len = self.comm.iter().position(|&c| c == 0).unwrap_or(self.comm.len());
+        String::from_utf8_lossy(&self.comm[..len])
+    }
+}
 
-    let sleep_duration = if event.sleep_end > event.sleep_start {
-        event.sleep_end - event.sleep_start
-    } else {
-        0
+#[cfg(test)]
+impl TaskEvent {
+    /// An event with every field set, for the output format tests
+    fn sample(tid: i32, comm: &str) -> Self {
+        // SAFETY: TaskEvent is a plain C struct, all-zero is a valid value
+        let mut e: Self = unsafe { std::mem::zeroed() };
+        e.tid = tid;
+        e.parent = 1;
+        e.tgid = tid;
+        e.timestamp = 1_000_000_000 + tid as u64;
+        e.sleep_start = 10;
+        e.sleep_end = 20;
+        e.runtime_ns = 300_000;
+        e.wait_ns = 4_000;
+        e.sleep_d_ns = 5;
+        e.sleep_s_ns = 6;
+        e.wakeup_latency_ns = 7_000;
+        e.throttled = 1;
+        e.nr_preempted = 2;
+        e.nr_voluntary = 3;
+        e.cpu = 4;
+        e.stack_id = -1;
+        e.waker_tid = 42;
+        e.nice = -5;
+        e.sleep_syscall = -1;
+        e.comm[..comm.len()].copy_from_slice(comm.as_bytes());
+        e
+    }
+}
+
+// Write the target selection into bss and per-task settings into target_tids
+fn apply_config(skel: &mut BpfSkel, config: &Config, single_opt: bool) -> Result<(), TeddyError> {
+    let bss = skel.maps.bss_data.as_mut().unwrap();
+    bss.target_mode = config.target_mode;
+    bss.dispatch_strategy = config.dispatch_strategy_id();
+    bss.idle_preference = config.idle_preference().1;
+    if let Some(tgid) = config.tgid {
+        bss.target_single_tgid = tgid;
+    }
+    // A lone TID is matched directly, skipping the target_tids lookup. Without
+    // it more targets can be added to the map while running.
+    if single_opt && config.target_mode as u32 == bpf_intf::MODE_TID && config.tasks.len() == 1 {
+        bss.target_single_tid = config.tasks[0].tid;
+    }
+    if let Some(default) = &config.default {
+        bss.default_enabled = 1;
+        bss.default_prio = default.resolved_prio(config.base_prio);
+        bss.default_slice = default.resolved_slice(config.base_slice);
+        bss.default_config = default.on_ecore;
+        bss.default_budget_ns = default.budget_ns;
+        bss.default_window_ns = default.window_ns;
+        bss.default_weight = default.weight.unwrap_or(0);
+        bss.default_preempt_latency_ns = default.preempt_latency_ns.max(0) as u64;
+    }
+
+    for task in &config.tasks {
+        insert_target_tid(&skel.maps.target_tids, task.tid, &policy_ctx(&task.policy, config.base_prio, config.base_slice))?;
+    }
+    // The tgid's other threads have no target_tids entry
+    if config.target_mode as u32 == bpf_intf::MODE_TGID {
+        apply_normal_default(skel);
+    }
+
+    Ok(())
+}
+
+fn policy_ctx(policy: &TaskPolicy, base_prio: Option<i32>, base_slice: Option<u64>) -> bpf_intf::target_ctx_t {
+    // SAFETY: target_ctx_t is a plain C struct, all-zero is a valid value
+    let mut ctx: bpf_intf::target_ctx_t = unsafe { std::mem::zeroed() };
+    ctx.prio = policy.resolved_prio(base_prio);
+    ctx.slice = policy.resolved_slice(base_slice);
+    ctx.config = policy.on_ecore;
+    ctx.budget_ns = policy.budget_ns;
+    ctx.window_ns = policy.window_ns;
+    ctx.weight = policy.weight.unwrap_or(0);
+    ctx.preempt_latency_ns = policy.preempt_latency_ns.max(0) as u64;
+    ctx
+}
+
+fn insert_target_tid(target_tids: &MapMut, tid: i32, ctx: &bpf_intf::target_ctx_t) -> Result<(), TeddyError> {
+    let key = tid.to_ne_bytes();
+    let val = unsafe { plain::as_bytes(ctx) };
+    let result = target_tids.update(&key, val, MapFlags::ANY);
+    let counter = match result {
+        Ok(()) => &sched_stats::TARGET_UPDATES_OK,
+        Err(_) => &sched_stats::TARGET_UPDATES_FAILED,
     };
+    counter.fetch_add(1, Ordering::Relaxed);
+    result.map_err(|source| TeddyError::MapUpdate {
+        map: "target_tids",
+        key: format!("TID {}", tid),
+        source,
+    })
+}
 
-    // Update statistics
-    let mut stats = stats.lock().unwrap();
+// Without a config default block, targets that have no target_tids entry
+// get the lowest config tier. The BPF side falls back to it as well; setting
+// it here makes explain report where the settings come from.
+fn apply_normal_default(skel: &mut BpfSkel) {
+    let bss = skel.maps.bss_data.as_mut().unwrap();
+    if bss.default_enabled == 0 {
+        bss.default_enabled = 1;
+        bss.default_prio = bpf_intf::TIER_NORMAL as i32;
+        bss.default_slice = bpf_intf::DEFAULT_SLICE as u64;
+        bss.default_config = 1;
+    }
+}
 
-    if event.parent > 0 {
-        let task_stats = stats.entry(event.tid).or_insert(TaskStats::new(event.parent));
-        task_stats.update(event.runtime_ns, sleep_duration, event.sleep_end);
-    } else if event.parent == -1 {
-        if let Some(task_stats) = stats.get_mut(&event.tid) {
-            task_stats.exit = 1;
+// Switch to MODE_MIXED, where a task is a target if its TID or its TGID is
+// listed. The config's own TIDs and tgid stay targets alongside the flags.
+fn apply_mixed_targets(
+    skel: &mut BpfSkel,
+    tids: &[i32],
+    tgids: &[i32],
+    config: Option<&Config>,
+) -> Result<(), TeddyError> {
+    // Flag TIDs use the config's default block, or the lowest config tier
+    // without one
+    let ctx = match config.and_then(|config| config.default.as_ref().map(|default| (default, config))) {
+        Some((default, config)) => policy_ctx(default, config.base_prio, config.base_slice),
+        None => {
+            // SAFETY: target_ctx_t is a plain C struct, all-zero is a valid value
+            let mut ctx: bpf_intf::target_ctx_t = unsafe { std::mem::zeroed() };
+            ctx.prio = bpf_intf::TIER_NORMAL as i32;
+            ctx.slice = bpf_intf::DEFAULT_SLICE as u64;
+            ctx.config = 1;
+            ctx
         }
+    };
+    // Threads matched by TGID have no target_tids entry and read the default
+    apply_normal_default(skel);
+    for &tid in tids {
+        insert_target_tid(&skel.maps.target_tids, tid, &ctx)?;
     }
 
-    0
+    let config_tgid = config
+        .filter(|config| config.target_mode as u32 == bpf_intf::MODE_TGID)
+        .and_then(|config| config.tgid);
+    for &tgid in tgids.iter().chain(config_tgid.iter()) {
+        skel.maps
+            .target_tgids
+            .update(&tgid.to_ne_bytes(), &[1u8], MapFlags::ANY)
+            .map_err(|source| TeddyError::MapUpdate {
+                map: "target_tgids",
+                key: format!("TGID {}", tgid),
+                source,
+            })?;
+    }
+
+    let bss = skel.maps.bss_data.as_mut().unwrap();
+    bss.target_mode = bpf_intf::MODE_MIXED as i32;
+    bss.target_single_tid = 0;
+    bss.target_single_tgid = 0;
+    Ok(())
 }
 
-fn main() -> Result<()> {
-    let args = Args::parse();
-    println!("scx_teddy scheduler starting...");
+// Room in target_tids for threads resolved from a --seed-config
+const SEED_TARGET_TIDS: usize = 4096;
 
-    // Build and load eBPF skeleton
-    let skel_builder = BpfSkelBuilder::default();
-    let mut open_object = MaybeUninit::uninit();
-    let mut open_skel = skel_builder.open(&mut open_object).context("Failed to open BPF object")?;
+// Insert the threads a seed scan matched and drop the ones that went away.
+// A failed insert, e.g. into a full target_tids, leaves that thread
+// unmanaged but the scheduler running.
+fn apply_seed_scan(target_tids: &MapMut, seeder: &mut seed::Seeder) {
+    let Some(scan) = seeder.scan_if_due() else {
+        return;
+    };
+    for tid in scan.gone {
+        let _ = target_tids.delete(&tid.to_ne_bytes());
+    }
+    for (tid, index) in scan.resolved {
+        let entry = seeder.entry(index);
+        let ctx = policy_ctx(&entry.policy, seeder.seed.base_prio, seeder.seed.base_slice);
+        match insert_target_tid(target_tids, tid, &ctx) {
+            Ok(()) => println!("Seed: resolved \"{}\" to TID {}", entry.comm, tid),
+            Err(e) => eprintln!("Warning: seed \"{}\" matched TID {} but it is not managed: {}", entry.comm, tid, e),
+        }
+    }
+}
+
+// Keep the given TIDs out of scheduling and reports, warning about any
+// that are not threads of the target tgid
+fn apply_exclusions(
+    skel: &mut BpfSkel,
+    tids: &[i32],
+    tgid: Option<i32>,
+    warnings: &mut warnings::Warnings,
+) -> Result<(), TeddyError> {
+    for &tid in tids {
+        if let Some(tgid) = tgid {
+            if !std::path::Path::new(&format!("/proc/{}/task/{}", tgid, tid)).exists() {
+                warnings.warn(format!("excluded TID {} is not a thread of tgid {}", tid, tgid));
+            }
+        }
+
+        skel.maps
+            .excluded_tids
+            .update(&tid.to_ne_bytes(), &[1u8], MapFlags::ANY)
+            .map_err(|source| TeddyError::MapUpdate {
+                map: "excluded_tids",
+                key: format!("TID {}", tid),
+                source,
+            })?;
+    }
+
+    skel.maps.bss_data.as_mut().unwrap().exclude_enabled = 1;
+    Ok(())
+}
+
+// Restrict event collection to the given CPUs
+fn apply_cpu_filter(skel: &mut BpfSkel, cpus: &[u32]) -> Result<()> {
+    let online: Vec<usize> = topology::Topology::detect()?.cpus.iter().map(|cpu| cpu.id).collect();
+    let bss = skel.maps.bss_data.as_mut().unwrap();
+
+    for &cpu in cpus {
+        if cpu >= bpf_intf::MAX_CPUS || !online.contains(&(cpu as usize)) {
+            bail!("--cpu-filter: CPU {} is not online", cpu);
+        }
+        bss.cpu_filter[(cpu / 64) as usize] |= 1u64 << (cpu % 64);
+    }
+    bss.cpu_filter_enabled = 1;
+
+    Ok(())
+}
+
+// Confine targets to the first max_cpus online CPUs, on top of their own affinity
+fn apply_max_cpus(skel: &mut BpfSkel, max_cpus: u32) -> Result<()> {
+    let online: Vec<usize> = topology::Topology::detect()?
+        .cpus
+        .iter()
+        .map(|cpu| cpu.id)
+        .filter(|&id| id < bpf_intf::MAX_CPUS as usize)
+        .collect();
+    if max_cpus as usize > online.len() {
+        bail!("max_cpus {} exceeds the {} online CPUs", max_cpus, online.len());
+    }
+    let managed = &online[..max_cpus as usize];
+    let bss = skel.maps.bss_data.as_mut().unwrap();
+
+    for &cpu in managed {
+        bss.managed_cpus[cpu / 64] |= 1u64 << (cpu % 64);
+    }
+    // Validation guarantees max_cpus >= 1
+    bss.managed_cpus_last = managed[managed.len() - 1] as u32;
+    bss.managed_cpus_enabled = 1;
+    bss.nr_managed_cpus = managed.len() as u32;
+
+    println!("Managed CPUs: {} ({} of {} online)", topology::format_cpulist(managed), managed.len(), online.len());
+    Ok(())
+}
+
+// Let the BPF side check on_ecore placements against the real core types
+fn apply_ecore_mask(skel: &mut BpfSkel) -> Result<()> {
+    let topo = topology::Topology::detect()?;
+    if !topo.is_hybrid() {
+        return Ok(());
+    }
+
+    let bss = skel.maps.bss_data.as_mut().unwrap();
+    for cpu in topo.cpus_of(topology::CoreType::Efficiency) {
+        if cpu.id < bpf_intf::MAX_CPUS as usize {
+            bss.ecore_mask[cpu.id / 64] |= 1u64 << (cpu.id % 64);
+        }
+    }
+    bss.ecore_mask_enabled = 1;
+
+    Ok(())
+}
+
+fn print_libbpf_log(_level: PrintLevel, msg: String) {
+    eprint!("libbpf: {}", msg);
+}
+
+// Resolve the targets the way the BPF side will match them, see apply_config
+// and apply_mixed_targets
+fn list_targets(args: &RunArgs, config: Option<&Config>) -> Result<()> {
+    let flag_targets = !args.tid.is_empty() || !args.tgid.is_empty();
+    let mut resolution = targets::Resolution::default();
+
+    if let Some(config) = config {
+        let mode = config.target_mode as u32;
+        if flag_targets || mode != bpf_intf::MODE_TGID {
+            for task in &config.tasks {
+                resolution.add_tid(task.tid, "config task".to_string());
+            }
+        }
+        if let Some(tgid) = config.tgid.filter(|_| flag_targets || mode != bpf_intf::MODE_TID) {
+            resolution.add_tgid(tgid, format!("config tgid {}", tgid));
+        }
+        if let Some(path) = &config.cgroup {
+            for tid in cgroup::threads(path)? {
+                resolution.add_tid(tid, format!("config cgroup {}", path));
+            }
+        }
+    }
+    for &tid in &args.tid {
+        resolution.add_tid(tid, format!("--tid {}", tid));
+    }
+    for &tgid in &args.tgid {
+        resolution.add_tgid(tgid, format!("--tgid {}", tgid));
+    }
+    for &tid in &args.exclude {
+        resolution.exclude(tid);
+    }
+
+    resolution.print()
+}
+
+fn process_exists(tgid: i32) -> bool {
+    std::path::Path::new(&format!("/proc/{}", tgid)).exists()
+}
+
+fn page_size() -> u32 {
+    // SAFETY: sysconf has no preconditions
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as u32 }
+}
+
+// Settings applied while the skeleton is open, before load()
+struct LoadOptions {
+    ringbuf_pages: Option<u32>,
+    debug_bpf: bool,
+    offcpu_stacks: bool,
+    syscall_sleeps: bool,
+    debug_decisions: bool,
+    // Entries each TID/TGID map must hold
+    nr_target_tids: usize,
+    nr_target_tgids: usize,
+    nr_excluded_tids: usize,
+}
+
+// Largest target map accepted, far beyond any sane config
+const TARGET_MAP_CEILING: usize = 1 << 20;
+
+// Make room for `needed` entries, never below the compiled-in TARGET_TIDS_MAX
+// so targets can still be added at runtime
+fn resize_target_map(map: &mut OpenMapMut, name: &'static str, needed: usize) -> Result<(), TeddyError> {
+    if needed > TARGET_MAP_CEILING {
+        return Err(TeddyError::TooManyTargets { map: name, needed, max: TARGET_MAP_CEILING });
+    }
+    let entries = needed.next_power_of_two().max(bpf_intf::TARGET_TIDS_MAX as usize);
+    map.set_max_entries(entries as u32)
+        .map_err(|source| TeddyError::MapResize { map: name, source })
+}
+
+// Build and load eBPF skeleton
+fn load_skel<'obj>(
+    open_object: &'obj mut MaybeUninit<OpenObject>,
+    opts: &LoadOptions,
+) -> Result<BpfSkel<'obj>, TeddyError> {
+    let mut skel_builder = BpfSkelBuilder::default();
+    if opts.debug_bpf {
+        // Debug level includes the full verifier log when a program fails to load
+        libbpf_rs::set_print(Some((PrintLevel::Debug, print_libbpf_log)));
+        skel_builder.obj_builder.debug(true);
+    }
+    let mut open_skel = skel_builder.open(open_object).map_err(TeddyError::BpfOpen)?;
+
+    if let Some(pages) = opts.ringbuf_pages {
+        let page_size = page_size();
+        let size = pages
+            .checked_mul(page_size)
+            .ok_or(TeddyError::RingbufTooLarge { pages, page_size })?;
+        open_skel
+            .maps
+            .events
+            .set_max_entries(size)
+            .map_err(|source| TeddyError::MapResize { map: "events", source })?;
+    }
+    if !opts.debug_decisions {
+        // Smallest ring buffer the kernel accepts
+        open_skel
+            .maps
+            .decisions
+            .set_max_entries(page_size())
+            .map_err(|source| TeddyError::MapResize { map: "decisions", source })?;
+    }
+    resize_target_map(&mut open_skel.maps.target_tids, "target_tids", opts.nr_target_tids)?;
+    resize_target_map(&mut open_skel.maps.target_tids_alt, "target_tids_alt", opts.nr_target_tids)?;
+    resize_target_map(&mut open_skel.maps.target_tgids, "target_tgids", opts.nr_target_tgids)?;
+    resize_target_map(&mut open_skel.maps.excluded_tids, "excluded_tids", opts.nr_excluded_tids)?;
+    if !opts.offcpu_stacks {
+        // Stack maps are preallocated, don't pay for one that stays unused
+        open_skel.progs.teddy_sched_switch.set_autoload(false);
+        open_skel
+            .maps
+            .stack_traces
+            .set_max_entries(1)
+            .map_err(|source| TeddyError::MapResize { map: "stack_traces", source })?;
+    }
+    if !opts.syscall_sleeps {
+        open_skel.progs.teddy_sys_enter.set_autoload(false);
+        open_skel.progs.teddy_sys_exit.set_autoload(false);
+    }
 
     // Initialize SCX enums from kernel BTF (SCX_DSQ_LOCAL_ON, etc.)
     scx_utils::import_enums!(open_skel);
 
-    let mut skel = open_skel.load().context("Failed to load BPF object")?;
+    let skel = open_skel.load().map_err(TeddyError::BpfLoad)?;
+    check_event_layout()?;
+    Ok(skel)
+}
+
+// TaskEvent is parsed straight from ring buffer bytes, so a layout drift
+// against task_event_t in intf.h would silently corrupt every event
+fn check_event_layout() -> Result<(), TeddyError> {
+    let rust = std::mem::size_of::<TaskEvent>();
+    let bpf = std::mem::size_of::<bpf_intf::task_event_t>();
+    if rust != bpf {
+        return Err(TeddyError::EventLayout { rust, bpf });
+    }
+    Ok(())
+}
 
-    // Load and attach the scheduler struct_ops
-    let _struct_ops = skel
-        .maps
-        .teddy_ops
-        .attach_struct_ops()
-        .context("Failed to attach struct_ops")?;
+// Attach the scheduler struct_ops, the returned link keeps it attached.
+// The attach blocks in the kernel and can hang when sched_ext is in a bad
+// state, and the skeleton can't move to another thread, so a watchdog thread
+// aborts the whole process if it takes longer than `timeout`.
+fn attach(skel: &mut BpfSkel, timeout: Duration) -> Result<Link, TeddyError> {
+    let (done, attached) = std::sync::mpsc::channel::<()>();
+    std::thread::spawn(move || {
+        if let Err(std::sync::mpsc::RecvTimeoutError::Timeout) = attached.recv_timeout(timeout) {
+            eprintln!("Error: attaching the scheduler did not finish within {}s, sched_ext may be \
+                in a bad state (see /sys/kernel/sched_ext/state)", timeout.as_secs());
+            std::process::exit(1);
+        }
+    });
 
-    // Statistics storage
-    let stats: Arc<Mutex<std::collections::HashMap<i32, TaskStats>>> =
-        Arc::new(Mutex::new(std::collections::HashMap::new()));
-    let stats_clone = Arc::clone(&stats);
+    let link = skel.maps.teddy_ops.attach_struct_ops().map_err(TeddyError::Attach);
+    let _ = done.send(());
+    link
+}
+
+/// Which tasks the BPF side emits events for
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+enum TraceMode {
+    /// Only the tasks selected by --config, --tid and --tgid
+    #[default]
+    Targeted,
+    /// Every task on the system, a discovery mode
+    All,
+}
+
+/// What happens to a new TID once --max-tasks are tracked
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+enum MaxTasksPolicy {
+    /// Stop tracking new TIDs, warning once
+    #[default]
+    IgnoreNew,
+    /// Drop the tracked task with the fewest events to make room
+    EvictLeastActive,
+}
+
+// How process_event builds TaskStats
+struct CollectOptions {
+    top_sleeps: usize,
+    bucket_ns: Option<u64>,
+    // Timestamp of the first event, time series buckets count from here
+    trace_start: u64,
+    // Events are dropped until then, None once the warmup is over
+    warmup_end: Option<Instant>,
+    warmup_discarded: u64,
+    max_tasks: Option<usize>,
+    max_tasks_policy: MaxTasksPolicy,
+    // --filter-comm, and the events it dropped
+    comm_filter: Option<regex::Regex>,
+    comm_filtered: u64,
+    // Events of untracked TIDs and tasks evicted because of max_tasks
+    capped_events: u64,
+    evicted_tasks: u64,
+}
+
+// Everything built from events. Only the main loop touches it, so event
+// processing takes no locks.
+struct Collector {
+    stats: std::collections::HashMap<i32, TaskStats>,
+    trace: Option<Trace>,
+    offcpu: Option<OffCpu>,
+    // Every TID tracked during the run, only kept for --summary-line
+    seen_tids: Option<HashSet<i32>>,
+    raw: Option<RawWriter>,
+    socket: Option<socket::EventSocket>,
+    recorder: Option<recorder::FlightRecorder>,
+    affinity: Option<affinity::AffinityTracker>,
+    // --threads above 1: TaskStats are built by the shards and copied into
+    // `stats` by sync()
+    shards: Option<shards::ShardPool>,
+    opts: CollectOptions,
+}
+
+// A task's accumulator as first seen by the collection
+fn new_task_stats(parent: i32, top_sleeps: usize, bucket_ns: Option<u64>, trace_start: u64) -> TaskStats {
+    let mut task_stats = TaskStats::new(parent);
+    task_stats.longest_sleeps = TopSleeps::new(top_sleeps);
+    task_stats.timeseries = bucket_ns.map(|bucket_ns| TimeSeries::new(bucket_ns, trace_start));
+    task_stats
+}
+
+impl Collector {
+    fn process_event(&mut self, event: &TaskEvent) {
+        let collect = &mut self.opts;
+        if let Some(warmup_end) = collect.warmup_end {
+            if Instant::now() < warmup_end {
+                collect.warmup_discarded += 1;
+                return;
+            }
+            println!("Warmup over, discarded {} events", collect.warmup_discarded);
+            collect.warmup_end = None;
+        }
+
+        if collect.trace_start == 0 {
+            collect.trace_start = event.timestamp;
+        }
+        if let Some(filter) = &collect.comm_filter {
+            if !filter.is_match(&event.comm()) {
+                collect.comm_filtered += 1;
+                return;
+            }
+        }
+        if let Some(raw) = &mut self.raw {
+            raw.write(event);
+        }
+        if let Some(socket) = &mut self.socket {
+            socket.write(event);
+        }
+        if let Some(recorder) = &mut self.recorder {
+            recorder.record(event);
+        }
+
+        if event.parent > 0 {
+            if !self.make_room(event.tid) {
+                return;
+            }
+            let collect = &self.opts;
+            if let Some(seen_tids) = &mut self.seen_tids {
+                seen_tids.insert(event.tid);
+            }
+            if let Some(affinity) = &mut self.affinity {
+                affinity.track(event.tid);
+            }
+            if let Some(shards) = &mut self.shards {
+                shards.push(event);
+            } else {
+                self.stats
+                    .entry(event.tid)
+                    .or_insert_with(|| new_task_stats(event.parent, collect.top_sleeps, collect.bucket_ns, collect.trace_start))
+                    .update(event);
+            }
+            if let Some(trace) = &mut self.trace {
+                trace.record(event);
+            }
+            if let Some(offcpu) = &mut self.offcpu {
+                offcpu.record(event);
+            }
+        } else if event.parent == -1 {
+            if let Some(shards) = &mut self.shards {
+                shards.push(event);
+            } else if let Some(task_stats) = self.stats.get_mut(&event.tid) {
+                task_stats.exit = 1;
+                task_stats.last_seen = task_stats.last_seen.max(event.timestamp);
+            }
+        }
+    }
+
+    // Bring `stats` up to date with the shards before it is read, a no-op
+    // without --threads
+    fn sync(&mut self) {
+        if let Some(shards) = &self.shards {
+            self.stats = shards.snapshot();
+        }
+    }
+
+    // Start over for a new period
+    fn clear_stats(&mut self) {
+        self.stats.clear();
+        if let Some(shards) = &self.shards {
+            shards.clear();
+        }
+        // The new period starts from each task's current mask
+        if let Some(affinity) = &mut self.affinity {
+            affinity.reset();
+        }
+    }
+
+    // Whether `tid` may have an entry under --max-tasks, evicting another
+    // task first when the policy asks for it
+    fn make_room(&mut self, tid: i32) -> bool {
+        let Some(max_tasks) = self.opts.max_tasks else {
+            return true;
+        };
+        if self.stats.len() < max_tasks || self.stats.contains_key(&tid) {
+            return true;
+        }
+
+        match self.opts.max_tasks_policy {
+            MaxTasksPolicy::IgnoreNew => {
+                self.opts.capped_events += 1;
+                if self.opts.capped_events == 1 {
+                    eprintln!("Warning: tracking --max-tasks {} tasks, ignoring new TIDs from {} on", max_tasks, tid);
+                }
+                false
+            }
+            MaxTasksPolicy::EvictLeastActive => {
+                // Fewest events first, the longest idle among those
+                let victim = self
+                    .stats
+                    .iter()
+                    .min_by_key(|(_, task_stats)| (task_stats.event_count, task_stats.last_seen))
+                    .map(|(&victim, _)| victim);
+                if let Some(victim) = victim {
+                    self.stats.remove(&victim);
+                }
+                self.opts.evicted_tasks += 1;
+                if self.opts.evicted_tasks == 1 {
+                    eprintln!("Warning: tracking --max-tasks {} tasks, evicting the least active for new TIDs", max_tasks);
+                }
+                true
+            }
+        }
+    }
+
+    // Apply the events queued by the ring buffer callback in one go, `batch`
+    // is scratch space kept across calls to reuse its allocation
+    fn drain(&mut self, pending: &RefCell<Vec<TaskEvent>>, batch: &mut Vec<TaskEvent>) -> usize {
+        std::mem::swap(&mut *pending.borrow_mut(), batch);
+        for event in batch.iter() {
+            self.process_event(event);
+        }
+        if let Some(shards) = &mut self.shards {
+            shards.flush(self.opts.trace_start);
+        }
+        if let Some(affinity) = &mut self.affinity {
+            for change in affinity.poll_if_due() {
+                if let Some(shards) = &self.shards {
+                    shards.record_affinity(change.tid, change.timestamp, change.cpus);
+                } else if let Some(task_stats) = self.stats.get_mut(&change.tid) {
+                    task_stats.record_affinity(change.timestamp, change.cpus);
+                }
+            }
+        }
+        if let Some(raw) = &mut self.raw {
+            raw.flush_if_due();
+        }
+        if let Some(socket) = &mut self.socket {
+            socket.poll();
+        }
+        let events = batch.len();
+        batch.clear();
+        events
+    }
+}
+
+// Per-task report followed by the scheduler-wide counters
+fn print_period_report(
+    stats_map: &std::collections::HashMap<i32, TaskStats>,
+    opts: &ReportOptions,
+    period: Duration,
+    skel: &BpfSkel,
+    stats_format: StatsFormat,
+) -> Result<()> {
+    print_report(stats_map, opts, period);
+    SchedStats::read(&skel.maps.stats)?.with_map_ops().with_frozen(&skel.maps.frozen_tids).print_as(stats_format)
+}
+
+// Feed saved events through the same collection path as a live run and
+// report them as one period spanning the recording
+fn replay(args: ReplayArgs) -> Result<()> {
+    if args.report.timeseries == Some(0) {
+        bail!("--timeseries bucket must be at least 1 ms");
+    }
+    let report_opts = args.report.options(false, None)?;
+    let events = raw_events::read(&args.csv)?;
+    let Some((first, last)) = events.first().zip(events.last()) else {
+        bail!("{} holds no events", args.csv);
+    };
+    let period = Duration::from_nanos(last.timestamp.saturating_sub(first.timestamp));
+
+    let mut collector = Collector {
+        stats: std::collections::HashMap::new(),
+        trace: None,
+        offcpu: None,
+        seen_tids: None,
+        raw: None,
+        socket: None,
+        recorder: None,
+        affinity: None,
+        shards: None,
+        opts: CollectOptions {
+            top_sleeps: args.report.top_sleeps,
+            bucket_ns: args.report.timeseries.map(|ms| ms * 1_000_000),
+            trace_start: 0,
+            warmup_end: None,
+            warmup_discarded: 0,
+            max_tasks: None,
+            max_tasks_policy: MaxTasksPolicy::default(),
+            comm_filter: None,
+            comm_filtered: 0,
+            capped_events: 0,
+            evicted_tasks: 0,
+        },
+    };
+    for event in &events {
+        collector.process_event(event);
+    }
+
+    println!("Replayed {} events from {}", events.len(), args.csv);
+    print_report(&collector.stats, &report_opts, period);
+    Ok(())
+}
+
+fn validate(path: &str, strict: bool) -> Result<()> {
+    let config = Config::parse_file(path)?;
+    let mut errors = config.validation_errors();
+    if let Some(reason) = config.empty_reason() {
+        if strict {
+            errors.push(format!("{} (--strict)", reason));
+        } else {
+            eprintln!("{}: warning: {}", path, reason);
+        }
+    }
+    if errors.is_empty() {
+        println!("{}: ok", path);
+        return Ok(());
+    }
+
+    for error in &errors {
+        eprintln!("{}: {}", path, error);
+    }
+    bail!("{} has {} validation error(s)", path, errors.len());
+}
+
+fn show_stats(pin_dir: &str) -> Result<()> {
+    let path = format!("{}/{}", pin_dir, sched_stats::STATS_MAP);
+    let map = MapHandle::from_pinned_path(&path).with_context(|| {
+        format!("Failed to open {}, is scx_teddy running?", path)
+    })?;
+    let mut stats = SchedStats::read(&map)?;
+    // Only pinned with --pin-maps
+    if let Ok(frozen) = MapHandle::from_pinned_path(format!("{}/frozen_tids", pin_dir)) {
+        stats = stats.with_frozen(&frozen);
+    }
+    stats.print();
+    Ok(())
+}
+
+// Maps pinned under the pin directory, stats always and the ones `explain`
+// reads with --pin-maps
+fn pinned_maps(all: bool) -> Vec<&'static str> {
+    let mut names = vec![sched_stats::STATS_MAP];
+    if all {
+        names.extend_from_slice(explain::PINNED);
+    }
+    names
+}
+
+fn map_by_name<'a, 'obj>(skel: &'a mut BpfSkel<'obj>, name: &str) -> &'a mut MapMut<'obj> {
+    match name {
+        "target_tids" => &mut skel.maps.target_tids,
+        "target_tids_alt" => &mut skel.maps.target_tids_alt,
+        "target_tgids" => &mut skel.maps.target_tgids,
+        "excluded_tids" => &mut skel.maps.excluded_tids,
+        "frozen_tids" => &mut skel.maps.frozen_tids,
+        "bss" => &mut skel.maps.bss,
+        _ => &mut skel.maps.stats,
+    }
+}
+
+// Pin maps as <dir>/<name> so `scx_teddy stats` and other tools can open them
+// from another process
+fn pin_maps(skel: &mut BpfSkel, dir: &str, names: &[&str]) -> Result<()> {
+    if let Err(e) = std::fs::create_dir_all(dir) {
+        let hint = match e.kind() {
+            std::io::ErrorKind::PermissionDenied => " (permission denied, run as root)",
+            std::io::ErrorKind::NotFound => " (is bpffs mounted, e.g. at /sys/fs/bpf?)",
+            _ => "",
+        };
+        return Err(e).with_context(|| format!("Failed to create pin directory {}{}", dir, hint));
+    }
+    for &name in names {
+        let path = format!("{}/{}", dir, name);
+        // A previous instance that crashed may have left its pin behind
+        let _ = std::fs::remove_file(&path);
+        map_by_name(skel, name)
+            .pin(&path)
+            .with_context(|| format!("Failed to pin the {} map to {}, is {} on bpffs?", name, path, dir))?;
+    }
+    Ok(())
+}
+
+fn unpin_maps(skel: &mut BpfSkel, dir: &str, names: &[&str]) {
+    for &name in names {
+        let _ = map_by_name(skel, name).unpin(format!("{}/{}", dir, name));
+    }
+}
+
+fn main() -> Result<ExitCode> {
+    let cli = Cli::parse();
+
+    let result = match cli.command.unwrap_or(Command::Run(cli.run)) {
+        Command::Run(args) => return run(args),
+        Command::Validate { config, strict } => validate(&config, strict),
+        Command::Stats { pin_dir } => show_stats(&pin_dir),
+        Command::Doctor { color } => {
+            let passed = doctor::run(Palette::new(color));
+            return Ok(if passed { ExitCode::SUCCESS } else { ExitCode::FAILURE });
+        }
+        Command::Explain { tid, pin_dir } => {
+            let passed = explain::explain(tid, &pin_dir)?;
+            return Ok(if passed { ExitCode::SUCCESS } else { ExitCode::FAILURE });
+        }
+        Command::Freeze { tid, pin_dir } => freeze::freeze(tid, &pin_dir),
+        Command::Thaw { tid, pin_dir } => freeze::thaw(tid, &pin_dir),
+        Command::Bench(args) => bench(args),
+        Command::Replay(args) => replay(args),
+    };
+    result.map(|()| ExitCode::SUCCESS)
+}
+
+// Schedule every thread of this process with teddy, then run the workload
+fn bench(args: BenchArgs) -> Result<()> {
+    let workload = bench::Workload {
+        cpu_workers: args
+            .cpu_workers
+            .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get())),
+        sleep_workers: args.sleep_workers,
+        sleep: Duration::from_micros(args.sleep_us),
+        duration: Duration::from_secs(args.duration),
+    };
+    if args.baseline {
+        workload.run().print("the default scheduler");
+        return Ok(());
+    }
+    if args.attach_timeout == 0 {
+        bail!("--attach-timeout must be at least 1 second");
+    }
+    if !args.skip_kernel_check {
+        kernel::check()?;
+    }
+
+    let mut open_object = MaybeUninit::uninit();
+    let load_opts = LoadOptions {
+        ringbuf_pages: None,
+        debug_bpf: false,
+        offcpu_stacks: false,
+        syscall_sleeps: false,
+        debug_decisions: false,
+        nr_target_tids: 0,
+        nr_target_tgids: 1,
+        nr_excluded_tids: 0,
+    };
+    let mut skel = load_skel(&mut open_object, &load_opts)?;
+    // The workers are matched by TGID, apply_mixed_targets gives them the
+    // normal tier as the default
+    apply_mixed_targets(&mut skel, &[], &[std::process::id() as i32], None)?;
+    // Nothing drains the events, measure scheduling alone
+    let key = bpf_intf::CONFIG_STOP_RINGBUF.to_ne_bytes();
+    skel.maps
+        .scheduler_config
+        .update(&key, &1u32.to_ne_bytes(), MapFlags::ANY)
+        .context("Failed to stop the event ring buffer")?;
+
+    let struct_ops = attach(&mut skel, Duration::from_secs(args.attach_timeout))?;
+    let result = workload.run();
+    // After an ejection the workload ran on the default scheduler for the rest
+    if scx_utils::uei_exited!(&skel, uei) {
+        let (_, why) = ejection(&skel);
+        bail!("The kernel ejected the scheduler during the benchmark, the results are not scx_teddy's: {}", why);
+    }
+    drop(struct_ops);
+
+    result.print("scx_teddy");
+    Ok(())
+}
+
+// The --config or --config-dir config with weights normalized and TIDs moved
+// to the host PID namespace as asked, read at startup and on each SIGHUP
+fn read_config(args: &RunArgs, pidns: Option<&PidNamespace>) -> Result<Option<Config>> {
+    let mut config = match (&args.config, &args.config_dir) {
+        (Some(path), _) => Config::load(path)?,
+        (None, Some(dir)) => Config::load_dir(dir)?,
+        (None, None) => return Ok(None),
+    };
+    if args.normalize_weights {
+        config.normalize_weights();
+    }
+    if let Some(pidns) = pidns {
+        for task in config.tasks.iter_mut() {
+            task.tid = pidns.to_host(task.tid)?;
+        }
+        config.tgid = config.tgid.map(|tgid| pidns.to_host(tgid)).transpose()?;
+    }
+    Ok(Some(config))
+}
+
+// The target_tids map of the current config generation, where runtime
+// additions go
+fn active_target_tids<'a, 'obj>(skel: &'a BpfSkel<'obj>) -> &'a MapMut<'obj> {
+    if skel.maps.bss_data.as_ref().unwrap().target_gen & 1 == 0 {
+        &skel.maps.target_tids
+    } else {
+        &skel.maps.target_tids_alt
+    }
+}
+
+// SIGHUP: build the reloaded config's task entries in the inactive target_tids
+// map, then bump target_gen so the BPF side switches to them in one step.
+// Entries not from the config (--tid, --seed-config) carry over. Only `tasks`
+// is reloaded, the other settings are applied at load. `target_maps` is
+// [target_tids, target_tids_alt].
+fn reload_config(
+    target_maps: [&MapMut; 2],
+    bss: &mut bpf_skel::types::bss,
+    args: &RunArgs,
+    pidns: Option<&PidNamespace>,
+    config: &mut Config,
+) -> Result<()> {
+    let path = args.config.as_deref().or(args.config_dir.as_deref()).unwrap_or_default();
+    let new = read_config(args, pidns)?.context("No config to reload")?;
+    let load_time = |config: &Config| {
+        serde_json::to_value((config.target_mode, config.tgid, &config.cgroup, &config.default,
+            &config.dispatch_strategy, &config.idle_preference, config.max_cpus)).ok()
+    };
+    if load_time(config) != load_time(&new) {
+        eprintln!("Warning: {}: only `tasks` is reloaded, restart to apply changes to \
+            target_mode, tgid, cgroup, default, dispatch_strategy, idle_preference or max_cpus", path);
+    }
+
+    let gen = bss.target_gen;
+    let [active, inactive] = if gen & 1 == 0 { target_maps } else { [target_maps[1], target_maps[0]] };
+    // Still holds the generation before the active one
+    for key in inactive.keys().collect::<Vec<_>>() {
+        inactive.delete(&key).context("Failed to clear the inactive target_tids map")?;
+    }
+    let old_tids: HashSet<i32> = config.tasks.iter().map(|task| task.tid).collect();
+    let flag_tids: HashSet<i32> = args.tid.iter().copied().collect();
+    for key in active.keys() {
+        let Ok(tid) = key.as_slice().try_into().map(i32::from_ne_bytes) else {
+            continue;
+        };
+        if old_tids.contains(&tid) && !flag_tids.contains(&tid) {
+            continue;
+        }
+        if let Some(value) = active.lookup(&key, MapFlags::ANY)? {
+            inactive.update(&key, &value, MapFlags::ANY)
+                .with_context(|| format!("Failed to copy TID {} into the inactive target_tids map", tid))?;
+        }
+    }
+    // --tid entries override config ones, as at load
+    for task in new.tasks.iter().filter(|task| !flag_tids.contains(&task.tid)) {
+        insert_target_tid(inactive, task.tid, &policy_ctx(&task.policy, new.base_prio, new.base_slice))?;
+    }
+
+    // A lone config TID was matched without the map; match through the old
+    // generation's map, which lists it too, before switching
+    bss.target_single_tid = 0;
+    bss.target_gen = gen.wrapping_add(1);
+    sched_stats::CONFIG_GENERATION.store(bss.target_gen as u64, Ordering::Relaxed);
+
+    println!("Reloaded {}: {} tasks, config generation {}", path, new.tasks.len(), bss.target_gen);
+    *config = new;
+    Ok(())
+}
+
+fn run(mut args: RunArgs) -> Result<ExitCode> {
+    if args.list_cpus {
+        topology::Topology::detect()?.print();
+        return Ok(ExitCode::SUCCESS);
+    }
+    if args.stats_format == StatsFormat::Json && !args.list_targets {
+        sched_stats::json_stdout()?;
+    }
+
+    println!("scx_teddy scheduler starting...");
+
+    let pidns = args.pid_namespace.map(PidNamespace::open).transpose()?;
+    if let Some(pidns) = &pidns {
+        let to_host = |tids: &[i32]| -> Result<Vec<i32>> { tids.iter().map(|&tid| pidns.to_host(tid)).collect() };
+        args.tid = to_host(&args.tid)?;
+        args.tgid = to_host(&args.tgid)?;
+        args.exclude = to_host(&args.exclude)?;
+    }
+    let mut config = read_config(&args, pidns.as_ref())?;
+    let mut warnings = warnings::Warnings::new(args.strict);
+    if args.list_targets {
+        return list_targets(&args, config.as_ref()).map(|()| ExitCode::SUCCESS);
+    }
+    // A config aimed at the wrong process would otherwise schedule nothing, silently
+    let target_tgids: Vec<i32> = config
+        .as_ref()
+        .and_then(|config| config.tgid)
+        .into_iter()
+        .chain(args.tgid.iter().copied())
+        .collect();
+    for &tgid in &target_tgids {
+        if process_exists(tgid) {
+            continue;
+        }
+        if !args.allow_missing_tgid {
+            bail!("tgid {} does not exist (no /proc/{}); check the config's tgid, \
+                or pass --allow-missing-tgid if the process has not started yet", tgid, tgid);
+        }
+        warnings.warn(format!("tgid {} does not exist yet", tgid));
+    }
+
+    let seed = args.seed_config.as_deref().map(config::SeedConfig::load).transpose()?;
+    // Seed matches are added to target_tids like --tid, so they need MODE_MIXED too
+    let flag_targets = !args.tid.is_empty() || !args.tgid.is_empty() || seed.is_some();
+    let empty_reason = config.as_ref().and_then(Config::empty_reason).filter(|_| !flag_targets);
+    if let (Some(path), Some(reason)) = (args.config.as_ref().or(args.config_dir.as_ref()), empty_reason) {
+        if !args.allow_empty {
+            bail!("{} schedules nothing: {} (pass --allow-empty to run anyway)", path, reason);
+        }
+        warnings.warn(format!("{} schedules nothing: {}", path, reason));
+    }
+
+    let trace_all = args.mode == TraceMode::All;
+    if !trace_all && (args.min_runtime.is_some() || args.sample.is_some()) {
+        bail!("--min-runtime and --sample only apply to --mode all");
+    }
+    if args.sample == Some(0) {
+        bail!("--sample must be at least 1");
+    }
+    if trace_all {
+        eprintln!("Warning: --mode all emits an event for every task on the system, \
+            which costs CPU and may drop events; consider --sample, --min-runtime and --top");
+    } else if config.is_none() && !flag_targets {
+        warnings.warn("no targets given, nothing will be reported (pass --mode all to trace every task)".to_string());
+    }
+
+    let report_opts = args.report.options(args.no_report, pidns.clone())?;
+    let baseline = match &args.baseline {
+        Some(path) if !std::path::Path::new(path).exists() => bail!("Baseline {} does not exist", path),
+        Some(path) => Some(saved_stats::SavedStats::load(path)?),
+        None => None,
+    };
+    if args.report.timeseries == Some(0) {
+        bail!("--timeseries bucket must be at least 1 ms");
+    }
+    if args.window_intervals == 0 {
+        bail!("--window-intervals must be at least 1");
+    }
+    if args.threads == 0 {
+        bail!("--threads must be at least 1");
+    }
+    if args.attach_timeout == 0 {
+        bail!("--attach-timeout must be at least 1 second");
+    }
+    if args.ring_capacity == Some(0) {
+        bail!("--ring-capacity must be at least 1");
+    }
+    if args.rotate_keep == 0 {
+        bail!("--rotate-keep must be at least 1");
+    }
+    if args.rotate_size.is_some_and(|size| size < MIN_ROTATE_SIZE) {
+        bail!("--rotate-size must be at least {} bytes", MIN_ROTATE_SIZE);
+    }
+    let rotation = args.rotate_size.map(|max_bytes| raw_events::Rotation { max_bytes, keep: args.rotate_keep });
+    let comm_filter = args
+        .filter_comm
+        .as_deref()
+        .map(|pattern| regex::Regex::new(pattern).with_context(|| format!("Invalid --filter-comm {:?}", pattern)))
+        .transpose()?;
+    if args.max_duration.is_some_and(|max| max < args.collect_duration) {
+        bail!("--max-duration must be at least --collect-duration ({}s)", args.collect_duration);
+    }
+    if let Some(pages) = args.ringbuf_pages {
+        if !pages.is_power_of_two() {
+            bail!("--ringbuf-pages must be a power of two, got {}", pages);
+        }
+    }
+
+    if !args.skip_kernel_check {
+        kernel::check()?;
+    }
+
+    let mut open_object = MaybeUninit::uninit();
+    let load_opts = LoadOptions {
+        ringbuf_pages: args.ringbuf_pages,
+        debug_bpf: args.debug_bpf,
+        offcpu_stacks: args.offcpu_flamegraph.is_some(),
+        syscall_sleeps: args.syscall_sleeps,
+        debug_decisions: args.debug_decisions,
+        nr_target_tids: config.as_ref().map_or(0, |config| config.tasks.len())
+            + args.tid.len()
+            + if seed.is_some() { SEED_TARGET_TIDS } else { 0 },
+        nr_target_tgids: args.tgid.len() + 1,
+        nr_excluded_tids: args.exclude.len(),
+    };
+    let mut skel = load_skel(&mut open_object, &load_opts)?;
+
+    if let Some(config) = &config {
+        apply_config(&mut skel, config, !args.no_single_opt)?;
+        if let Some(path) = &config.cgroup {
+            let bss = skel.maps.bss_data.as_mut().unwrap();
+            bss.target_cgroup_id = cgroup::resolve(path)?;
+            bss.target_mode = bpf_intf::MODE_CGROUP as i32;
+            // The cgroup's tasks aren't in target_tids
+            apply_normal_default(&mut skel);
+        }
+        if let Some(max_cpus) = config.max_cpus {
+            apply_max_cpus(&mut skel, max_cpus)?;
+        }
+    }
+    if flag_targets {
+        apply_mixed_targets(&mut skel, &args.tid, &args.tgid, config.as_ref())?;
+    }
+    if !args.exclude.is_empty() {
+        let tgid = config.as_ref().and_then(|config| config.tgid);
+        apply_exclusions(&mut skel, &args.exclude, tgid, &mut warnings)?;
+    }
+    if !args.cpu_filter.is_empty() {
+        apply_cpu_filter(&mut skel, &args.cpu_filter)?;
+    }
+    // Without max_cpus targets may use every online CPU
+    if skel.maps.bss_data.as_ref().unwrap().nr_managed_cpus == 0 {
+        if let Ok(topo) = topology::Topology::detect() {
+            skel.maps.bss_data.as_mut().unwrap().nr_managed_cpus = topo.cpus.len() as u32;
+        }
+    }
+    if let Err(e) = apply_ecore_mask(&mut skel) {
+        eprintln!("Warning: not checking on_ecore placement: {:#}", e);
+    }
+
+    if args.start_paused {
+        skel.maps.bss_data.as_mut().unwrap().paused = 1;
+    }
+    if trace_all {
+        let bss = skel.maps.bss_data.as_mut().unwrap();
+        bss.trace_all = 1;
+        bss.trace_min_runtime_ns = args.min_runtime.unwrap_or(0) * 1000;
+        bss.trace_sample = args.sample.unwrap_or(1);
+    }
+    if args.debug_decisions {
+        let bss = skel.maps.bss_data.as_mut().unwrap();
+        bss.debug_decisions = 1;
+        bss.decision_sample = args.decision_sample.max(1);
+        bss.decision_rate = args.decision_rate;
+    }
+    if args.start_paused {
+        install_signal(libc::SIGUSR1, handle_sigusr1_pause);
+    } else {
+        install_signal(libc::SIGUSR1, handle_sigusr1_dump);
+    }
+    if args.ring_capacity.is_some() {
+        install_signal(libc::SIGUSR2, handle_sigusr2);
+    }
+    // A config read from stdin can't be read again
+    let reloadable = config.is_some() && args.config.as_deref() != Some("-");
+    if reloadable {
+        install_signal(libc::SIGHUP, handle_sighup);
+    }
+
+    let pin_dir = args.pin_maps.as_deref().unwrap_or(sched_stats::PIN_DIR);
+    let pinned = pinned_maps(args.pin_maps.is_some());
+    match pin_maps(&mut skel, pin_dir, &pinned) {
+        Ok(()) => {}
+        // Only an explicit --pin-maps is worth failing for
+        Err(e) if args.pin_maps.is_some() => return Err(e),
+        Err(e) => eprintln!("Warning: {:#}", e),
+    }
+
+    // Everything up to here is known before attaching, fail before touching sched_ext
+    if warnings.fatal() {
+        unpin_maps(&mut skel, pin_dir, &pinned);
+        return Ok(ExitCode::from(EXIT_STRICT));
+    }
+    let _struct_ops = attach(&mut skel, Duration::from_secs(args.attach_timeout))?;
+    for &tgid in &target_tgids {
+        if !process_exists(tgid) && !args.allow_missing_tgid {
+            warnings.warn(format!("tgid {} exited before the scheduler attached", tgid));
+        }
+    }
+    if warnings.fatal() {
+        unpin_maps(&mut skel, pin_dir, &pinned);
+        return Ok(ExitCode::from(EXIT_STRICT));
+    }
+    let _sched_switch = match args.offcpu_flamegraph {
+        Some(_) => Some(skel.progs.teddy_sched_switch.attach().context("Failed to attach sched_switch")?),
+        None => None,
+    };
+    let _syscall_links = if args.syscall_sleeps {
+        Some((
+            skel.progs.teddy_sys_enter.attach().context("Failed to attach sys_enter")?,
+            skel.progs.teddy_sys_exit.attach().context("Failed to attach sys_exit")?,
+        ))
+    } else {
+        None
+    };
+
+    let run_start = Instant::now();
+    let mut hist_export = args.hist_out.is_some().then(hist_export::HistExport::default);
+    let mut summary_export = args.summary_out.is_some().then(|| summary::SummaryExport::new(report_opts.variance));
+    let self_stats_enabled = args.self_stats;
+    let mut self_stats = SelfStats::default();
+
+    let mut collector = Collector {
+        stats: std::collections::HashMap::new(),
+        trace: args.trace_out.is_some().then(Trace::default),
+        offcpu: args.offcpu_flamegraph.is_some().then(OffCpu::default),
+        seen_tids: args.summary_line.then(std::collections::HashSet::new),
+        raw: args.raw_out.as_deref().map(|path| RawWriter::create(path, rotation)).transpose()?,
+        socket: args.socket.as_deref().map(|path| socket::EventSocket::bind(path, args.socket_buffer)).transpose()?,
+        recorder: args
+            .ring_capacity
+            .map(|capacity| recorder::FlightRecorder::new(capacity, args.ring_dump_sleep.map(|ms| ms * 1_000_000))),
+        affinity: args.track_affinity.then(affinity::AffinityTracker::new),
+        shards: (args.threads > 1).then(|| {
+            let opts = shards::ShardOptions {
+                top_sleeps: args.report.top_sleeps,
+                bucket_ns: args.report.timeseries.map(|ms| ms * 1_000_000),
+            };
+            shards::ShardPool::new(args.threads, opts)
+        }),
+        opts: CollectOptions {
+            top_sleeps: args.report.top_sleeps,
+            bucket_ns: args.report.timeseries.map(|ms| ms * 1_000_000),
+            trace_start: 0,
+            warmup_end: (args.warmup > 0).then(|| Instant::now() + Duration::from_secs(args.warmup)),
+            warmup_discarded: 0,
+            max_tasks: args.max_tasks,
+            max_tasks_policy: args.max_tasks_policy,
+            comm_filter,
+            comm_filtered: 0,
+            capped_events: 0,
+            evicted_tasks: 0,
+        },
+    };
+
+    // Callbacks run inside poll() on this thread; they only copy events out,
+    // the main loop applies them after each poll
+    let pending: Rc<RefCell<Vec<TaskEvent>>> = Rc::default();
+    let pending_clone = Rc::clone(&pending);
+    let mut batch = Vec::new();
+
+    // Seed matches never replace the config's or the flags' own TIDs
+    let mut seeder = seed.map(|seed| {
+        let fixed = config.iter().flat_map(|config| config.tasks.iter().map(|task| task.tid)).chain(args.tid.iter().copied());
+        seed::Seeder::new(seed, fixed.collect())
+    });
 
     let mut builder = libbpf_rs::RingBufferBuilder::new();
     builder
-        .add(&skel.maps.events, move |data| process_event(data, &stats_clone))
+        .add(&skel.maps.events, move |data| {
+            if let Ok(event) = plain::from_bytes::<TaskEvent>(data) {
+                pending_clone.borrow_mut().push(*event);
+                EVENTS_RECEIVED.fetch_add(1, Ordering::Relaxed);
+            }
+            0
+        })
         .context("Failed to add ringbuf")?;
+    if args.debug_decisions {
+        builder
+            .add(&skel.maps.decisions, decisions::print_decision)
+            .context("Failed to add decisions ringbuf")?;
+    }
     let ringbuf = builder.build().context("Failed to build ringbuf")?;
 
     let scheduler_config = &skel.maps.scheduler_config;
 
     println!("scx_teddy scheduler loaded successfully!");
+    println!("Idle preference: {}", config.as_ref().map_or("none", |config| config.idle_preference().0));
+    if args.start_paused {
+        println!("Started paused, send SIGUSR1 (kill -USR1 {}) to activate", std::process::id());
+    }
+    if args.warmup > 0 {
+        println!("Warming up, discarding events for the first {}s", args.warmup);
+    }
     println!("Press Ctrl+C to exit...\n");
 
     // Setup Ctrl+C handler
@@ -220,30 +1929,235 @@ fn main() -> Result<()> {
         *running_clone.lock().unwrap() = false;
     })
     .expect("Error setting Ctrl+C handler");
+    install_signal(libc::SIGTERM, handle_sigterm);
 
-    let mut start_time = Instant::now();
+    // The first period starts after the warmup, elapsed() saturates to 0 until then
+    let mut start_time = Instant::now() + Duration::from_secs(args.warmup);
+    // When the current stats started accumulating, --dump-reset restarts it
+    let mut stats_start = start_time;
     let duration = Duration::from_secs(args.collect_duration);
+    let max_duration = args.max_duration.map(Duration::from_secs);
+    // The current period ran past --collect-duration waiting for events
+    let mut extended = false;
+    let mut stats_time = Instant::now();
+    let mut window = window::Window::new(args.window_mode, args.window_intervals);
+    let stats_interval = Duration::from_secs(args.stats_interval);
 
-    // Main loop - keep scheduler running
-    while *running.lock().unwrap() {
-        if start_time.elapsed() >= duration {
+    // Main loop - keep scheduler running. Short polls keep Ctrl+C responsive,
+    // stats and reports only fire when their interval boundary is crossed.
+    while *running.lock().unwrap() && !TERMINATE.load(Ordering::Relaxed) && !scx_utils::uei_exited!(&skel, uei) {
+        if TOGGLE_PAUSE.swap(false, Ordering::Relaxed) {
+            let bss = skel.maps.bss_data.as_mut().unwrap();
+            bss.paused ^= 1;
+            println!("Scheduler {}", if bss.paused != 0 { "paused" } else { "active" });
+        }
+        if RELOAD_CONFIG.swap(false, Ordering::Relaxed) {
+            if let Some(config) = &mut config {
+                let target_maps = [&skel.maps.target_tids, &skel.maps.target_tids_alt];
+                let bss = skel.maps.bss_data.as_mut().unwrap();
+                match reload_config(target_maps, bss, &args, pidns.as_ref(), config) {
+                    Ok(()) => sched_stats::RELOADS.fetch_add(1, Ordering::Relaxed),
+                    Err(e) => {
+                        warnings.warn(format!("config reload failed, keeping the running config: {:#}", e));
+                        sched_stats::RELOAD_FAILURES.fetch_add(1, Ordering::Relaxed)
+                    }
+                };
+            }
+        }
+        if let Some(seeder) = &mut seeder {
+            apply_seed_scan(active_target_tids(&skel), seeder);
+        }
+        if DUMP_RING.swap(false, Ordering::Relaxed) {
+            if let Some(recorder) = &mut collector.recorder {
+                recorder.dump("SIGUSR2");
+            }
+        }
+        if DUMP_STATS.swap(false, Ordering::Relaxed) {
+            println!("\n--- SIGUSR1 snapshot ---");
+            collector.sync();
+            print_period_report(&collector.stats, &report_opts, stats_start.elapsed(), &skel, args.stats_format)?;
+            if args.dump_reset {
+                if let Some(hist_export) = &mut hist_export {
+                    hist_export.add(&collector.stats);
+                }
+                if let Some(summary_export) = &mut summary_export {
+                    summary_export.add(&collector.stats);
+                }
+                collector.clear_stats();
+                stats_start = Instant::now();
+            }
+        }
+        if args.stats_format == StatsFormat::Json && stats_time.elapsed() >= stats_interval {
+            SchedStats::read(&skel.maps.stats)?.with_map_ops().with_frozen(&skel.maps.frozen_tids).print_json()?;
+            stats_time = Instant::now();
+        } else if args.verbose && stats_time.elapsed() >= stats_interval {
+            collector.sync();
+            let events: u64 = collector.stats.values().map(|task_stats| task_stats.event_count).sum();
+            println!("[{}] Scheduler running... {} tasks, {} events this period, {} received in total",
+                timestamp(), collector.stats.len(), events, EVENTS_RECEIVED.load(Ordering::Relaxed));
+            stats_time = Instant::now();
+        }
+        let elapsed = start_time.elapsed();
+        let period_end = if elapsed < duration {
+            None
+        } else if let Some(min_events) = args.min_total_events {
+            collector.sync();
+            let events: u64 = collector.stats.values().map(|task_stats| task_stats.event_count).sum();
+            if events >= min_events {
+                Some(if extended { "on event count" } else { "on time" })
+            } else if max_duration.is_some_and(|max| elapsed >= max) {
+                Some("on the --max-duration cap")
+            } else {
+                if !extended {
+                    println!("Only {} of {} events after {}s, extending the period", events, min_events, duration.as_secs());
+                    extended = true;
+                }
+                None
+            }
+        } else {
+            Some("on time")
+        };
+        if let Some(reason) = period_end {
+            if args.min_total_events.is_some() {
+                println!("Collection period ended {} after {:.1}s", reason, elapsed.as_secs_f64());
+            }
             let key = 0u32.to_ne_bytes();
             let mut val = 1u32.to_ne_bytes();
             scheduler_config.update(&key, &val, MapFlags::ANY)?;
-            let mut stats_map = stats.lock().unwrap();
-            for (&tid, task_stats) in stats_map.iter() {
-                println!("TID: {}, Event cnt: {}, parent: {}, runtime: {}, exit: {}", 
-                tid, task_stats.event_count, task_stats.parent, task_stats.runtime_sum, task_stats.exit);
+            collector.sync();
+            match window.push(&collector.stats, stats_start.elapsed()) {
+                Some(view) => {
+                    println!("--window-mode {}: {} interval(s), {:.1}s",
+                        if args.window_mode == WindowMode::Sliding { "sliding" } else { "cumulative" },
+                        view.intervals, view.period.as_secs_f64());
+                    print_period_report(&view.stats, &report_opts, view.period, &skel, args.stats_format)?;
+                }
+                None => print_period_report(&collector.stats, &report_opts, stats_start.elapsed(), &skel, args.stats_format)?,
+            }
+            if let (Some(baseline), Some(path)) = (&baseline, &args.baseline) {
+                println!("\n--- Compared to {} ---", path);
+                diff::print_diff(baseline, &collector.stats, args.baseline_match, &report_opts.palette, report_opts.precision);
+            }
+            if let Some(path) = &args.merge {
+                merge_into_file(path, &collector.stats)?;
+            }
+            if let Some(hist_export) = &mut hist_export {
+                hist_export.add(&collector.stats);
+            }
+            if let Some(summary_export) = &mut summary_export {
+                summary_export.add(&collector.stats);
             }
-            stats_map.clear();
+            collector.clear_stats();
             start_time = Instant::now();
+            stats_start = start_time;
+            extended = false;
             val = 0u32.to_ne_bytes();
             scheduler_config.update(&key, &val, MapFlags::ANY)?;
         }
-        ringbuf.poll(Duration::from_millis(1000))?;
+        let poll_start = Instant::now();
+        ringbuf.poll(POLL_TIMEOUT)?;
+        let poll_time = poll_start.elapsed();
+        let drain_start = Instant::now();
+        let events = collector.drain(&pending, &mut batch);
+        if self_stats_enabled {
+            self_stats.record_poll(poll_time, events);
+            self_stats.record_batch(drain_start.elapsed(), events);
+        }
+    }
+
+    if TERMINATE.load(Ordering::Relaxed) {
+        println!("\nReceived SIGTERM, shutting down...");
+    }
+    let ejected = scx_utils::uei_exited!(&skel, uei).then(|| ejection(&skel));
+    if let Some((_, why)) = &ejected {
+        eprintln!("Error: the kernel ejected the scheduler: {}", why);
     }
 
+    // Whatever is still in the ring buffer belongs in the final output
+    ringbuf.consume()?;
+    collector.drain(&pending, &mut batch);
+    collector.sync();
+    if let Some(shards) = collector.shards.take() {
+        shards.join();
+    }
+    // The event streams first, so a later output failing can't cut a capture short
+    if let (Some(raw), Some(path)) = (collector.raw.take(), &args.raw_out) {
+        raw.finish()?;
+        println!("Events written to {}", path);
+    }
+    if let (Some(socket), Some(path)) = (collector.socket.take(), &args.socket) {
+        println!("Streamed {} events to {}, {} dropped", socket.sent, path, socket.dropped);
+        socket.finish();
+    }
+
+    if self_stats_enabled {
+        self_stats.print();
+    }
+    if let Some(seeder) = &seeder {
+        let pending = seeder.pending();
+        if !pending.is_empty() {
+            warnings.warn(format!("--seed-config: never resolved: {}", pending.join(", ")));
+        }
+    }
+    if collector.opts.comm_filtered > 0 {
+        println!("--filter-comm: dropped {} events of non-matching tasks", collector.opts.comm_filtered);
+    }
+    if collector.opts.capped_events > 0 {
+        println!("--max-tasks: dropped {} events of untracked TIDs", collector.opts.capped_events);
+    }
+    if collector.opts.evicted_tasks > 0 {
+        println!("--max-tasks: evicted {} least active tasks", collector.opts.evicted_tasks);
+    }
+
+    if let Some(path) = &args.merge {
+        merge_into_file(path, &collector.stats)?;
+    }
+
+    if let (Some(hist_export), Some(path)) = (&mut hist_export, &args.hist_out) {
+        hist_export.add(&collector.stats);
+        hist_export.write(path)?;
+        println!("Histograms written to {}", path);
+    }
+    if let (Some(summary_export), Some(path)) = (&mut summary_export, &args.summary_out) {
+        summary_export.add(&collector.stats);
+        summary_export.write(path)?;
+        println!("Summary written to {}", path);
+    }
+    if let (Some(trace), Some(path)) = (&mut collector.trace, &args.trace_out) {
+        trace.write(path)?;
+        println!("Trace written to {}", path);
+    }
+    if let (Some(offcpu), Some(path)) = (&collector.offcpu, &args.offcpu_flamegraph) {
+        offcpu.write(path, &skel.maps.stack_traces)?;
+        println!("Off-CPU stacks written to {}", path);
+    }
+
+    unpin_maps(&mut skel, pin_dir, &pinned);
+
     println!("scx_teddy scheduler exiting...");
 
-    Ok(())
+    let received = EVENTS_RECEIVED.load(Ordering::Relaxed);
+    let dropped = SchedStats::read(&skel.maps.stats)?.nr_dropped;
+    if let Some(seen_tids) = &collector.seen_tids {
+        // Stable format for wrapper scripts, keep in sync with the README
+        println!("SUMMARY tasks={} events={} dropped={} duration_ms={}",
+            seen_tids.len(), received, dropped, run_start.elapsed().as_millis());
+    }
+    let drop_rate = dropped as f64 / (received + dropped).max(1) as f64;
+    if drop_rate > HIGH_DROP_RATE {
+        warnings.warn(format!("{:.1}% of events ({} of {}) were dropped on a full ring buffer, \
+            the report is incomplete; try a larger --ringbuf-pages", drop_rate * 100.0, dropped, received + dropped));
+    }
+
+    if let Some((kind, _)) = ejected {
+        return Ok(ExitCode::from(if kind == SCX_EXIT_ERROR_STALL { EXIT_WATCHDOG } else { EXIT_EJECTED }));
+    }
+    if EVENTS_RECEIVED.load(Ordering::Relaxed) == 0 && !args.allow_empty {
+        eprintln!("Error: no events were collected, check the targets (pass --allow-empty to exit 0 anyway)");
+        return Ok(ExitCode::from(EXIT_NO_EVENTS));
+    }
+    if warnings.fatal() {
+        return Ok(ExitCode::from(EXIT_STRICT));
+    }
+    Ok(ExitCode::SUCCESS)
 }
@@ -0,0 +1,93 @@
+// SPDX-License-Identifier: GPL-2.0
+//! Off-CPU time by kernel stack, written in the folded format flamegraph.pl reads
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+
+use anyhow::{Context, Result};
+use libbpf_rs::{MapCore, MapFlags};
+
+use crate::bpf_intf;
+use crate::TaskEvent;
+
+const KALLSYMS: &str = "/proc/kallsyms";
+
+// Kernel symbols sorted by address, addresses are only visible to root
+struct KernelSymbols {
+    syms: Vec<(u64, String)>,
+}
+
+impl KernelSymbols {
+    fn load() -> Result<Self> {
+        let data = std::fs::read_to_string(KALLSYMS).with_context(|| format!("Failed to read {}", KALLSYMS))?;
+        let mut syms: Vec<(u64, String)> = data
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split_whitespace();
+                let addr = u64::from_str_radix(fields.next()?, 16).ok()?;
+                let name = fields.nth(1)?;
+                Some((addr, name.to_string()))
+            })
+            .collect();
+        syms.sort_unstable_by_key(|(addr, _)| *addr);
+        Ok(Self { syms })
+    }
+
+    fn resolve(&self, ip: u64) -> &str {
+        match self.syms.partition_point(|(addr, _)| *addr <= ip) {
+            0 => "[unknown]",
+            i => &self.syms[i - 1].1,
+        }
+    }
+}
+
+/// Sleep time summed per (comm, kernel stack id) until shutdown
+#[derive(Debug, Default)]
+pub struct OffCpu {
+    by_stack: HashMap<(String, i32), u64>,
+}
+
+impl OffCpu {
+    pub fn record(&mut self, event: &TaskEvent) {
+        let sleep_ns = event.sleep_end.saturating_sub(event.sleep_start);
+        if event.stack_id < 0 || event.sleep_start == 0 || sleep_ns == 0 {
+            return;
+        }
+        *self.by_stack.entry((event.comm().into_owned(), event.stack_id)).or_default() += sleep_ns;
+    }
+
+    /// Write one `comm;outermost;...;innermost microseconds` line per stack
+    pub fn write(&self, path: &str, stack_traces: &dyn MapCore) -> Result<()> {
+        let syms = KernelSymbols::load()?;
+        let mut out = String::new();
+
+        for ((comm, stack_id), &sleep_ns) in self.by_stack.iter() {
+            let key = (*stack_id as u32).to_ne_bytes();
+            let frames = stack_traces
+                .lookup(&key, MapFlags::ANY)
+                .with_context(|| format!("Failed to read stack {}", stack_id))?;
+
+            out.push_str(comm);
+            match frames {
+                Some(bytes) => {
+                    // Innermost frame first, zero-terminated when shorter than MAX_STACK_DEPTH
+                    let ips: Vec<u64> = bytes
+                        .chunks_exact(8)
+                        .take(bpf_intf::MAX_STACK_DEPTH as usize)
+                        .map(|ip| u64::from_ne_bytes(ip.try_into().unwrap()))
+                        .take_while(|&ip| ip != 0)
+                        .collect();
+                    for &ip in ips.iter().rev() {
+                        out.push(';');
+                        out.push_str(syms.resolve(ip));
+                    }
+                }
+                // Evicted from a full stack map
+                None => out.push_str(";[lost stack]"),
+            }
+            let _ = writeln!(out, " {}", sleep_ns / 1000);
+        }
+
+        std::fs::write(path, out).with_context(|| format!("Failed to write {}", path))
+    }
+}
@@ -0,0 +1,75 @@
+// SPDX-License-Identifier: GPL-2.0
+//! TID translation between a container's PID namespace and the host, from
+//! the NSpid lines in /proc. Only works from the host PID namespace, where
+//! every container task is visible.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+
+#[derive(Debug, Clone)]
+pub struct PidNamespace {
+    // readlink of /proc/<pid>/ns/pid, e.g. "pid:[4026532281]"
+    ns: PathBuf,
+    to_host: HashMap<i32, i32>,
+}
+
+fn pid_ns(pid: i32) -> Option<PathBuf> {
+    fs::read_link(format!("/proc/{}/ns/pid", pid)).ok()
+}
+
+// Innermost namespace ID of a task, the last NSpid field
+fn local_id(pid: i32) -> Option<i32> {
+    let status = fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+    let line = status.lines().find(|line| line.starts_with("NSpid:"))?;
+    line.split_whitespace().last()?.parse().ok()
+}
+
+impl PidNamespace {
+    /// Index every task in the PID namespace of host PID `pid`, usually the container's init
+    pub fn open(pid: i32) -> Result<Self> {
+        let ns = pid_ns(pid).with_context(|| format!("Failed to read the PID namespace of {}", pid))?;
+        let mut to_host = HashMap::new();
+
+        for proc_entry in fs::read_dir("/proc").context("Failed to read /proc")? {
+            let Some(tgid) = proc_entry?.file_name().to_str().and_then(|n| n.parse::<i32>().ok()) else {
+                continue;
+            };
+            if pid_ns(tgid).as_ref() != Some(&ns) {
+                continue;
+            }
+            // Processes can exit while we walk them
+            let Ok(tasks) = fs::read_dir(format!("/proc/{}/task", tgid)) else {
+                continue;
+            };
+            for task in tasks.flatten() {
+                let Some(tid) = task.file_name().to_str().and_then(|n| n.parse::<i32>().ok()) else {
+                    continue;
+                };
+                if let Some(local) = local_id(tid) {
+                    to_host.insert(local, tid);
+                }
+            }
+        }
+
+        Ok(Self { ns, to_host })
+    }
+
+    /// Host TID of a namespace-local TID, as seen when the namespace was indexed
+    pub fn to_host(&self, local: i32) -> Result<i32> {
+        match self.to_host.get(&local) {
+            Some(&host) => Ok(host),
+            None => bail!("TID {} does not exist in PID namespace {}", local, self.ns.display()),
+        }
+    }
+
+    /// Namespace-local TID of a live host TID, None if it exited or lives elsewhere
+    pub fn to_local(&self, host: i32) -> Option<i32> {
+        if pid_ns(host).as_ref() != Some(&self.ns) {
+            return None;
+        }
+        local_id(host)
+    }
+}
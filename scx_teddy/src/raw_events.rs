@@ -0,0 +1,260 @@
+// SPDX-License-Identifier: GPL-2.0
+//! Raw task events as CSV, written by --raw-out and read back by `replay` to
+//! rebuild the report offline
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+
+use crate::TaskEvent;
+
+// One column per TaskEvent field, in struct order. comm comes last so names
+// containing commas need no quoting.
+const COLUMNS: &[&str] = &[
+    "tid", "parent", "tgid", "timestamp", "sleep_start", "sleep_end", "runtime_ns", "wait_ns",
+    "sleep_d_ns", "sleep_s_ns", "wakeup_latency_ns", "throttled", "nr_preempted", "nr_voluntary",
+    "cpu", "stack_id", "waker_tid", "nice", "sleep_syscall", "comm",
+];
+
+// Buffered events reach the file at least this often, bounding what a crash loses
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// When the active file is rotated, see --rotate-size
+#[derive(Debug, Clone, Copy)]
+pub struct Rotation {
+    pub max_bytes: u64,
+    // Rotated files kept as <path>.1 (newest) to <path>.<keep>
+    pub keep: u32,
+}
+
+/// Appends every processed event to a CSV file. Write errors are kept until
+/// finish() so event processing stays infallible. Dropped without finish(),
+/// on a panic or an early error return, BufWriter still flushes what is
+/// buffered, only the error is lost.
+pub struct RawWriter {
+    path: String,
+    out: BufWriter<File>,
+    // Bytes in the active file, header included
+    written: u64,
+    rotation: Option<Rotation>,
+    last_flush: Instant,
+    error: Option<std::io::Error>,
+}
+
+/// Column names as the first line of a file, newline included
+pub fn header() -> String {
+    format!("{}\n", COLUMNS.join(","))
+}
+
+/// One event as a CSV line, newline included
+pub fn format_record(e: &TaskEvent) -> String {
+    // A newline in comm would split the record
+    let comm = e.comm().replace('\n', " ");
+    format!("{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}\n",
+        e.tid, e.parent, e.tgid, e.timestamp, e.sleep_start, e.sleep_end, e.runtime_ns, e.wait_ns,
+        e.sleep_d_ns, e.sleep_s_ns, e.wakeup_latency_ns, e.throttled, e.nr_preempted, e.nr_voluntary,
+        e.cpu, e.stack_id, e.waker_tid, e.nice, e.sleep_syscall, comm)
+}
+
+// A new active file starting with the header, so every rotated file replays on its own
+fn open_with_header(path: &str) -> std::io::Result<(BufWriter<File>, u64)> {
+    let mut out = BufWriter::new(File::create(path)?);
+    let header = header();
+    out.write_all(header.as_bytes())?;
+    Ok((out, header.len() as u64))
+}
+
+impl RawWriter {
+    pub fn create(path: &str, rotation: Option<Rotation>) -> Result<Self> {
+        let (out, written) = open_with_header(path).with_context(|| format!("Failed to create {}", path))?;
+        Ok(Self {
+            path: path.to_string(),
+            out,
+            written,
+            rotation,
+            last_flush: Instant::now(),
+            error: None,
+        })
+    }
+
+    // Shift <path>.N up by one, dropping the oldest, and start a fresh <path>.
+    // The active file is flushed before it is renamed and never written again
+    // afterwards, so no event lands in a file after it moved.
+    fn rotate(&mut self, keep: u32) -> std::io::Result<()> {
+        self.out.flush()?;
+        self.out.get_ref().sync_data()?;
+        let rotated = |n: u32| format!("{}.{}", self.path, n);
+        let _ = std::fs::remove_file(rotated(keep));
+        for n in (1..keep).rev() {
+            match std::fs::rename(rotated(n), rotated(n + 1)) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        std::fs::rename(&self.path, rotated(1))?;
+        (self.out, self.written) = open_with_header(&self.path)?;
+        Ok(())
+    }
+
+    pub fn write(&mut self, e: &TaskEvent) {
+        if self.error.is_some() {
+            return;
+        }
+        let line = format_record(e);
+
+        let mut result = Ok(());
+        if let Some(rotation) = self.rotation {
+            if self.written + line.len() as u64 > rotation.max_bytes {
+                result = self.rotate(rotation.keep);
+            }
+        }
+        let result = result.and_then(|()| self.out.write_all(line.as_bytes()));
+        match result {
+            Ok(()) => self.written += line.len() as u64,
+            Err(err) => self.error = Some(err),
+        }
+    }
+
+    /// Flush once FLUSH_INTERVAL passed since the last flush, called from the
+    /// main loop so quiet periods do not leave events in the buffer
+    pub fn flush_if_due(&mut self) {
+        if self.error.is_some() || self.last_flush.elapsed() < FLUSH_INTERVAL {
+            return;
+        }
+        if let Err(err) = self.out.flush() {
+            self.error = Some(err);
+        }
+        self.last_flush = Instant::now();
+    }
+
+    pub fn finish(mut self) -> Result<()> {
+        if let Some(err) = self.error.take() {
+            return Err(err).with_context(|| format!("Failed to write events to {}", self.path));
+        }
+        self.out.flush().with_context(|| format!("Failed to write events to {}", self.path))
+    }
+}
+
+fn num<T: std::str::FromStr>(fields: &[&str], i: usize) -> Result<T> {
+    match fields[i].parse() {
+        Ok(value) => Ok(value),
+        Err(_) => bail!("bad {} {:?}", COLUMNS[i], fields[i]),
+    }
+}
+
+fn parse_record(line: &str) -> Result<TaskEvent> {
+    let fields: Vec<&str> = line.splitn(COLUMNS.len(), ',').collect();
+    if fields.len() != COLUMNS.len() {
+        bail!("expected {} columns, found {}", COLUMNS.len(), fields.len());
+    }
+
+    let mut comm = [0u8; 16];
+    // Keep the NUL terminator, as the kernel does
+    let name = fields[19].as_bytes();
+    let len = name.len().min(comm.len() - 1);
+    comm[..len].copy_from_slice(&name[..len]);
+
+    Ok(TaskEvent {
+        tid: num(&fields, 0)?,
+        parent: num(&fields, 1)?,
+        tgid: num(&fields, 2)?,
+        timestamp: num(&fields, 3)?,
+        sleep_start: num(&fields, 4)?,
+        sleep_end: num(&fields, 5)?,
+        runtime_ns: num(&fields, 6)?,
+        wait_ns: num(&fields, 7)?,
+        sleep_d_ns: num(&fields, 8)?,
+        sleep_s_ns: num(&fields, 9)?,
+        wakeup_latency_ns: num(&fields, 10)?,
+        throttled: num(&fields, 11)?,
+        nr_preempted: num(&fields, 12)?,
+        nr_voluntary: num(&fields, 13)?,
+        cpu: num(&fields, 14)?,
+        stack_id: num(&fields, 15)?,
+        waker_tid: num(&fields, 16)?,
+        nice: num(&fields, 17)?,
+        sleep_syscall: num(&fields, 18)?,
+        comm,
+    })
+}
+
+/// Read events written by RawWriter, failing on a header that does not match
+/// this version's columns
+pub fn read(path: &str) -> Result<Vec<TaskEvent>> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path))?;
+    let mut lines = BufReader::new(file).lines();
+
+    let header = lines.next().transpose()?.unwrap_or_default();
+    let expected = COLUMNS.join(",");
+    if header.trim_end() != expected {
+        bail!("{}: unexpected header {:?}, expected {:?} as written by --raw-out", path, header, expected);
+    }
+
+    let mut events = Vec::new();
+    for (i, line) in lines.enumerate() {
+        let line = line.with_context(|| format!("Failed to read {}", path))?;
+        if line.is_empty() {
+            continue;
+        }
+        // +2: 1-based, after the header
+        let event = parse_record(line.trim_end_matches('\r')).with_context(|| format!("{}:{}", path, i + 2))?;
+        events.push(event);
+    }
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> String {
+        format!("{}/scx_teddy-{}-{}.csv", std::env::temp_dir().display(), std::process::id(), name)
+    }
+
+    #[test]
+    fn replay_reads_back_what_raw_out_wrote() {
+        let path = temp_path("roundtrip");
+        let events = [TaskEvent::sample(100, "worker"), TaskEvent::sample(101, "a,b,c"), TaskEvent::sample(102, "")];
+        let mut raw = RawWriter::create(&path, None).unwrap();
+        for e in &events {
+            raw.write(e);
+        }
+        raw.finish().unwrap();
+
+        let read = read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(read.len(), events.len());
+        for (written, read) in events.iter().zip(&read) {
+            assert_eq!(format_record(written), format_record(read));
+        }
+    }
+
+    #[test]
+    fn replay_rejects_another_header() {
+        let path = temp_path("header");
+        std::fs::write(&path, "tid,comm\n1,x\n").unwrap();
+        let result = read(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn dropping_without_finish_keeps_every_event() {
+        let path = temp_path("drop");
+        // More than BufWriter holds, so some events are still buffered at the drop
+        let events: Vec<TaskEvent> = (0..1000).map(|tid| TaskEvent::sample(tid, "worker")).collect();
+        let mut raw = RawWriter::create(&path, None).unwrap();
+        for e in &events {
+            raw.write(e);
+        }
+        // As on a panic or an early error return before finish()
+        drop(raw);
+
+        let read = read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(read.len(), events.len());
+        assert_eq!(format_record(&read[999]), format_record(&events[999]));
+    }
+}
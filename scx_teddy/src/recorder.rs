@@ -0,0 +1,49 @@
+// SPDX-License-Identifier: GPL-2.0
+//! Flight recorder of the most recent events, dumped on SIGUSR2 or when a
+//! sleep crosses --ring-dump-sleep, for raw context around an anomaly
+
+use std::collections::VecDeque;
+use std::io::Write;
+
+use crate::raw_events;
+use crate::TaskEvent;
+
+pub struct FlightRecorder {
+    events: VecDeque<TaskEvent>,
+    capacity: usize,
+    // Dump as soon as an event ends a sleep this long, None for SIGUSR2 only
+    anomaly_sleep_ns: Option<u64>,
+}
+
+impl FlightRecorder {
+    pub fn new(capacity: usize, anomaly_sleep_ns: Option<u64>) -> Self {
+        Self { events: VecDeque::with_capacity(capacity), capacity, anomaly_sleep_ns }
+    }
+
+    /// Keep the event, evicting the oldest when full, and dump if it ended an
+    /// anomalous sleep
+    pub fn record(&mut self, event: &TaskEvent) {
+        if self.events.len() == self.capacity {
+            self.events.pop_front();
+        }
+        self.events.push_back(*event);
+
+        let sleep_ns = event.sleep_end.saturating_sub(event.sleep_start);
+        if self.anomaly_sleep_ns.is_some_and(|threshold| event.sleep_start != 0 && sleep_ns >= threshold) {
+            self.dump(&format!("TID {} ({}) slept {:.3} ms", event.tid, event.comm(), sleep_ns as f64 / 1e6));
+        }
+    }
+
+    /// Print the recorded events oldest first in the --raw-out CSV format, so
+    /// they can be saved and replayed, then start over so the next dump only
+    /// holds newer events
+    pub fn dump(&mut self, reason: &str) {
+        let mut out = std::io::stdout().lock();
+        let _ = writeln!(out, "\n--- Last {} events: {} ---", self.events.len(), reason);
+        let _ = out.write_all(raw_events::header().as_bytes());
+        for event in self.events.drain(..) {
+            let _ = out.write_all(raw_events::format_record(&event).as_bytes());
+        }
+        let _ = writeln!(out, "--- End of events ---");
+    }
+}
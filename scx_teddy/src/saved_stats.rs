@@ -0,0 +1,105 @@
+// SPDX-License-Identifier: GPL-2.0
+//! Versioned on-disk format of the stats files written by --merge
+//!
+//! Bumping FORMAT_VERSION: keep the old layout readable by converting it in
+//! `migrate()`, one match arm per old version, each producing the JSON of the
+//! next version, so that files written by any earlier release still merge.
+
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::stats::TaskStats;
+
+/// Version written by this build. Version 0 is the bare TID map saved before
+/// stats files were versioned.
+pub const FORMAT_VERSION: u64 = 1;
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SavedStats {
+    #[serde(rename = "format_version")]
+    pub version: u64,
+    /// Unix time in seconds of the last write
+    pub generated_at: u64,
+    /// FNV-1a of the canonical JSON of `tasks`, as hex
+    pub checksum: String,
+    pub tasks: HashMap<i32, TaskStats>,
+}
+
+// 64-bit FNV-1a, enough to catch truncated or hand-edited files
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+// Going through Value sorts every object by key, so HashMap order doesn't matter
+fn checksum(tasks: &HashMap<i32, TaskStats>) -> Result<String> {
+    let canonical = serde_json::to_string(&serde_json::to_value(tasks)?)?;
+    Ok(format!("{:016x}", fnv1a(canonical.as_bytes())))
+}
+
+// Bring the JSON of an older format up to FORMAT_VERSION
+fn migrate(version: u64, value: Value) -> Result<Value> {
+    match version {
+        0 => Ok(serde_json::json!({
+            "format_version": 1,
+            "generated_at": 0,
+            "checksum": "",
+            "tasks": value,
+        })),
+        FORMAT_VERSION => Ok(value),
+        _ => bail!("unknown format_version {}, this scx_teddy reads versions up to {}", version, FORMAT_VERSION),
+    }
+}
+
+impl SavedStats {
+    pub fn new(tasks: HashMap<i32, TaskStats>) -> Result<Self> {
+        let generated_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        Ok(Self {
+            version: FORMAT_VERSION,
+            generated_at,
+            checksum: checksum(&tasks)?,
+            tasks,
+        })
+    }
+
+    /// Read a stats file, rejecting unknown versions and checksum mismatches.
+    /// A missing file is an empty set of tasks.
+    pub fn load(path: &str) -> Result<HashMap<i32, TaskStats>> {
+        let data = match std::fs::read_to_string(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(HashMap::new()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read stats file {}", path)),
+        };
+        let value: Value = serde_json::from_str(&data)
+            .with_context(|| format!("Failed to parse stats file {}", path))?;
+
+        let version = match value.get("format_version") {
+            Some(version) => version
+                .as_u64()
+                .with_context(|| format!("Stats file {} has a non-integer format_version", path))?,
+            None => 0,
+        };
+        let value = migrate(version, value).with_context(|| format!("Cannot load stats file {}", path))?;
+        let saved: SavedStats = serde_json::from_value(value)
+            .with_context(|| format!("Failed to parse stats file {}", path))?;
+
+        // Migrated files predate checksums
+        if version == FORMAT_VERSION && saved.checksum != checksum(&saved.tasks)? {
+            bail!("Stats file {} (format_version {}, written at unix time {}) is corrupted: checksum mismatch",
+                path, saved.version, saved.generated_at);
+        }
+        Ok(saved.tasks)
+    }
+
+    pub fn save(self, path: &str) -> Result<()> {
+        let data = serde_json::to_string_pretty(&self).context("Failed to serialize stats")?;
+        std::fs::write(path, data).with_context(|| format!("Failed to write stats file {}", path))
+    }
+}
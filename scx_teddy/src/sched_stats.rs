@@ -0,0 +1,238 @@
+// SPDX-License-Identifier: GPL-2.0
+//! Scheduler-wide counters from the BPF stats map, shared by `run` and the `stats` subcommand
+
+use std::fs::File;
+use std::io::Write;
+use std::os::fd::FromRawFd;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+use anyhow::{anyhow, Context, Result};
+use libbpf_rs::{MapCore, MapFlags};
+use serde::Serialize;
+
+use crate::bpf_intf;
+
+/// bpffs directory the running scheduler pins its maps under unless
+/// --pin-maps names another; each map is pinned as `<dir>/<map name>`
+pub const PIN_DIR: &str = "/sys/fs/bpf/scx_teddy";
+pub const STATS_MAP: &str = "stats";
+
+/// How the counters are printed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum StatsFormat {
+    #[default]
+    Text,
+    /// One JSON object per line, for jq and log shippers
+    Json,
+}
+
+// The original stdout once json_stdout() moved the text output to stderr
+static JSON_OUT: OnceLock<Mutex<File>> = OnceLock::new();
+
+/// With --stats-format json, keep stdout for the JSON lines alone so it can be
+/// piped into jq: the report and every other line printed to stdout goes to
+/// stderr from now on, print_json() writes to the original stdout
+pub fn json_stdout() -> Result<()> {
+    std::io::stdout().flush().context("Failed to flush stdout")?;
+    // SAFETY: dup and dup2 only duplicate descriptors, the new one is owned below
+    let fd = unsafe { libc::dup(libc::STDOUT_FILENO) };
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error()).context("Failed to duplicate stdout");
+    }
+    // SAFETY: fd is a fresh descriptor nothing else owns
+    let out = unsafe { File::from_raw_fd(fd) };
+    if unsafe { libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) } < 0 {
+        return Err(std::io::Error::last_os_error()).context("Failed to point stdout at stderr");
+    }
+    let _ = JSON_OUT.set(Mutex::new(out));
+    Ok(())
+}
+
+// Map operations done by this scheduler process. They are cumulative over
+// its whole lifetime, config reloads do not reset them.
+pub static TARGET_UPDATES_OK: AtomicU64 = AtomicU64::new(0);
+pub static TARGET_UPDATES_FAILED: AtomicU64 = AtomicU64::new(0);
+pub static RELOADS: AtomicU64 = AtomicU64::new(0);
+pub static RELOAD_FAILURES: AtomicU64 = AtomicU64::new(0);
+// target_gen in bss, bumped by each reload that swapped target_tids
+pub static CONFIG_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+// Above this average load factor the targets are queuing for CPUs
+const HIGH_LOAD_FACTOR: f64 = 1.0;
+
+/// Health of config application, only known inside the scheduler process
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MapOps {
+    pub nr_target_updates: u64,
+    pub nr_target_update_failures: u64,
+    pub nr_reloads: u64,
+    pub nr_reload_failures: u64,
+    pub config_generation: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SchedStats {
+    pub nr_other: u64,
+    pub nr_enqueued: u64,
+    pub nr_wakeup: u64,
+    pub nr_direct: u64,
+    pub nr_sync: u64,
+    pub nr_throttled: u64,
+    pub nr_ecore_honored: u64,
+    pub nr_ecore_violated: u64,
+    pub nr_dropped: u64,
+    pub nr_preempt_suppressed: u64,
+    // Runnable targets per managed CPU, sampled by the BPF side every
+    // LOAD_SAMPLE_NS while any CPU ticks; 0 before the first sample
+    pub nr_load_samples: u64,
+    pub load_factor_avg: f64,
+    pub load_factor_peak: f64,
+    #[serde(skip)]
+    load_sum: u64,
+    // Idle CPU searches of woken targets under an idle_preference
+    pub nr_idle_pref_found: u64,
+    pub nr_idle_pref_missed: u64,
+    // None when read by the `stats` subcommand from another process
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub map_ops: Option<MapOps>,
+    // TIDs held by `scx_teddy freeze`, empty unless frozen_tids was read
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub frozen: Vec<i32>,
+}
+
+impl SchedStats {
+    /// Sum the per-CPU copies of the stats map
+    pub fn read(map: &dyn MapCore) -> Result<Self> {
+        let key = 0u32.to_ne_bytes();
+        let percpu = map
+            .lookup_percpu(&key, MapFlags::ANY)
+            .context("Failed to read stats map")?
+            .unwrap_or_default();
+
+        let mut stats = Self::default();
+        for bytes in percpu {
+            // SAFETY: teddy_stats_t is a plain C struct of u64 counters
+            let mut raw: bpf_intf::teddy_stats_t = unsafe { std::mem::zeroed() };
+            plain::copy_from_bytes(&mut raw, &bytes)
+                .map_err(|e| anyhow!("Unexpected stats map value: {:?}", e))?;
+            stats.add(&raw);
+        }
+        if stats.nr_load_samples > 0 {
+            stats.load_factor_avg = stats.load_sum as f64 / stats.nr_load_samples as f64 / bpf_intf::LOAD_SCALE as f64;
+        }
+
+        Ok(stats)
+    }
+
+    /// Attach this process's map operation counters
+    pub fn with_map_ops(mut self) -> Self {
+        self.map_ops = Some(MapOps {
+            nr_target_updates: TARGET_UPDATES_OK.load(Ordering::Relaxed),
+            nr_target_update_failures: TARGET_UPDATES_FAILED.load(Ordering::Relaxed),
+            nr_reloads: RELOADS.load(Ordering::Relaxed),
+            nr_reload_failures: RELOAD_FAILURES.load(Ordering::Relaxed),
+            config_generation: CONFIG_GENERATION.load(Ordering::Relaxed),
+        });
+        self
+    }
+
+    /// Attach the frozen TIDs listed in a frozen_tids map
+    pub fn with_frozen(mut self, map: &dyn MapCore) -> Self {
+        self.frozen = crate::freeze::frozen(map);
+        self
+    }
+
+    fn add(&mut self, raw: &bpf_intf::teddy_stats_t) {
+        self.nr_other += raw.nr_other;
+        self.nr_enqueued += raw.nr_enqueued;
+        self.nr_wakeup += raw.nr_wakeup;
+        self.nr_direct += raw.nr_direct;
+        self.nr_sync += raw.nr_sync;
+        self.nr_throttled += raw.nr_throttled;
+        self.nr_ecore_honored += raw.nr_ecore_honored;
+        self.nr_ecore_violated += raw.nr_ecore_violated;
+        self.nr_dropped += raw.nr_dropped;
+        self.nr_preempt_suppressed += raw.nr_preempt_suppressed;
+        self.nr_load_samples += raw.nr_load_samples;
+        self.load_sum += raw.load_sum;
+        self.load_factor_peak = self.load_factor_peak.max(raw.load_peak as f64 / bpf_intf::LOAD_SCALE as f64);
+        self.nr_idle_pref_found += raw.nr_idle_pref_found;
+        self.nr_idle_pref_missed += raw.nr_idle_pref_missed;
+    }
+
+    pub fn print_as(&self, format: StatsFormat) -> Result<()> {
+        match format {
+            StatsFormat::Text => self.print(),
+            StatsFormat::Json => self.print_json()?,
+        }
+        Ok(())
+    }
+
+    /// One line holding the counters and the Unix time they were read at
+    pub fn print_json(&self) -> Result<()> {
+        #[derive(Serialize)]
+        struct Sample<'a> {
+            timestamp: f64,
+            #[serde(flatten)]
+            stats: &'a SchedStats,
+        }
+
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0.0, |elapsed| elapsed.as_secs_f64());
+        let line = serde_json::to_string(&Sample { timestamp, stats: self })
+            .context("Failed to serialize stats")?;
+        match JSON_OUT.get() {
+            Some(out) => writeln!(out.lock().unwrap(), "{}", line).context("Failed to write stats")?,
+            None => println!("{}", line),
+        }
+        Ok(())
+    }
+
+    pub fn print(&self) {
+        println!("Scheduler: enqueued {} (wakeup {}), direct {}, sync {}, throttled {}, other {}",
+            self.nr_enqueued, self.nr_wakeup, self.nr_direct, self.nr_sync,
+            self.nr_throttled, self.nr_other);
+        if self.nr_dropped > 0 {
+            println!("Events dropped on a full ring buffer: {} (try a larger --ringbuf-pages)", self.nr_dropped);
+        }
+
+        if self.nr_preempt_suppressed > 0 {
+            println!("Preemptions suppressed by preempt_latency_ns: {}", self.nr_preempt_suppressed);
+        }
+        if self.nr_load_samples > 0 {
+            println!("Load factor: avg {:.2}, peak {:.2} (runnable targets per managed CPU, {} samples)",
+                self.load_factor_avg, self.load_factor_peak, self.nr_load_samples);
+            if self.load_factor_avg > HIGH_LOAD_FACTOR {
+                eprintln!("Warning: load factor averages {:.2}, targets are queuing for CPUs; \
+                    consider shorter slices, fewer targets or more CPUs (max_cpus, affinities)", self.load_factor_avg);
+            }
+        }
+        let idle_searches = self.nr_idle_pref_found + self.nr_idle_pref_missed;
+        if idle_searches > 0 {
+            println!("Preferred idle CPU found: {:.1}% ({} of {} searches)",
+                self.nr_idle_pref_found as f64 * 100.0 / idle_searches as f64,
+                self.nr_idle_pref_found, idle_searches);
+        }
+        if !self.frozen.is_empty() {
+            let frozen: Vec<String> = self.frozen.iter().map(|tid| tid.to_string()).collect();
+            println!("Frozen TIDs: {}", frozen.join(", "));
+        }
+        if let Some(map_ops) = &self.map_ops {
+            println!("target_tids updates: {} ok, {} failed; config reloads: {} ok, {} failed (generation {})",
+                map_ops.nr_target_updates, map_ops.nr_target_update_failures, map_ops.nr_reloads,
+                map_ops.nr_reload_failures, map_ops.config_generation);
+        }
+
+        // Only counted on hybrid machines, for targets with on_ecore set
+        let ecore_runs = self.nr_ecore_honored + self.nr_ecore_violated;
+        if ecore_runs > 0 {
+            println!("e-core honor rate: {:.1}% ({} of {} runs)",
+                self.nr_ecore_honored as f64 * 100.0 / ecore_runs as f64,
+                self.nr_ecore_honored, ecore_runs);
+        }
+    }
+}
+
+unsafe impl plain::Plain for bpf_intf::teddy_stats_t {}
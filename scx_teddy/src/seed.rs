@@ -0,0 +1,110 @@
+// SPDX-License-Identifier: GPL-2.0
+//! Lazy resolution of --seed-config entries: /proc is scanned periodically
+//! and threads whose comm matches an entry become targets once they appear
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::time::{Duration, Instant};
+
+use crate::config::{SeedConfig, SeedTask};
+
+// How often /proc is scanned for new matching threads
+const SCAN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Result of one scan
+#[derive(Debug, Default)]
+pub struct Scan {
+    // New matching threads, with the index of their entry
+    pub resolved: Vec<(i32, usize)>,
+    // Previously resolved threads that exited, or were renamed away
+    pub gone: Vec<i32>,
+}
+
+pub struct Seeder {
+    pub seed: SeedConfig,
+    // Resolved TIDs and the entry each matched
+    resolved: HashMap<i32, usize>,
+    // Targets set by the config or the flags, never touched here
+    fixed: HashSet<i32>,
+    // Entries that matched at least once; the rest are still pending
+    matched: HashSet<usize>,
+    last_scan: Option<Instant>,
+}
+
+// Every thread on the system with its comm
+fn threads() -> Vec<(i32, String)> {
+    let Ok(procs) = fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    let mut threads = Vec::new();
+    for tgid in procs.flatten().filter_map(|proc| proc.file_name().to_str().and_then(|n| n.parse::<i32>().ok())) {
+        let Ok(tasks) = fs::read_dir(format!("/proc/{}/task", tgid)) else {
+            continue;
+        };
+        for tid in tasks.flatten().filter_map(|task| task.file_name().to_str().and_then(|n| n.parse::<i32>().ok())) {
+            // The thread may exit between the listing and the read
+            if let Ok(comm) = fs::read_to_string(format!("/proc/{}/task/{}/comm", tgid, tid)) {
+                threads.push((tid, comm.trim_end().to_string()));
+            }
+        }
+    }
+    threads
+}
+
+impl Seeder {
+    pub fn new(seed: SeedConfig, fixed: HashSet<i32>) -> Self {
+        Self {
+            seed,
+            resolved: HashMap::new(),
+            fixed,
+            matched: HashSet::new(),
+            last_scan: None,
+        }
+    }
+
+    pub fn entry(&self, index: usize) -> &SeedTask {
+        &self.seed.tasks[index]
+    }
+
+    /// Scan /proc once SCAN_INTERVAL passed since the last scan, the first
+    /// call scans right away
+    pub fn scan_if_due(&mut self) -> Option<Scan> {
+        if self.last_scan.is_some_and(|last| last.elapsed() < SCAN_INTERVAL) {
+            return None;
+        }
+        self.last_scan = Some(Instant::now());
+
+        let mut scan = Scan::default();
+        let mut live = HashSet::new();
+        for (tid, comm) in threads() {
+            if self.fixed.contains(&tid) {
+                continue;
+            }
+            let Some(index) = self.seed.tasks.iter().position(|task| task.comm == comm) else {
+                continue;
+            };
+            live.insert(tid);
+            // A reused TID may now match a different entry
+            if self.resolved.insert(tid, index) != Some(index) {
+                self.matched.insert(index);
+                scan.resolved.push((tid, index));
+            }
+        }
+        self.resolved.retain(|tid, _| {
+            let keep = live.contains(tid);
+            if !keep {
+                scan.gone.push(*tid);
+            }
+            keep
+        });
+        Some(scan)
+    }
+
+    /// Entries no thread matched so far
+    pub fn pending(&self) -> Vec<&str> {
+        (0..self.seed.tasks.len())
+            .filter(|index| !self.matched.contains(index))
+            .map(|index| self.seed.tasks[index].comm.as_str())
+            .collect()
+    }
+}
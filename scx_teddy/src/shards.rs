@@ -0,0 +1,135 @@
+// SPDX-License-Identifier: GPL-2.0
+//! --threads: TaskStats updates split by TID across worker threads. The main
+//! thread still polls the ring buffer; each batch is partitioned so every
+//! task's events stay on one worker, in order.
+
+use std::collections::HashMap;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::JoinHandle;
+
+use crate::stats::TaskStats;
+use crate::TaskEvent;
+
+enum Msg {
+    // Events of this shard's TIDs, with the collection's trace_start
+    Events(Vec<TaskEvent>, u64),
+    // An affinity mask read by --track-affinity: TID, timestamp, CPUs
+    Affinity(i32, u64, Vec<usize>),
+    // Send back a copy of the shard's stats
+    Snapshot(Sender<HashMap<i32, TaskStats>>),
+    Clear,
+}
+
+// How a worker starts a new task, as Collector::process_event does
+#[derive(Debug, Clone, Copy)]
+pub struct ShardOptions {
+    pub top_sleeps: usize,
+    pub bucket_ns: Option<u64>,
+}
+
+pub struct ShardPool {
+    senders: Vec<Sender<Msg>>,
+    workers: Vec<JoinHandle<()>>,
+    // Events waiting for the next flush, one Vec per shard
+    queued: Vec<Vec<TaskEvent>>,
+}
+
+fn worker(inbox: Receiver<Msg>, opts: ShardOptions) {
+    let mut stats: HashMap<i32, TaskStats> = HashMap::new();
+    for msg in inbox {
+        match msg {
+            Msg::Events(events, trace_start) => {
+                for event in &events {
+                    if event.parent > 0 {
+                        stats
+                            .entry(event.tid)
+                            .or_insert_with(|| crate::new_task_stats(event.parent, opts.top_sleeps, opts.bucket_ns, trace_start))
+                            .update(event);
+                    } else if let Some(task_stats) = stats.get_mut(&event.tid) {
+                        task_stats.exit = 1;
+                        task_stats.last_seen = task_stats.last_seen.max(event.timestamp);
+                    }
+                }
+            }
+            Msg::Affinity(tid, timestamp, cpus) => {
+                if let Some(task_stats) = stats.get_mut(&tid) {
+                    task_stats.record_affinity(timestamp, cpus);
+                }
+            }
+            Msg::Snapshot(reply) => {
+                let _ = reply.send(stats.clone());
+            }
+            Msg::Clear => stats.clear(),
+        }
+    }
+}
+
+impl ShardPool {
+    pub fn new(threads: usize, opts: ShardOptions) -> Self {
+        let (senders, workers) = (0..threads)
+            .map(|_| {
+                let (sender, inbox) = mpsc::channel();
+                (sender, std::thread::spawn(move || worker(inbox, opts)))
+            })
+            .unzip();
+        Self { senders, workers, queued: vec![Vec::new(); threads] }
+    }
+
+    /// Queue an event for the shard owning its TID
+    pub fn push(&mut self, event: &TaskEvent) {
+        let shard = event.tid.unsigned_abs() as usize % self.queued.len();
+        self.queued[shard].push(*event);
+    }
+
+    /// Hand the queued events to the workers, once per drained batch
+    pub fn flush(&mut self, trace_start: u64) {
+        for (sender, queued) in self.senders.iter().zip(self.queued.iter_mut()) {
+            if !queued.is_empty() {
+                let _ = sender.send(Msg::Events(std::mem::take(queued), trace_start));
+            }
+        }
+    }
+
+    /// Hand an affinity mask to the shard owning `tid`, after its flushed events
+    pub fn record_affinity(&self, tid: i32, timestamp: u64, cpus: Vec<usize>) {
+        let shard = tid.unsigned_abs() as usize % self.senders.len();
+        let _ = self.senders[shard].send(Msg::Affinity(tid, timestamp, cpus));
+    }
+
+    /// Every shard's stats merged into one map, after all flushed events
+    /// were applied. TIDs never span shards, so this is a union built with
+    /// TaskStats::merge.
+    pub fn snapshot(&self) -> HashMap<i32, TaskStats> {
+        let replies: Vec<Receiver<HashMap<i32, TaskStats>>> = self
+            .senders
+            .iter()
+            .map(|sender| {
+                let (reply, receiver) = mpsc::channel();
+                let _ = sender.send(Msg::Snapshot(reply));
+                receiver
+            })
+            .collect();
+
+        let mut merged: HashMap<i32, TaskStats> = HashMap::new();
+        for shard in replies.into_iter().filter_map(|receiver| receiver.recv().ok()) {
+            for (tid, task_stats) in shard {
+                merged.entry(tid).or_insert_with(|| TaskStats::new(task_stats.parent)).merge(&task_stats);
+            }
+        }
+        merged
+    }
+
+    pub fn clear(&self) {
+        for sender in &self.senders {
+            let _ = sender.send(Msg::Clear);
+        }
+    }
+
+    /// Stop the workers once they applied everything sent to them
+    pub fn join(self) {
+        drop(self.senders);
+        for worker in self.workers {
+            let _ = worker.join();
+        }
+    }
+}
@@ -0,0 +1,213 @@
+// SPDX-License-Identifier: GPL-2.0
+//! --socket: every collected event streamed to the consumers of a Unix domain
+//! socket as JSON lines, for live monitoring without polling files
+
+use std::collections::VecDeque;
+use std::io::{ErrorKind, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+
+use anyhow::{bail, Context, Result};
+
+use crate::TaskEvent;
+
+// Bytes a slow consumer may fall behind by before its events are dropped
+const PENDING_MAX: usize = 1 << 20;
+
+struct Consumer {
+    stream: UnixStream,
+    // Lines not accepted by the socket yet, written out on the next flush
+    pending: Vec<u8>,
+}
+
+/// Accepts consumers and hands each one every event. Never blocks event
+/// processing: writes are non-blocking and what a consumer can't take is
+/// queued up to PENDING_MAX, then dropped and counted.
+pub struct EventSocket {
+    path: String,
+    listener: UnixListener,
+    consumers: Vec<Consumer>,
+    // Events kept while no consumer is connected, for the first one to connect
+    backlog: VecDeque<String>,
+    backlog_capacity: usize,
+    pub sent: u64,
+    pub dropped: u64,
+}
+
+/// One event as a JSON object on its own line, with the --raw-out column names
+fn format_line(e: &TaskEvent) -> String {
+    let value = serde_json::json!({
+        "tid": e.tid,
+        "parent": e.parent,
+        "tgid": e.tgid,
+        "timestamp": e.timestamp,
+        "sleep_start": e.sleep_start,
+        "sleep_end": e.sleep_end,
+        "runtime_ns": e.runtime_ns,
+        "wait_ns": e.wait_ns,
+        "sleep_d_ns": e.sleep_d_ns,
+        "sleep_s_ns": e.sleep_s_ns,
+        "wakeup_latency_ns": e.wakeup_latency_ns,
+        "throttled": e.throttled,
+        "nr_preempted": e.nr_preempted,
+        "nr_voluntary": e.nr_voluntary,
+        "cpu": e.cpu,
+        "stack_id": e.stack_id,
+        "waker_tid": e.waker_tid,
+        "nice": e.nice,
+        "sleep_syscall": e.sleep_syscall,
+        "comm": e.comm(),
+    });
+    // Serializing a Value can't fail
+    format!("{}\n", serde_json::to_string(&value).unwrap_or_default())
+}
+
+// Write as much of `pending` as the socket takes without blocking, false once
+// the consumer went away
+fn flush_pending(consumer: &mut Consumer) -> bool {
+    while !consumer.pending.is_empty() {
+        match consumer.stream.write(&consumer.pending) {
+            Ok(0) => return false,
+            Ok(n) => {
+                consumer.pending.drain(..n);
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => return true,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            // EPIPE and ECONNRESET when the consumer closed its end
+            Err(_) => return false,
+        }
+    }
+    true
+}
+
+impl EventSocket {
+    /// Listen on `path`, replacing a stale socket file a previous run left
+    /// behind but never another kind of file or a socket still served
+    pub fn bind(path: &str, backlog_capacity: usize) -> Result<Self> {
+        if let Ok(meta) = std::fs::symlink_metadata(path) {
+            if !meta.file_type().is_socket() {
+                bail!("{} exists and is not a socket", path);
+            }
+            if UnixStream::connect(path).is_ok() {
+                bail!("{} is in use by another process", path);
+            }
+            std::fs::remove_file(path).with_context(|| format!("Failed to remove stale socket {}", path))?;
+        }
+        let listener = UnixListener::bind(path).with_context(|| format!("Failed to listen on {}", path))?;
+        listener.set_nonblocking(true).with_context(|| format!("Failed to set up {}", path))?;
+        Ok(Self {
+            path: path.to_string(),
+            listener,
+            consumers: Vec::new(),
+            backlog: VecDeque::with_capacity(backlog_capacity),
+            backlog_capacity,
+            sent: 0,
+            dropped: 0,
+        })
+    }
+
+    pub fn write(&mut self, e: &TaskEvent) {
+        let line = format_line(e);
+        if self.consumers.is_empty() {
+            if self.backlog_capacity == 0 {
+                self.dropped += 1;
+                return;
+            }
+            // Keep the newest events, a consumer cares most about what just happened
+            if self.backlog.len() == self.backlog_capacity {
+                self.backlog.pop_front();
+                self.dropped += 1;
+            }
+            self.backlog.push_back(line);
+            return;
+        }
+        for consumer in &mut self.consumers {
+            if consumer.pending.len() + line.len() > PENDING_MAX {
+                self.dropped += 1;
+                continue;
+            }
+            consumer.pending.extend_from_slice(line.as_bytes());
+            self.sent += 1;
+        }
+    }
+
+    /// Accept new consumers and push queued lines out, once per drained batch
+    pub fn poll(&mut self) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    if stream.set_nonblocking(true).is_err() {
+                        continue;
+                    }
+                    let mut consumer = Consumer { stream, pending: Vec::new() };
+                    if self.consumers.is_empty() {
+                        self.sent += self.backlog.len() as u64;
+                        for line in self.backlog.drain(..) {
+                            consumer.pending.extend_from_slice(line.as_bytes());
+                        }
+                    }
+                    self.consumers.push(consumer);
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(_) => break,
+            }
+        }
+
+        let before = self.consumers.len();
+        self.consumers.retain_mut(flush_pending);
+        if self.consumers.len() < before {
+            println!("{} consumer(s) of {} disconnected", before - self.consumers.len(), self.path);
+        }
+    }
+
+    /// Give the consumers a last chance to take what is queued, then remove
+    /// the socket file
+    pub fn finish(mut self) {
+        self.poll();
+        for consumer in &mut self.consumers {
+            if consumer.stream.set_nonblocking(false).is_ok()
+                && consumer.stream.set_write_timeout(Some(std::time::Duration::from_millis(500))).is_ok()
+            {
+                let _ = consumer.stream.write_all(&consumer.pending);
+                consumer.pending.clear();
+            }
+        }
+    }
+}
+
+// Also reached on a panic or an early error return, which skip finish()
+impl Drop for EventSocket {
+    fn drop(&mut self) {
+        for consumer in &mut self.consumers {
+            flush_pending(consumer);
+        }
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    #[test]
+    fn dropping_without_finish_flushes_and_removes_the_socket() {
+        let path = format!("{}/scx_teddy-{}-drop.sock", std::env::temp_dir().display(), std::process::id());
+        let mut socket = EventSocket::bind(&path, 0).unwrap();
+        let mut consumer = UnixStream::connect(&path).unwrap();
+        socket.poll();
+
+        let events = [TaskEvent::sample(100, "worker"), TaskEvent::sample(101, "io")];
+        for e in &events {
+            socket.write(e);
+        }
+        // As on a panic or an early error return before finish()
+        drop(socket);
+
+        let mut received = String::new();
+        consumer.read_to_string(&mut received).unwrap();
+        assert_eq!(received, events.iter().map(format_line).collect::<String>());
+        assert!(!std::path::Path::new(&path).exists());
+    }
+}
@@ -0,0 +1,1522 @@
+// SPDX-License-Identifier: GPL-2.0
+//! Per-task statistics accumulated from ring buffer events, and the report printed from them
+
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+use std::time::Duration;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::color::Palette;
+use crate::pidns::PidNamespace;
+use crate::saved_stats::SavedStats;
+use crate::syscalls;
+use crate::topology::{self, NumaNodes};
+use crate::TaskEvent;
+
+// Wakers listed per task in the report
+const TOP_WAKERS: usize = 3;
+const TOP_SYSCALLS: usize = 3;
+
+// Nice changes kept per task, later ones are only counted
+const MAX_NICE_CHANGES: usize = 16;
+// Same for affinity changes
+const MAX_AFFINITY_CHANGES: usize = 16;
+
+// Rates over shorter periods are flagged as noisy
+const MIN_RATE_PERIOD: Duration = Duration::from_secs(1);
+
+// Duty cycle histogram bins, 10% wide
+pub const DUTY_BINS: usize = 10;
+
+// Report highlighting thresholds
+const HIGH_CV: f64 = 1.0;
+const HIGH_PREEMPTED_PCT: f64 = 50.0;
+const HIGH_RUNNING_PCT: f64 = 90.0;
+const HIGH_CTXSW_PER_S: f64 = 1000.0;
+
+// Gaps this long usually mean dropped events or unclassified off-CPU time
+pub const SUSPICIOUS_GAP_NS: u64 = 100_000_000;
+
+// Runtime and sleep-interval accumulators for the CPU a task ran on before sleeping
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct CpuStats {
+    pub events: u64,
+    pub runtime_sum: u64,
+    pub sleep_interval_sum: u64,
+    pub sleep_interval_sum_sq: f64,
+    pub sleep_interval_min: u64,
+    pub sleep_interval_max: u64,
+    pub sleep_interval_count: u64,
+}
+
+impl Default for CpuStats {
+    fn default() -> Self {
+        Self {
+            events: 0,
+            runtime_sum: 0,
+            sleep_interval_sum: 0,
+            sleep_interval_sum_sq: 0.0,
+            sleep_interval_min: u64::MAX,
+            sleep_interval_max: 0,
+            sleep_interval_count: 0,
+        }
+    }
+}
+
+impl CpuStats {
+    fn record_interval(&mut self, interval: u64) {
+        self.sleep_interval_count += 1;
+        self.sleep_interval_sum += interval;
+        self.sleep_interval_sum_sq += (interval as f64) * (interval as f64);
+        self.sleep_interval_min = self.sleep_interval_min.min(interval);
+        self.sleep_interval_max = self.sleep_interval_max.max(interval);
+    }
+
+    pub fn merge(&mut self, other: &CpuStats) {
+        self.events += other.events;
+        self.runtime_sum += other.runtime_sum;
+        self.sleep_interval_sum += other.sleep_interval_sum;
+        self.sleep_interval_sum_sq += other.sleep_interval_sum_sq;
+        self.sleep_interval_min = self.sleep_interval_min.min(other.sleep_interval_min);
+        self.sleep_interval_max = self.sleep_interval_max.max(other.sleep_interval_max);
+        self.sleep_interval_count += other.sleep_interval_count;
+    }
+
+    fn sleep_interval_avg(&self) -> f64 {
+        mean(self.sleep_interval_sum, self.sleep_interval_count)
+    }
+}
+
+// The longest individual sleeps seen, bounded to `capacity` entries
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct TopSleeps {
+    pub capacity: usize,
+    // Min-heap of (duration, sleep_start) so the shortest kept sleep is evicted first
+    pub heap: BinaryHeap<Reverse<(u64, u64)>>,
+}
+
+impl TopSleeps {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            heap: BinaryHeap::with_capacity(capacity),
+        }
+    }
+
+    fn record(&mut self, duration: u64, sleep_start: u64) {
+        if self.heap.len() < self.capacity {
+            self.heap.push(Reverse((duration, sleep_start)));
+        } else if let Some(&Reverse((shortest, _))) = self.heap.peek() {
+            if duration > shortest {
+                self.heap.pop();
+                self.heap.push(Reverse((duration, sleep_start)));
+            }
+        }
+    }
+
+    fn merge(&mut self, other: &TopSleeps) {
+        self.capacity = self.capacity.max(other.capacity);
+        for &Reverse((duration, sleep_start)) in other.heap.iter() {
+            self.record(duration, sleep_start);
+        }
+    }
+
+    // (duration, sleep_start) pairs, longest first
+    fn sorted(&self) -> Vec<(u64, u64)> {
+        let mut sleeps: Vec<(u64, u64)> = self.heap.iter().map(|&Reverse(entry)| entry).collect();
+        sleeps.sort_unstable_by(|a, b| b.cmp(a));
+        sleeps
+    }
+}
+
+// Bounds per-task time series memory, an hour of 1s buckets
+pub const MAX_BUCKETS: usize = 3600;
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Bucket {
+    pub events: u64,
+    pub runtime_sum: u64,
+    pub sleep_sum: u64,
+}
+
+// Runtime and sleep binned into wall-clock buckets counted from `origin_ns`
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct TimeSeries {
+    pub bucket_ns: u64,
+    pub origin_ns: u64,
+    // Index of buckets[0] counted from origin_ns
+    pub first: u64,
+    pub buckets: Vec<Bucket>,
+}
+
+impl TimeSeries {
+    pub fn new(bucket_ns: u64, origin_ns: u64) -> Self {
+        Self {
+            bucket_ns,
+            origin_ns,
+            first: 0,
+            buckets: Vec::new(),
+        }
+    }
+
+    // Bucket `index`, or None once the series would grow past MAX_BUCKETS
+    fn bucket_mut(&mut self, index: u64) -> Option<&mut Bucket> {
+        if self.buckets.is_empty() {
+            self.first = index;
+        } else if index < self.first {
+            let grow = (self.first - index) as usize;
+            if self.buckets.len() + grow > MAX_BUCKETS {
+                return None;
+            }
+            self.buckets.splice(0..0, std::iter::repeat_with(Bucket::default).take(grow));
+            self.first = index;
+        }
+
+        let offset = (index - self.first) as usize;
+        if offset >= MAX_BUCKETS {
+            return None;
+        }
+        if offset >= self.buckets.len() {
+            self.buckets.resize_with(offset + 1, Bucket::default);
+        }
+        Some(&mut self.buckets[offset])
+    }
+
+    fn record(&mut self, timestamp: u64, runtime_ns: u64, sleep_ns: u64) {
+        let index = timestamp.saturating_sub(self.origin_ns) / self.bucket_ns;
+        if let Some(bucket) = self.bucket_mut(index) {
+            bucket.events += 1;
+            bucket.runtime_sum += runtime_ns;
+            bucket.sleep_sum += sleep_ns;
+        }
+    }
+
+    // Series from another run have a different origin and can't be lined up
+    fn merge(&mut self, other: &TimeSeries) {
+        if self.bucket_ns != other.bucket_ns || self.origin_ns != other.origin_ns {
+            return;
+        }
+        for (i, other_bucket) in other.buckets.iter().enumerate() {
+            if let Some(bucket) = self.bucket_mut(other.first + i as u64) {
+                bucket.events += other_bucket.events;
+                bucket.runtime_sum += other_bucket.runtime_sum;
+                bucket.sleep_sum += other_bucket.sleep_sum;
+            }
+        }
+    }
+}
+
+// Linear sub-buckets per power of two of the histograms, as bits.
+// 16 sub-buckets keep percentiles within about 3% of the exact value.
+const HIST_SUB_BITS: u32 = 4;
+const HIST_SUB_BUCKETS: u64 = 1 << HIST_SUB_BITS;
+
+// Log-linear histogram of durations in ns, sparse so idle tasks stay small
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Histogram {
+    pub count: u64,
+    pub buckets: BTreeMap<u32, u64>,
+}
+
+impl Histogram {
+    // Values below HIST_SUB_BUCKETS get a bucket each, larger values
+    // HIST_SUB_BUCKETS buckets per power of two
+    fn bucket(ns: u64) -> u32 {
+        if ns < HIST_SUB_BUCKETS {
+            return ns as u32;
+        }
+        let shift = 63 - ns.leading_zeros() - HIST_SUB_BITS;
+        ((shift + 1) << HIST_SUB_BITS) + ((ns >> shift) - HIST_SUB_BUCKETS) as u32
+    }
+
+    /// Values falling into `bucket`, as [low, high)
+    pub fn bucket_bounds(bucket: u32) -> (u64, u64) {
+        let bucket = bucket as u64;
+        if bucket < HIST_SUB_BUCKETS {
+            return (bucket, bucket + 1);
+        }
+        let shift = (bucket >> HIST_SUB_BITS) - 1;
+        let low = (HIST_SUB_BUCKETS + (bucket & (HIST_SUB_BUCKETS - 1))) << shift;
+        (low, low + (1 << shift))
+    }
+
+    // Middle of the range of values falling into `bucket`
+    fn bucket_value(bucket: u32) -> u64 {
+        let (low, high) = Self::bucket_bounds(bucket);
+        low + (high - low) / 2
+    }
+
+    fn record(&mut self, ns: u64) {
+        self.count += 1;
+        *self.buckets.entry(Self::bucket(ns)).or_default() += 1;
+    }
+
+    pub fn merge(&mut self, other: &Histogram) {
+        self.count += other.count;
+        for (&bucket, &count) in other.buckets.iter() {
+            *self.buckets.entry(bucket).or_default() += count;
+        }
+    }
+
+    /// Latency at or below which `p` percent of the samples fall, None without samples
+    pub fn percentile(&self, p: f64) -> Option<u64> {
+        if self.count == 0 {
+            return None;
+        }
+        let rank = ((p / 100.0 * self.count as f64).ceil() as u64).clamp(1, self.count);
+        let mut seen = 0;
+        for (&bucket, &count) in self.buckets.iter() {
+            seen += count;
+            if seen >= rank {
+                return Some(Self::bucket_value(bucket));
+            }
+        }
+        self.buckets.keys().next_back().map(|&bucket| Self::bucket_value(bucket))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct TaskStats {
+    // Runtime statistics
+    pub runtime_sum: u64,
+    pub runtime_sum_sq: f64,  // Sum of squares for variance calculation
+    pub runtime_min: u64,
+    pub runtime_max: u64,
+    pub runtime_max_at: u64,  // Timestamp of the event that set runtime_max
+
+    // Least-squares fit of runtime (ms) over event time (s since trend_origin)
+    pub trend_origin: u64,
+    pub trend_count: u64,
+    pub trend_sum_x: f64,
+    pub trend_sum_y: f64,
+    pub trend_sum_xy: f64,
+    pub trend_sum_xx: f64,
+
+    // Sleep statistics
+    pub sleep_sum: u64,
+    pub sleep_sum_sq: f64,
+    pub sleep_min: u64,
+    pub sleep_max: u64,
+    pub sleep_max_at: u64,  // sleep_end of the longest sleep
+    pub sleep_count: u64,  // Number of events with sleep
+    pub longest_sleeps: TopSleeps,
+
+    // Sleep interval statistics (time between sleeps)
+    pub last_sleep_end: u64,
+    pub sleep_interval_sum: u64,
+    pub sleep_interval_sum_sq: f64,
+    pub sleep_interval_min: u64,
+    pub sleep_interval_max: u64,
+    pub sleep_interval_count: u64,
+
+    // Gap statistics (unobserved time between the end of one event and the next sleep)
+    pub last_event_end: u64,
+    pub gap_sum: u64,
+    pub gap_sum_sq: f64,
+    pub gap_min: u64,
+    pub gap_max: u64,
+    pub gap_count: u64,
+    pub gap_suspicious: u64,  // Gaps above SUSPICIOUS_GAP_NS
+
+    // Budget throttling, as of the latest event
+    pub throttled: u8,
+    pub throttled_events: u64,
+
+    // Context switch statistics
+    pub preempted_count: u64,
+    pub voluntary_count: u64,
+
+    // Per-event runtime / (runtime + sleep), as a fraction
+    pub duty_sum: f64,
+    pub duty_sum_sq: f64,
+    pub duty_count: u64,
+    pub duty_hist: [u64; DUTY_BINS],
+
+    // Time spent in each scheduling state (running is runtime_sum)
+    pub wait_sum: u64,
+    pub sleep_d_sum: u64,
+    pub sleep_s_sum: u64,
+
+    // Wakeups per waker TID, 0 for interrupts and idle
+    pub wakers: HashMap<i32, u64>,
+
+    // Sleep time per syscall the task blocked in, with --syscall-sleeps
+    pub sleep_syscalls: BTreeMap<i32, u64>,
+
+    // Wakeup-to-running latency of each wakeup, and runtime of each event
+    pub wakeup_latency: Histogram,
+    pub runtime_hist: Histogram,
+
+    // Events seen at each nice value
+    pub nice_counts: BTreeMap<i32, u64>,
+    // (timestamp, nice) of the first event and each change after it, up to
+    // MAX_NICE_CHANGES
+    pub nice_changes: Vec<(u64, i32)>,
+    pub nice_changes_dropped: u64,
+    // (timestamp, allowed CPUs) when --track-affinity first read the task's
+    // mask and at each change it saw after, up to MAX_AFFINITY_CHANGES
+    pub affinity_changes: Vec<(u64, Vec<usize>)>,
+    pub affinity_changes_dropped: u64,
+
+    // Thread name as of the latest event
+    pub comm: String,
+
+    // Keyed by the CPU the task ran on before each event
+    pub per_cpu: BTreeMap<u32, CpuStats>,
+
+    // Only kept with --timeseries
+    pub timeseries: Option<TimeSeries>,
+
+    // Timestamps of the first and latest event, 0 before any
+    pub first_seen: u64,
+    pub last_seen: u64,
+
+    pub event_count: u64,
+    pub parent: i32,
+    // Process the thread belongs to, 0 before the first event
+    pub tgid: i32,
+    pub exit: u8,
+}
+
+// Fields missing from a saved file take the same sentinels as a fresh task,
+// u64::MAX for the minimums rather than 0
+impl Default for TaskStats {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl TaskStats {
+    pub fn new(parent: i32) -> Self {
+        Self {
+            runtime_sum: 0,
+            runtime_sum_sq: 0.0,
+            runtime_min: u64::MAX,
+            runtime_max: 0,
+            runtime_max_at: 0,
+
+            trend_origin: 0,
+            trend_count: 0,
+            trend_sum_x: 0.0,
+            trend_sum_y: 0.0,
+            trend_sum_xy: 0.0,
+            trend_sum_xx: 0.0,
+
+            sleep_sum: 0,
+            sleep_sum_sq: 0.0,
+            sleep_min: u64::MAX,
+            sleep_max: 0,
+            sleep_max_at: 0,
+            sleep_count: 0,
+            longest_sleeps: TopSleeps::default(),
+
+            last_sleep_end: 0,
+            sleep_interval_sum: 0,
+            sleep_interval_sum_sq: 0.0,
+            sleep_interval_min: u64::MAX,
+            sleep_interval_max: 0,
+            sleep_interval_count: 0,
+
+            last_event_end: 0,
+            gap_sum: 0,
+            gap_sum_sq: 0.0,
+            gap_min: u64::MAX,
+            gap_max: 0,
+            gap_count: 0,
+            gap_suspicious: 0,
+
+            throttled: 0,
+            throttled_events: 0,
+
+            preempted_count: 0,
+            voluntary_count: 0,
+
+            duty_sum: 0.0,
+            duty_sum_sq: 0.0,
+            duty_count: 0,
+            duty_hist: [0; DUTY_BINS],
+
+            wait_sum: 0,
+            sleep_d_sum: 0,
+            sleep_s_sum: 0,
+
+            wakers: HashMap::new(),
+
+            sleep_syscalls: BTreeMap::new(),
+
+            wakeup_latency: Histogram::default(),
+            runtime_hist: Histogram::default(),
+
+            nice_counts: BTreeMap::new(),
+            nice_changes: Vec::new(),
+            nice_changes_dropped: 0,
+            affinity_changes: Vec::new(),
+            affinity_changes_dropped: 0,
+
+            comm: String::new(),
+            per_cpu: BTreeMap::new(),
+            timeseries: None,
+
+            event_count: 0,
+            parent,
+            first_seen: 0,
+            last_seen: 0,
+
+            tgid: 0,
+            exit: 0,
+        }
+    }
+
+    pub fn update(&mut self, event: &TaskEvent) {
+        let runtime_ns = event.runtime_ns;
+        let sleep_ns = event.sleep_end.saturating_sub(event.sleep_start);
+        let sleep_end = event.sleep_end;
+
+        self.event_count += 1;
+        self.tgid = event.tgid;
+        if self.first_seen == 0 {
+            self.first_seen = event.timestamp;
+        }
+        self.last_seen = self.last_seen.max(event.timestamp);
+
+        let comm = event.comm();
+        if self.comm != comm {
+            self.comm = comm.into_owned();
+        }
+
+        self.throttled = event.throttled;
+        if event.throttled != 0 {
+            self.throttled_events += 1;
+        }
+
+        self.preempted_count += event.nr_preempted as u64;
+        self.voluntary_count += event.nr_voluntary as u64;
+
+        // Events with neither runtime nor sleep say nothing about duty cycle
+        if runtime_ns + sleep_ns > 0 {
+            let duty = runtime_ns as f64 / (runtime_ns + sleep_ns) as f64;
+            self.duty_sum += duty;
+            self.duty_sum_sq += duty * duty;
+            self.duty_count += 1;
+            self.duty_hist[((duty * DUTY_BINS as f64) as usize).min(DUTY_BINS - 1)] += 1;
+        }
+
+        self.wait_sum += event.wait_ns;
+        self.sleep_d_sum += event.sleep_d_ns;
+        self.sleep_s_sum += event.sleep_s_ns;
+        if event.wakeup_latency_ns > 0 {
+            self.wakeup_latency.record(event.wakeup_latency_ns);
+        }
+        self.runtime_hist.record(runtime_ns);
+        *self.nice_counts.entry(event.nice).or_default() += 1;
+        self.record_nice(event.timestamp, event.nice);
+
+        let cpu_stats = self.per_cpu.entry(event.cpu).or_default();
+        cpu_stats.events += 1;
+        cpu_stats.runtime_sum += runtime_ns;
+
+        if let Some(timeseries) = &mut self.timeseries {
+            timeseries.record(event.timestamp, runtime_ns, sleep_ns);
+        }
+
+        // Update runtime statistics
+        self.runtime_sum += runtime_ns;
+        self.runtime_sum_sq += (runtime_ns as f64) * (runtime_ns as f64);
+        self.runtime_min = self.runtime_min.min(runtime_ns);
+        if runtime_ns > self.runtime_max {
+            self.runtime_max = runtime_ns;
+            self.runtime_max_at = event.timestamp;
+        }
+
+        if self.trend_count == 0 {
+            self.trend_origin = event.timestamp;
+        }
+        let x = event.timestamp.saturating_sub(self.trend_origin) as f64 / 1e9;
+        let y = ns_to_ms(runtime_ns as f64);
+        self.trend_count += 1;
+        self.trend_sum_x += x;
+        self.trend_sum_y += y;
+        self.trend_sum_xy += x * y;
+        self.trend_sum_xx += x * x;
+
+        // Update sleep statistics
+        if sleep_ns > 0 {
+            self.sleep_count += 1;
+            self.sleep_sum += sleep_ns;
+            self.sleep_sum_sq += (sleep_ns as f64) * (sleep_ns as f64);
+            self.sleep_min = self.sleep_min.min(sleep_ns);
+            if sleep_ns > self.sleep_max {
+                self.sleep_max = sleep_ns;
+                self.sleep_max_at = sleep_end;
+            }
+            self.longest_sleeps.record(sleep_ns, event.sleep_start);
+            *self.wakers.entry(event.waker_tid).or_default() += 1;
+            if event.sleep_syscall >= 0 {
+                *self.sleep_syscalls.entry(event.sleep_syscall).or_default() += sleep_ns;
+            }
+
+            // Update sleep interval statistics
+            if self.last_sleep_end > 0 && sleep_end > self.last_sleep_end {
+                let interval = sleep_end - self.last_sleep_end;
+                self.sleep_interval_count += 1;
+                self.sleep_interval_sum += interval;
+                self.sleep_interval_sum_sq += (interval as f64) * (interval as f64);
+                self.sleep_interval_min = self.sleep_interval_min.min(interval);
+                self.sleep_interval_max = self.sleep_interval_max.max(interval);
+                cpu_stats.record_interval(interval);
+            }
+            self.last_sleep_end = sleep_end;
+
+            // Update gap statistics
+            if self.last_event_end > 0 && event.sleep_start > self.last_event_end {
+                let gap = event.sleep_start - self.last_event_end;
+                self.gap_count += 1;
+                self.gap_sum += gap;
+                self.gap_sum_sq += (gap as f64) * (gap as f64);
+                self.gap_min = self.gap_min.min(gap);
+                self.gap_max = self.gap_max.max(gap);
+                if gap >= SUSPICIOUS_GAP_NS {
+                    self.gap_suspicious += 1;
+                }
+            }
+            self.last_event_end = sleep_end + runtime_ns;
+        } else {
+            // Runtime-only event, its span can't be placed on the timeline
+            self.last_event_end = 0;
+        }
+    }
+
+    /// Fold another accumulator for the same task into this one. All
+    /// moments are plain sums, so the combined variance stays exact.
+    pub fn merge(&mut self, other: &TaskStats) {
+        self.runtime_sum += other.runtime_sum;
+        self.runtime_sum_sq += other.runtime_sum_sq;
+        self.runtime_min = self.runtime_min.min(other.runtime_min);
+        if other.runtime_max > self.runtime_max {
+            self.runtime_max = other.runtime_max;
+            self.runtime_max_at = other.runtime_max_at;
+        }
+
+        self.merge_trend(other);
+
+        self.sleep_sum += other.sleep_sum;
+        self.sleep_sum_sq += other.sleep_sum_sq;
+        self.sleep_min = self.sleep_min.min(other.sleep_min);
+        if other.sleep_max > self.sleep_max {
+            self.sleep_max = other.sleep_max;
+            self.sleep_max_at = other.sleep_max_at;
+        }
+        self.sleep_count += other.sleep_count;
+        self.longest_sleeps.merge(&other.longest_sleeps);
+
+        self.last_sleep_end = self.last_sleep_end.max(other.last_sleep_end);
+        self.sleep_interval_sum += other.sleep_interval_sum;
+        self.sleep_interval_sum_sq += other.sleep_interval_sum_sq;
+        self.sleep_interval_min = self.sleep_interval_min.min(other.sleep_interval_min);
+        self.sleep_interval_max = self.sleep_interval_max.max(other.sleep_interval_max);
+        self.sleep_interval_count += other.sleep_interval_count;
+
+        self.last_event_end = self.last_event_end.max(other.last_event_end);
+        self.gap_sum += other.gap_sum;
+        self.gap_sum_sq += other.gap_sum_sq;
+        self.gap_min = self.gap_min.min(other.gap_min);
+        self.gap_max = self.gap_max.max(other.gap_max);
+        self.gap_count += other.gap_count;
+        self.gap_suspicious += other.gap_suspicious;
+
+        self.throttled = other.throttled;
+        self.throttled_events += other.throttled_events;
+
+        self.preempted_count += other.preempted_count;
+        self.voluntary_count += other.voluntary_count;
+
+        self.duty_sum += other.duty_sum;
+        self.duty_sum_sq += other.duty_sum_sq;
+        self.duty_count += other.duty_count;
+        for (bin, count) in self.duty_hist.iter_mut().zip(other.duty_hist.iter()) {
+            *bin += count;
+        }
+
+        self.wait_sum += other.wait_sum;
+        self.sleep_d_sum += other.sleep_d_sum;
+        self.sleep_s_sum += other.sleep_s_sum;
+
+        for (&waker, &count) in other.wakers.iter() {
+            *self.wakers.entry(waker).or_default() += count;
+        }
+        for (&nr, &ns) in other.sleep_syscalls.iter() {
+            *self.sleep_syscalls.entry(nr).or_default() += ns;
+        }
+        self.wakeup_latency.merge(&other.wakeup_latency);
+        self.runtime_hist.merge(&other.runtime_hist);
+        for (&nice, &count) in other.nice_counts.iter() {
+            *self.nice_counts.entry(nice).or_default() += count;
+        }
+        let mut changes = std::mem::take(&mut self.nice_changes);
+        changes.extend_from_slice(&other.nice_changes);
+        changes.sort_unstable();
+        self.nice_changes_dropped += other.nice_changes_dropped;
+        for (timestamp, nice) in changes {
+            self.record_nice(timestamp, nice);
+        }
+        let mut changes = std::mem::take(&mut self.affinity_changes);
+        changes.extend_from_slice(&other.affinity_changes);
+        changes.sort_unstable();
+        self.affinity_changes_dropped += other.affinity_changes_dropped;
+        for (timestamp, cpus) in changes {
+            self.record_affinity(timestamp, cpus);
+        }
+
+        if self.comm.is_empty() {
+            self.comm = other.comm.clone();
+        }
+        for (&cpu, cpu_stats) in other.per_cpu.iter() {
+            self.per_cpu.entry(cpu).or_default().merge(cpu_stats);
+        }
+        match (&mut self.timeseries, &other.timeseries) {
+            (Some(timeseries), Some(other_timeseries)) => timeseries.merge(other_timeseries),
+            (None, Some(other_timeseries)) => self.timeseries = Some(other_timeseries.clone()),
+            _ => {}
+        }
+
+        if other.first_seen != 0 && (self.first_seen == 0 || other.first_seen < self.first_seen) {
+            self.first_seen = other.first_seen;
+        }
+        self.last_seen = self.last_seen.max(other.last_seen);
+
+        self.event_count += other.event_count;
+        if self.parent <= 0 {
+            self.parent = other.parent;
+        }
+        if self.tgid <= 0 {
+            self.tgid = other.tgid;
+        }
+        self.exit = self.exit.max(other.exit);
+    }
+
+    fn record_nice(&mut self, timestamp: u64, nice: i32) {
+        if self.nice_changes.last().is_some_and(|&(_, last)| last == nice) {
+            return;
+        }
+        if self.nice_changes.len() < MAX_NICE_CHANGES {
+            self.nice_changes.push((timestamp, nice));
+        } else {
+            self.nice_changes_dropped += 1;
+        }
+    }
+
+    /// Note the CPUs the task may run on as of `timestamp`, kept only when
+    /// they differ from the last ones noted
+    pub fn record_affinity(&mut self, timestamp: u64, cpus: Vec<usize>) {
+        if self.affinity_changes.last().is_some_and(|(_, last)| *last == cpus) {
+            return;
+        }
+        if self.affinity_changes.len() < MAX_AFFINITY_CHANGES {
+            self.affinity_changes.push((timestamp, cpus));
+        } else {
+            self.affinity_changes_dropped += 1;
+        }
+    }
+
+    // Shift other's x values onto our origin, the sums stay exact
+    fn merge_trend(&mut self, other: &TaskStats) {
+        if other.trend_count == 0 {
+            return;
+        }
+        if self.trend_count == 0 {
+            self.trend_origin = other.trend_origin;
+        }
+        let n = other.trend_count as f64;
+        let d = (other.trend_origin as f64 - self.trend_origin as f64) / 1e9;
+        self.trend_count += other.trend_count;
+        self.trend_sum_x += other.trend_sum_x + n * d;
+        self.trend_sum_y += other.trend_sum_y;
+        self.trend_sum_xy += other.trend_sum_xy + d * other.trend_sum_y;
+        self.trend_sum_xx += other.trend_sum_xx + 2.0 * d * other.trend_sum_x + n * d * d;
+    }
+
+    /// Runtime slope in ms per second of trace, None without two distinct event times
+    fn runtime_trend(&self) -> Option<f64> {
+        if self.trend_count < 2 {
+            return None;
+        }
+        let n = self.trend_count as f64;
+        let denom = n * self.trend_sum_xx - self.trend_sum_x * self.trend_sum_x;
+        if denom.abs() < f64::EPSILON {
+            return None;
+        }
+        Some((n * self.trend_sum_xy - self.trend_sum_x * self.trend_sum_y) / denom)
+    }
+
+    pub fn runtime_avg(&self) -> f64 {
+        mean(self.runtime_sum, self.event_count)
+    }
+
+    pub fn runtime_stddev(&self, variance: Variance) -> f64 {
+        stddev(self.runtime_sum, self.runtime_sum_sq, self.event_count, variance)
+    }
+
+    /// Runtime stddev relative to its mean; high values flag bursty tasks
+    fn runtime_cv(&self, variance: Variance) -> f64 {
+        cv(self.runtime_avg(), self.runtime_stddev(variance))
+    }
+
+    pub fn sleep_avg(&self) -> f64 {
+        mean(self.sleep_sum, self.sleep_count)
+    }
+
+    fn sleep_stddev(&self, variance: Variance) -> f64 {
+        stddev(self.sleep_sum, self.sleep_sum_sq, self.sleep_count, variance)
+    }
+
+    fn sleep_cv(&self, variance: Variance) -> f64 {
+        cv(self.sleep_avg(), self.sleep_stddev(variance))
+    }
+
+    fn sleep_interval_avg(&self) -> f64 {
+        mean(self.sleep_interval_sum, self.sleep_interval_count)
+    }
+
+    fn sleep_interval_stddev(&self, variance: Variance) -> f64 {
+        stddev(self.sleep_interval_sum, self.sleep_interval_sum_sq, self.sleep_interval_count, variance)
+    }
+
+    fn sleep_interval_cv(&self, variance: Variance) -> f64 {
+        cv(self.sleep_interval_avg(), self.sleep_interval_stddev(variance))
+    }
+
+    fn duty_avg(&self) -> f64 {
+        if self.duty_count == 0 {
+            return 0.0;
+        }
+        self.duty_sum / self.duty_count as f64
+    }
+
+    fn duty_stddev(&self, variance: Variance) -> f64 {
+        stddev_f64(self.duty_sum, self.duty_sum_sq, self.duty_count, variance)
+    }
+
+    /// Fraction of observed run + sleep time spent running
+    pub fn utilization(&self) -> f64 {
+        let total = self.runtime_sum + self.sleep_sum;
+        if total == 0 {
+            return 0.0;
+        }
+        self.runtime_sum as f64 / total as f64
+    }
+
+    /// Share of context switches where the task was still runnable
+    fn preempted_pct(&self) -> f64 {
+        let switches = self.preempted_count + self.voluntary_count;
+        if switches == 0 {
+            return 0.0;
+        }
+        self.preempted_count as f64 * 100.0 / switches as f64
+    }
+
+    /// Runtime in ms weighted by the power weight of each CPU it ran on, see
+    /// Topology::power_weights; CPUs outside `weights` count 1.0
+    pub fn energy_score(&self, weights: &[f64]) -> f64 {
+        self.per_cpu
+            .iter()
+            .map(|(&cpu, cpu_stats)| {
+                ns_to_ms(cpu_stats.runtime_sum as f64) * weights.get(cpu as usize).copied().unwrap_or(1.0)
+            })
+            .sum()
+    }
+
+    /// Times per second the task was switched out, preempted or to sleep, over `period`
+    fn ctxsw_rate(&self, period: Duration) -> Option<f64> {
+        let secs = period.as_secs_f64();
+        (secs > 0.0).then(|| (self.preempted_count + self.voluntary_count) as f64 / secs)
+    }
+
+    /// Wakeup-to-running latency in ns that `p` percent of wakeups stayed within
+    pub fn latency_percentile(&self, p: f64) -> Option<u64> {
+        self.wakeup_latency.percentile(p)
+    }
+
+    fn gap_avg(&self) -> f64 {
+        mean(self.gap_sum, self.gap_count)
+    }
+
+    fn gap_stddev(&self, variance: Variance) -> f64 {
+        stddev(self.gap_sum, self.gap_sum_sq, self.gap_count, variance)
+    }
+}
+
+fn mean(sum: u64, count: u64) -> f64 {
+    if count == 0 {
+        return 0.0;
+    }
+    sum as f64 / count as f64
+}
+
+// Standard deviation from running sum and sum of squares
+fn stddev(sum: u64, sum_sq: f64, count: u64, variance: Variance) -> f64 {
+    stddev_f64(sum as f64, sum_sq, count, variance)
+}
+
+fn stddev_f64(sum: f64, sum_sq: f64, count: u64, variance: Variance) -> f64 {
+    let divisor = match variance {
+        Variance::Population => count,
+        // One sample has no spread to estimate
+        Variance::Sample => count.saturating_sub(1),
+    };
+    if divisor == 0 {
+        return 0.0;
+    }
+    let n = count as f64;
+    let avg = sum / n;
+    ((sum_sq - n * avg * avg) / divisor as f64).max(0.0).sqrt()
+}
+
+// Coefficient of variation, 0 when the mean is 0
+fn cv(avg: f64, stddev: f64) -> f64 {
+    if avg == 0.0 {
+        return 0.0;
+    }
+    stddev / avg
+}
+
+pub fn ns_to_ms(ns: f64) -> f64 {
+    ns / 1_000_000.0
+}
+
+// Scheduling latencies are mostly far below a millisecond
+fn ns_to_us(ns: f64) -> f64 {
+    ns / 1_000.0
+}
+
+// Merge this interval's stats into a JSON stats file saved by a previous run
+pub fn merge_into_file(path: &str, stats_map: &HashMap<i32, TaskStats>) -> Result<()> {
+    let mut saved = SavedStats::load(path)?;
+
+    for (&tid, task_stats) in stats_map.iter() {
+        saved
+            .entry(tid)
+            .or_insert_with(|| TaskStats::new(task_stats.parent))
+            .merge(task_stats);
+    }
+
+    SavedStats::new(saved)?.save(path)
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum GroupBy {
+    /// One entry per thread
+    #[default]
+    Tid,
+    /// Fold threads with the same name together
+    Comm,
+    /// Fold the threads of each process together
+    Tgid,
+}
+
+/// Divisor of the reported standard deviations
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Variance {
+    /// Divide by N, the spread of exactly the events seen
+    #[default]
+    Population,
+    /// Divide by N - 1, estimating the spread of the task's behaviour in general
+    Sample,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum SortBy {
+    /// TID, or comm with --group-by comm
+    #[default]
+    Id,
+    /// Total runtime, which is also the order of the runtime share
+    #[value(alias = "share")]
+    Runtime,
+    /// Average runtime per run
+    AvgRuntime,
+    /// Share of run + sleep time spent running
+    Util,
+    /// Event count
+    Events,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ReportOptions {
+    pub show_cpu: bool,
+    // --show-numa on a machine with more than one node
+    pub numa: Option<NumaNodes>,
+    // --energy: per-CPU power weights
+    pub power_weights: Option<Vec<f64>>,
+    pub group_by: GroupBy,
+    pub sort_by: SortBy,
+    // Only report this many entries, after sorting
+    pub top: Option<usize>,
+    pub compact: bool,
+    // Also print per-second rates over the collection period
+    pub rates: bool,
+    // With --group-by tgid, list each thread under its process
+    pub show_threads: bool,
+    // Decimals of the floats in the report
+    pub precision: usize,
+    // Scheduling latency percentiles to print, e.g. 99.9
+    pub percentiles: Vec<f64>,
+    pub variance: Variance,
+    // --no-report: print nothing, only the machine-readable outputs are wanted
+    pub no_report: bool,
+    pub palette: Palette,
+    // Also show namespace-local TIDs
+    pub pidns: Option<PidNamespace>,
+}
+
+fn tid_label(tid: i32, opts: &ReportOptions) -> String {
+    match &opts.pidns {
+        Some(pidns) => match pidns.to_local(tid) {
+            Some(local) => format!("{} (ns {})", tid, local),
+            None => format!("{} (ns ?)", tid),
+        },
+        None => tid.to_string(),
+    }
+}
+
+fn print_cpu_table(task_stats: &TaskStats, p: usize) {
+    println!("  {:>5} {:>8} {:>12} {:>10} {:>10} {:>10}",
+        "CPU", "events", "runtime ms", "intv avg", "intv min", "intv max");
+    for (cpu, cpu_stats) in task_stats.per_cpu.iter() {
+        if cpu_stats.sleep_interval_count > 0 {
+            println!("  {:>5} {:>8} {:>12.p$} {:>10.p$} {:>10.p$} {:>10.p$}",
+                cpu, cpu_stats.events, ns_to_ms(cpu_stats.runtime_sum as f64),
+                ns_to_ms(cpu_stats.sleep_interval_avg()),
+                ns_to_ms(cpu_stats.sleep_interval_min as f64),
+                ns_to_ms(cpu_stats.sleep_interval_max as f64));
+        } else {
+            println!("  {:>5} {:>8} {:>12.p$} {:>10} {:>10} {:>10}",
+                cpu, cpu_stats.events, ns_to_ms(cpu_stats.runtime_sum as f64), "-", "-", "-");
+        }
+    }
+}
+
+// Per-CPU figures folded by the node of each CPU, CPUs in no node under None
+fn node_table(task_stats: &TaskStats, numa: &NumaNodes) -> BTreeMap<Option<u32>, (u64, u64)> {
+    let mut nodes: BTreeMap<Option<u32>, (u64, u64)> = BTreeMap::new();
+    for (&cpu, cpu_stats) in task_stats.per_cpu.iter() {
+        let node = nodes.entry(numa.node_of(cpu)).or_default();
+        node.0 += cpu_stats.events;
+        node.1 += cpu_stats.runtime_sum;
+    }
+    nodes
+}
+
+// Running on several nodes is flagged, memory allocated on one is remote to the others
+fn print_node_table(task_stats: &TaskStats, numa: &NumaNodes, palette: &Palette, p: usize) {
+    let nodes = node_table(task_stats, numa);
+    let total: u64 = nodes.values().map(|&(_, runtime)| runtime).sum();
+    println!("  {:>5} {:>8} {:>12} {:>8}", "NODE", "events", "runtime ms", "share");
+    for (node, &(events, runtime)) in nodes.iter() {
+        let share = if total > 0 { runtime as f64 * 100.0 / total as f64 } else { 0.0 };
+        println!("  {:>5} {:>8} {:>12.p$} {:>7.p$}%",
+            node.map_or("-".to_string(), |node| node.to_string()), events, ns_to_ms(runtime as f64), share);
+    }
+    if nodes.len() > 1 {
+        println!("  {}", palette.warn(format!("Ran on {} NUMA nodes", nodes.len()), true));
+    }
+}
+
+pub fn print_timeseries(timeseries: &TimeSeries, p: usize) {
+    println!("  {:>10} {:>8} {:>12} {:>12}", "start s", "events", "runtime ms", "sleep ms");
+    for (i, bucket) in timeseries.buckets.iter().enumerate() {
+        if bucket.events == 0 {
+            continue;
+        }
+        let start = (timeseries.first + i as u64) * timeseries.bucket_ns;
+        println!("  {:>10.3} {:>8} {:>12.p$} {:>12.p$}",
+            ns_to_s(start), bucket.events,
+            ns_to_ms(bucket.runtime_sum as f64), ns_to_ms(bucket.sleep_sum as f64));
+    }
+}
+
+fn print_residency_table(task_stats: &TaskStats, palette: &Palette, p: usize) {
+    let states = [
+        ("running", task_stats.runtime_sum),
+        ("runnable", task_stats.wait_sum),
+        ("sleep (D)", task_stats.sleep_d_sum),
+        ("sleep (S)", task_stats.sleep_s_sum),
+    ];
+    let total: u64 = states.iter().map(|(_, ns)| ns).sum();
+    if total == 0 {
+        return;
+    }
+
+    println!("  {:<10} {:>12} {:>8}", "State", "time ms", "share");
+    for (state, ns) in states {
+        let pct = ns as f64 * 100.0 / total as f64;
+        let share = format!("{:>7.p$}%", pct);
+        println!("  {:<10} {:>12.p$} {}",
+            state, ns_to_ms(ns as f64), palette.warn(share, state == "running" && pct >= HIGH_RUNNING_PCT));
+    }
+}
+
+// One reported task or group, before sorting
+struct ReportEntry<'a> {
+    id: String,
+    header: String,
+    stats: Cow<'a, TaskStats>,
+    // Per-thread detail shown beneath a TGID group
+    threads: Vec<ReportEntry<'a>>,
+}
+
+// Percentage of `total`, the runtime of every reported task, that `runtime` is
+fn runtime_share(runtime: u64, total: u64) -> f64 {
+    if total == 0 {
+        return 0.0;
+    }
+    runtime as f64 * 100.0 / total as f64
+}
+
+fn tid_entry<'a>(tid: i32, task_stats: &'a TaskStats, total_runtime: u64, opts: &ReportOptions) -> ReportEntry<'a> {
+    let id = tid_label(tid, opts);
+    let header = format!("TID: {}, comm: {}, Event cnt: {}, parent: {}, runtime: {}, share: {:.1}%, exit: {}",
+        id, task_stats.comm, task_stats.event_count, task_stats.parent,
+        task_stats.runtime_sum, runtime_share(task_stats.runtime_sum, total_runtime), task_stats.exit);
+    ReportEntry { id, header, stats: Cow::Borrowed(task_stats), threads: Vec::new() }
+}
+
+// Order entries by the --sort-by metric, largest first. Stable, so ties keep
+// TID/comm order.
+fn sort_entries(entries: &mut [ReportEntry], sort_by: SortBy) {
+    match sort_by {
+        SortBy::Id => {}
+        SortBy::Runtime => entries.sort_by_key(|e| Reverse(e.stats.runtime_sum)),
+        SortBy::AvgRuntime => entries.sort_by(|a, b| b.stats.runtime_avg().total_cmp(&a.stats.runtime_avg())),
+        SortBy::Util => entries.sort_by(|a, b| b.stats.utilization().total_cmp(&a.stats.utilization())),
+        SortBy::Events => entries.sort_by_key(|e| Reverse(e.stats.event_count)),
+    }
+}
+
+/// `period` is the wall-clock time the stats were collected over, for --rates
+pub fn print_report(stats_map: &HashMap<i32, TaskStats>, opts: &ReportOptions, period: Duration) {
+    if opts.no_report {
+        return;
+    }
+    // Shares are of the traced set, whatever --top leaves of it
+    let total_runtime: u64 = stats_map.values().map(|task_stats| task_stats.runtime_sum).sum();
+    let mut entries: Vec<ReportEntry> = match opts.group_by {
+        GroupBy::Tid => {
+            let mut tids: Vec<&i32> = stats_map.keys().collect();
+            tids.sort_unstable();
+            tids.into_iter().map(|&tid| tid_entry(tid, &stats_map[&tid], total_runtime, opts)).collect()
+        }
+        GroupBy::Comm => {
+            // Thread pools have many interchangeable threads, fold them by name
+            let mut groups: BTreeMap<&str, (TaskStats, usize)> = BTreeMap::new();
+            for task_stats in stats_map.values() {
+                let (group, threads) = groups
+                    .entry(task_stats.comm.as_str())
+                    .or_insert_with(|| (TaskStats::new(task_stats.parent), 0));
+                group.merge(task_stats);
+                *threads += 1;
+            }
+            groups
+                .into_iter()
+                .map(|(comm, (group, threads))| ReportEntry {
+                    id: comm.to_string(),
+                    header: format!("COMM: {}, threads: {}, Event cnt: {}, runtime: {}, share: {:.1}%",
+                        comm, threads, group.event_count, group.runtime_sum,
+                        runtime_share(group.runtime_sum, total_runtime)),
+                    stats: Cow::Owned(group),
+                    threads: Vec::new(),
+                })
+                .collect()
+        }
+        GroupBy::Tgid => {
+            // Roll listed threads up to their process
+            let mut groups: BTreeMap<i32, (TaskStats, Vec<i32>)> = BTreeMap::new();
+            for (&tid, task_stats) in stats_map.iter() {
+                let (group, tids) = groups
+                    .entry(task_stats.tgid)
+                    .or_insert_with(|| (TaskStats::new(task_stats.parent), Vec::new()));
+                group.merge(task_stats);
+                tids.push(tid);
+            }
+            groups
+                .into_iter()
+                .map(|(tgid, (group, mut tids))| {
+                    let mut threads = Vec::new();
+                    if opts.show_threads {
+                        tids.sort_unstable();
+                        threads = tids.iter().map(|&tid| tid_entry(tid, &stats_map[&tid], total_runtime, opts)).collect();
+                        sort_entries(&mut threads, opts.sort_by);
+                    }
+                    // Name the process after its main thread when that one is listed too
+                    let comm = stats_map.get(&tgid).map_or(&group.comm, |main| &main.comm);
+                    let header = format!("TGID: {}, comm: {}, threads: {}, Event cnt: {}, runtime: {}, share: {:.1}%",
+                        tid_label(tgid, opts), comm, tids.len(), group.event_count, group.runtime_sum,
+                        runtime_share(group.runtime_sum, total_runtime));
+                    ReportEntry {
+                        id: tid_label(tgid, opts),
+                        header,
+                        stats: Cow::Owned(group),
+                        threads,
+                    }
+                })
+                .collect()
+        }
+    };
+
+    sort_entries(&mut entries, opts.sort_by);
+    if let Some(top) = opts.top {
+        entries.truncate(top);
+    }
+
+    if opts.compact {
+        print_compact(&entries, total_runtime, opts);
+        return;
+    }
+    for entry in &entries {
+        println!("{}", entry.header);
+        print_task(&entry.stats, opts, period);
+        for thread in &entry.threads {
+            println!("{}", thread.header);
+            print_task(&thread.stats, opts, period);
+        }
+    }
+}
+
+// One aligned line per entry, for scanning many threads at once
+fn print_compact(entries: &[ReportEntry], total_runtime: u64, opts: &ReportOptions) {
+    let p = opts.precision;
+    let id_column = match opts.group_by {
+        GroupBy::Tid => "TID",
+        GroupBy::Comm => "COMM",
+        GroupBy::Tgid => "TGID",
+    };
+    println!("{:>16} {:>10} {:>10} {:>10} {:>7} {:>7} {:>8}",
+        id_column, "avg_rt ms", "stddev ms", "sleep ms", "util", "share", "events");
+    let print_line = |id: &str, task_stats: &TaskStats| {
+        println!("{:>16} {:>10.p$} {:>10.p$} {:>10.p$} {:>6.1}% {:>6.1}% {:>8}",
+            id, ns_to_ms(task_stats.runtime_avg()), ns_to_ms(task_stats.runtime_stddev(opts.variance)),
+            ns_to_ms(task_stats.sleep_avg()), task_stats.utilization() * 100.0,
+            runtime_share(task_stats.runtime_sum, total_runtime), task_stats.event_count);
+    };
+    for entry in entries {
+        print_line(&entry.id, &entry.stats);
+        for thread in &entry.threads {
+            print_line(&format!("tid {}", thread.id), &thread.stats);
+        }
+    }
+}
+
+// CV rounded for display, highlighted when bursty
+fn fmt_cv(palette: &Palette, cv: f64, p: usize) -> String {
+    palette.warn(format!("{:.p$}", cv), cv >= HIGH_CV)
+}
+
+// Timestamps are reported in seconds
+fn ns_to_s(ns: u64) -> f64 {
+    ns as f64 / 1e9
+}
+
+// Throughput view of a task over the collection period
+fn print_rates(task_stats: &TaskStats, period: Duration, p: usize) {
+    let secs = period.as_secs_f64();
+    if secs <= 0.0 {
+        println!("  Rates: n/a, empty collection period");
+        return;
+    }
+    println!("  Rates: {:.p$} events/s, {:.p$} sleeps/s, runtime {:.p$}% of {:.1} s{}",
+        task_stats.event_count as f64 / secs, task_stats.sleep_count as f64 / secs,
+        task_stats.runtime_sum as f64 * 100.0 / period.as_nanos() as f64, secs,
+        if period < MIN_RATE_PERIOD { " (short period, rates are noisy)" } else { "" });
+}
+
+fn print_task(task_stats: &TaskStats, opts: &ReportOptions, period: Duration) {
+    let palette = &opts.palette;
+    // Decimals of every figure below
+    let p = opts.precision;
+    if task_stats.first_seen != 0 {
+        println!("  Active {:.3}s-{:.3}s ({:.3}s), {}",
+            ns_to_s(task_stats.first_seen), ns_to_s(task_stats.last_seen),
+            ns_to_s(task_stats.last_seen - task_stats.first_seen),
+            if task_stats.exit != 0 { "exited" } else { "still running at the end" });
+    }
+    if opts.rates {
+        print_rates(task_stats, period, p);
+    }
+    if let Some(weights) = &opts.power_weights {
+        println!("  Energy score: {:.p$} (estimate: runtime ms x relative CPU max frequency)",
+            task_stats.energy_score(weights));
+    }
+    println!("  Runtime: avg {:.p$} ms, stddev {:.p$} ms, CV: {}",
+        ns_to_ms(task_stats.runtime_avg()), ns_to_ms(task_stats.runtime_stddev(opts.variance)),
+        fmt_cv(palette, task_stats.runtime_cv(opts.variance), p));
+    if task_stats.event_count > 0 {
+        println!("    Max: {:.p$} ms (at t={:.3}s)",
+            ns_to_ms(task_stats.runtime_max as f64), ns_to_s(task_stats.runtime_max_at));
+    }
+    if let Some(slope) = task_stats.runtime_trend() {
+        println!("    Runtime trend: {:+.p$} ms/s", slope);
+    }
+    if task_stats.sleep_count > 0 {
+        println!("  Sleep: avg {:.p$} ms, stddev {:.p$} ms, CV: {}",
+            ns_to_ms(task_stats.sleep_avg()), ns_to_ms(task_stats.sleep_stddev(opts.variance)),
+            fmt_cv(palette, task_stats.sleep_cv(opts.variance), p));
+        println!("    Max: {:.p$} ms (at t={:.3}s)",
+            ns_to_ms(task_stats.sleep_max as f64), ns_to_s(task_stats.sleep_max_at));
+    }
+    if !task_stats.longest_sleeps.heap.is_empty() {
+        println!("  Longest sleeps:");
+        for (duration, sleep_start) in task_stats.longest_sleeps.sorted() {
+            println!("    {:.p$} ms at {:.3} s", ns_to_ms(duration as f64), ns_to_s(sleep_start));
+        }
+    }
+    if task_stats.sleep_interval_count > 0 {
+        println!("  Sleep interval: avg {:.p$} ms, stddev {:.p$} ms, CV: {}",
+            ns_to_ms(task_stats.sleep_interval_avg()), ns_to_ms(task_stats.sleep_interval_stddev(opts.variance)),
+            fmt_cv(palette, task_stats.sleep_interval_cv(opts.variance), p));
+    }
+    if task_stats.wakeup_latency.count > 0 && !opts.percentiles.is_empty() {
+        let percentiles: Vec<String> = opts
+            .percentiles
+            .iter()
+            .filter_map(|&pct| {
+                let ns = task_stats.latency_percentile(pct)?;
+                Some(format!("p{} {:.p$} us", pct, ns_to_us(ns as f64)))
+            })
+            .collect();
+        println!("  Scheduling latency: {} ({} wakeups)", percentiles.join(", "), task_stats.wakeup_latency.count);
+    }
+    if let Some((&(_, first), changes)) = task_stats.nice_changes.split_first() {
+        let changes: Vec<String> = changes
+            .iter()
+            .map(|&(timestamp, nice)| format!("to {} at t={:.3}s", nice, ns_to_s(timestamp)))
+            .collect();
+        if changes.is_empty() {
+            println!("  Nice: {}", first);
+        } else {
+            let dropped = match task_stats.nice_changes_dropped {
+                0 => String::new(),
+                n => format!(", {} more", n),
+            };
+            println!("  Nice: {} ({}{})", first, palette.warn(format!("changed {}", changes.join(", ")), true), dropped);
+            let counts: Vec<String> = task_stats
+                .nice_counts
+                .iter()
+                .map(|(nice, count)| format!("{}: {}", nice, count))
+                .collect();
+            println!("    Events per nice: {}", counts.join(", "));
+        }
+    }
+    if let Some(((_, first), changes)) = task_stats.affinity_changes.split_first() {
+        println!("  Affinity: {}", topology::format_cpulist(first));
+        let mut from = first;
+        for (timestamp, to) in changes {
+            println!("    {}", palette.warn(format!("affinity changed at t={:.3}s: {} \u{2192} {}", ns_to_s(*timestamp),
+                topology::format_cpulist(from), topology::format_cpulist(to)), true));
+            from = to;
+        }
+        if task_stats.affinity_changes_dropped > 0 {
+            println!("    {} more changes", task_stats.affinity_changes_dropped);
+        }
+    }
+    if !task_stats.wakers.is_empty() {
+        let mut wakers: Vec<(i32, u64)> = task_stats.wakers.iter().map(|(&tid, &count)| (tid, count)).collect();
+        wakers.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        let top: Vec<String> = wakers
+            .iter()
+            .take(TOP_WAKERS)
+            .map(|&(tid, count)| match tid {
+                0 => format!("[irq/idle] ({})", count),
+                _ => format!("{} ({})", tid, count),
+            })
+            .collect();
+        println!("  Top wakers: {}", top.join(", "));
+    }
+    if !task_stats.sleep_syscalls.is_empty() && task_stats.sleep_sum > 0 {
+        let mut syscalls: Vec<(i32, u64)> = task_stats.sleep_syscalls.iter().map(|(&nr, &ns)| (nr, ns)).collect();
+        syscalls.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        // Shares of all sleep time, the rest was spent outside a syscall (e.g. page faults)
+        let top: Vec<String> = syscalls
+            .iter()
+            .take(TOP_SYSCALLS)
+            .map(|&(nr, ns)| format!("{} ({:.0}%)", syscalls::name(nr), ns as f64 * 100.0 / task_stats.sleep_sum as f64))
+            .collect();
+        println!("  Top blocking syscalls: {}", top.join(", "));
+    }
+    if task_stats.duty_count > 0 {
+        println!("  Duty cycle: avg {:.p$}%, stddev {:.p$}%",
+            task_stats.duty_avg() * 100.0, task_stats.duty_stddev(opts.variance) * 100.0);
+        let bins: Vec<String> = task_stats
+            .duty_hist
+            .iter()
+            .enumerate()
+            .map(|(i, count)| format!("{}-{}%: {}", i * 100 / DUTY_BINS, (i + 1) * 100 / DUTY_BINS, count))
+            .collect();
+        println!("    {}", bins.join(", "));
+    }
+    if task_stats.preempted_count + task_stats.voluntary_count > 0 {
+        let pct = task_stats.preempted_pct();
+        // Lock contention and thrashing show up as very frequent switches
+        let rate = task_stats
+            .ctxsw_rate(period)
+            .map_or(String::new(), |rate| {
+                format!(", {}", palette.warn(format!("{:.p$} ctxsw/s", rate), rate >= HIGH_CTXSW_PER_S))
+            });
+        println!("  Preempted: {} ({}), voluntary: {}{}",
+            task_stats.preempted_count, palette.warn(format!("{:.p$}%", pct), pct >= HIGH_PREEMPTED_PCT),
+            task_stats.voluntary_count, rate);
+    }
+    if task_stats.gap_count > 0 {
+        println!("  Gaps: avg {:.p$} ms, stddev {:.p$} ms, max {:.p$} ms, count {}",
+            ns_to_ms(task_stats.gap_avg()), ns_to_ms(task_stats.gap_stddev(opts.variance)),
+            ns_to_ms(task_stats.gap_max as f64), task_stats.gap_count);
+        if task_stats.gap_suspicious > 0 {
+            println!("    {}", palette.alert(format!("Warning: {} gaps over {} ms, events may have been dropped",
+                task_stats.gap_suspicious, SUSPICIOUS_GAP_NS / 1_000_000)));
+        }
+    }
+    if task_stats.throttled_events > 0 {
+        println!("  Throttled: {} ({} of {} events over budget)",
+            if task_stats.throttled != 0 { palette.warn("yes", true) } else { "no".to_string() },
+            task_stats.throttled_events, task_stats.event_count);
+    }
+    print_residency_table(task_stats, palette, p);
+    if let Some(timeseries) = &task_stats.timeseries {
+        print_timeseries(timeseries, p);
+    }
+    if opts.show_cpu && !task_stats.per_cpu.is_empty() {
+        print_cpu_table(task_stats, p);
+    }
+    if let (Some(numa), false) = (&opts.numa, task_stats.per_cpu.is_empty()) {
+        print_node_table(task_stats, numa, palette, p);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An event at `timestamp` (ms) after `sleep` ms asleep, then `runtime` ms on CPU
+    fn event(timestamp: u64, runtime: u64, sleep: u64, nice: i32) -> TaskEvent {
+        let mut e = TaskEvent::sample(100, "worker");
+        e.timestamp = timestamp * 1_000_000;
+        e.sleep_end = e.timestamp - runtime * 1_000_000;
+        e.sleep_start = e.sleep_end - sleep * 1_000_000;
+        e.runtime_ns = runtime * 1_000_000;
+        e.wakeup_latency_ns = runtime * 1_000;
+        e.nice = nice;
+        e
+    }
+
+    fn stats_of(events: &[TaskEvent]) -> TaskStats {
+        let mut stats = TaskStats::new(1);
+        for e in events {
+            stats.update(e);
+        }
+        stats
+    }
+
+    #[test]
+    fn merge_matches_one_accumulator() {
+        let first = [event(100, 2, 10, 0), event(200, 8, 30, 0)];
+        let second = [event(300, 1, 5, 0), event(400, 20, 60, 0)];
+        let all: Vec<TaskEvent> = first.iter().chain(&second).copied().collect();
+
+        let mut merged = stats_of(&first);
+        merged.merge(&stats_of(&second));
+        let whole = stats_of(&all);
+
+        assert_eq!(merged.event_count, 4);
+        assert_eq!(merged.runtime_sum, whole.runtime_sum);
+        assert_eq!(merged.runtime_sum_sq, whole.runtime_sum_sq);
+        assert_eq!(merged.sleep_sum, whole.sleep_sum);
+        assert_eq!(merged.sleep_count, whole.sleep_count);
+        assert_eq!(merged.wait_sum, whole.wait_sum);
+        assert_eq!(merged.preempted_count, whole.preempted_count);
+        assert_eq!(merged.duty_count, whole.duty_count);
+        assert_eq!(merged.duty_hist, whole.duty_hist);
+        assert_eq!(merged.wakers, whole.wakers);
+        assert_eq!(merged.first_seen, whole.first_seen);
+        assert_eq!(merged.last_seen, whole.last_seen);
+    }
+
+    #[test]
+    fn merge_keeps_the_extremes_of_both() {
+        let mut merged = stats_of(&[event(100, 2, 10, 0), event(200, 8, 30, 0)]);
+        merged.merge(&stats_of(&[event(300, 1, 5, 0), event(400, 20, 60, 0)]));
+
+        assert_eq!(merged.runtime_min, 1_000_000);
+        assert_eq!(merged.runtime_max, 20_000_000);
+        assert_eq!(merged.runtime_max_at, 400_000_000);
+        assert_eq!(merged.sleep_min, 5_000_000);
+        assert_eq!(merged.sleep_max, 60_000_000);
+    }
+
+    #[test]
+    fn merge_into_an_empty_accumulator_keeps_the_minimums() {
+        let other = stats_of(&[event(100, 2, 10, 0)]);
+        for mut empty in [TaskStats::new(1), TaskStats::default()] {
+            empty.merge(&other);
+            assert_eq!(empty.runtime_min, other.runtime_min);
+            assert_eq!(empty.sleep_min, other.sleep_min);
+            assert_eq!(empty.event_count, 1);
+        }
+    }
+
+    #[test]
+    fn merge_adds_up_the_histograms() {
+        let first = stats_of(&[event(100, 2, 10, 0), event(200, 2, 10, 0)]);
+        let second = stats_of(&[event(300, 2, 10, 0), event(400, 50, 10, 0)]);
+        let mut merged = first.clone();
+        merged.merge(&second);
+
+        assert_eq!(merged.runtime_hist.count, 4);
+        assert_eq!(merged.wakeup_latency.count, 4);
+        let bucket = Histogram::bucket(2_000_000);
+        assert_eq!(merged.runtime_hist.buckets[&bucket], 3);
+        assert_eq!(merged.runtime_hist.buckets.values().sum::<u64>(), 4);
+    }
+
+    #[test]
+    fn merge_interleaves_the_nice_timelines() {
+        let mut merged = stats_of(&[event(100, 1, 1, 0), event(300, 1, 1, 5)]);
+        merged.merge(&stats_of(&[event(200, 1, 1, 0), event(400, 1, 1, -2)]));
+
+        assert_eq!(merged.nice_changes, vec![(100_000_000, 0), (300_000_000, 5), (400_000_000, -2)]);
+        assert_eq!(merged.nice_counts, BTreeMap::from([(-2, 1), (0, 2), (5, 1)]));
+    }
+
+    #[test]
+    fn merge_interleaves_the_affinity_timelines() {
+        let mut merged = TaskStats::new(1);
+        merged.record_affinity(10, vec![0, 1]);
+        merged.record_affinity(30, vec![2]);
+        let mut other = TaskStats::new(1);
+        other.record_affinity(20, vec![0, 1]);
+        other.record_affinity(40, vec![3]);
+        merged.merge(&other);
+
+        assert_eq!(merged.affinity_changes, vec![(10, vec![0, 1]), (30, vec![2]), (40, vec![3])]);
+        assert_eq!(merged.affinity_changes_dropped, 0);
+    }
+
+    #[test]
+    fn merge_caps_the_affinity_timeline() {
+        let mut merged = TaskStats::new(1);
+        let mut other = TaskStats::new(1);
+        for i in 0..MAX_AFFINITY_CHANGES {
+            merged.record_affinity(i as u64, vec![i % 2]);
+            other.record_affinity(100 + i as u64, vec![i % 2 + 2]);
+        }
+        merged.merge(&other);
+
+        assert_eq!(merged.affinity_changes.len(), MAX_AFFINITY_CHANGES);
+        assert_eq!(merged.affinity_changes_dropped, MAX_AFFINITY_CHANGES as u64);
+    }
+}
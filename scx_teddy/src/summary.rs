@@ -0,0 +1,182 @@
+// SPDX-License-Identifier: GPL-2.0
+//! Per-task key figures over the whole run for --summary-out, as JSON or CSV.
+//!
+//! Every duration is written twice: `<metric>_ns` is the integer nanosecond
+//! value (averages and deviations rounded to the nearest ns) and
+//! `<metric>_ms` the same value in milliseconds, for reading at a glance.
+//! Tools should compute from the `_ns` fields, which lose no precision.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::stats::{ns_to_ms, TaskStats, Variance};
+
+// Bumped whenever the fields below change
+const SUMMARY_FORMAT_VERSION: u64 = 1;
+
+/// A duration as exact nanoseconds and as milliseconds
+#[derive(Debug, Clone, Copy)]
+struct Nanos(u64);
+
+impl Nanos {
+    fn rounded(ns: f64) -> Self {
+        Self(ns.round() as u64)
+    }
+
+    fn ms(self) -> f64 {
+        ns_to_ms(self.0 as f64)
+    }
+}
+
+// One task's figures, in column order. Durations come in _ns/_ms pairs, see
+// the module docs; a wakeup latency percentile is null without wakeups.
+#[derive(Debug, Serialize)]
+struct TaskSummary<'a> {
+    tid: i32,
+    tgid: i32,
+    comm: &'a str,
+    events: u64,
+    runtime_sum_ns: u64,
+    runtime_sum_ms: f64,
+    runtime_avg_ns: u64,
+    runtime_avg_ms: f64,
+    runtime_stddev_ns: u64,
+    runtime_stddev_ms: f64,
+    runtime_max_ns: u64,
+    runtime_max_ms: f64,
+    sleep_sum_ns: u64,
+    sleep_sum_ms: f64,
+    sleep_avg_ns: u64,
+    sleep_avg_ms: f64,
+    sleep_max_ns: u64,
+    sleep_max_ms: f64,
+    wait_sum_ns: u64,
+    wait_sum_ms: f64,
+    wakeup_latency_p50_ns: Option<u64>,
+    wakeup_latency_p50_ms: Option<f64>,
+    wakeup_latency_p99_ns: Option<u64>,
+    wakeup_latency_p99_ms: Option<f64>,
+    utilization: f64,
+}
+
+const COLUMNS: &[&str] = &[
+    "tid", "tgid", "comm", "events", "runtime_sum_ns", "runtime_sum_ms", "runtime_avg_ns",
+    "runtime_avg_ms", "runtime_stddev_ns", "runtime_stddev_ms", "runtime_max_ns", "runtime_max_ms",
+    "sleep_sum_ns", "sleep_sum_ms", "sleep_avg_ns", "sleep_avg_ms", "sleep_max_ns", "sleep_max_ms",
+    "wait_sum_ns", "wait_sum_ms", "wakeup_latency_p50_ns", "wakeup_latency_p50_ms",
+    "wakeup_latency_p99_ns", "wakeup_latency_p99_ms", "utilization",
+];
+
+impl<'a> TaskSummary<'a> {
+    fn new(tid: i32, task_stats: &'a TaskStats, variance: Variance) -> Self {
+        let runtime_sum = Nanos(task_stats.runtime_sum);
+        let runtime_avg = Nanos::rounded(task_stats.runtime_avg());
+        let runtime_stddev = Nanos::rounded(task_stats.runtime_stddev(variance));
+        let runtime_max = Nanos(task_stats.runtime_max);
+        let sleep_sum = Nanos(task_stats.sleep_sum);
+        let sleep_avg = Nanos::rounded(task_stats.sleep_avg());
+        let sleep_max = Nanos(task_stats.sleep_max);
+        let wait_sum = Nanos(task_stats.wait_sum);
+        let p50 = task_stats.latency_percentile(50.0).map(Nanos);
+        let p99 = task_stats.latency_percentile(99.0).map(Nanos);
+        Self {
+            tid,
+            tgid: task_stats.tgid,
+            comm: &task_stats.comm,
+            events: task_stats.event_count,
+            runtime_sum_ns: runtime_sum.0,
+            runtime_sum_ms: runtime_sum.ms(),
+            runtime_avg_ns: runtime_avg.0,
+            runtime_avg_ms: runtime_avg.ms(),
+            runtime_stddev_ns: runtime_stddev.0,
+            runtime_stddev_ms: runtime_stddev.ms(),
+            runtime_max_ns: runtime_max.0,
+            runtime_max_ms: runtime_max.ms(),
+            sleep_sum_ns: sleep_sum.0,
+            sleep_sum_ms: sleep_sum.ms(),
+            sleep_avg_ns: sleep_avg.0,
+            sleep_avg_ms: sleep_avg.ms(),
+            sleep_max_ns: sleep_max.0,
+            sleep_max_ms: sleep_max.ms(),
+            wait_sum_ns: wait_sum.0,
+            wait_sum_ms: wait_sum.ms(),
+            wakeup_latency_p50_ns: p50.map(|ns| ns.0),
+            wakeup_latency_p50_ms: p50.map(Nanos::ms),
+            wakeup_latency_p99_ns: p99.map(|ns| ns.0),
+            wakeup_latency_p99_ms: p99.map(Nanos::ms),
+            utilization: task_stats.utilization(),
+        }
+    }
+
+    // One CSV line matching COLUMNS, newline included. comm is quoted since
+    // it may hold commas.
+    fn csv_record(&self) -> String {
+        let opt = |value: Option<String>| value.unwrap_or_default();
+        let mut line = format!("{},{},\"{}\",{}", self.tid, self.tgid,
+            self.comm.replace('"', "\"\"").replace('\n', " "), self.events);
+        for (ns, ms) in [
+            (self.runtime_sum_ns, self.runtime_sum_ms),
+            (self.runtime_avg_ns, self.runtime_avg_ms),
+            (self.runtime_stddev_ns, self.runtime_stddev_ms),
+            (self.runtime_max_ns, self.runtime_max_ms),
+            (self.sleep_sum_ns, self.sleep_sum_ms),
+            (self.sleep_avg_ns, self.sleep_avg_ms),
+            (self.sleep_max_ns, self.sleep_max_ms),
+            (self.wait_sum_ns, self.wait_sum_ms),
+        ] {
+            let _ = write!(line, ",{},{}", ns, ms);
+        }
+        for (ns, ms) in [
+            (self.wakeup_latency_p50_ns, self.wakeup_latency_p50_ms),
+            (self.wakeup_latency_p99_ns, self.wakeup_latency_p99_ms),
+        ] {
+            let _ = write!(line, ",{},{}", opt(ns.map(|ns| ns.to_string())), opt(ms.map(|ms| ms.to_string())));
+        }
+        let _ = writeln!(line, ",{}", self.utilization);
+        line
+    }
+}
+
+/// Stats of every task seen over the whole run, across collection periods
+#[derive(Debug)]
+pub struct SummaryExport {
+    tasks: BTreeMap<i32, TaskStats>,
+    variance: Variance,
+}
+
+impl SummaryExport {
+    pub fn new(variance: Variance) -> Self {
+        Self { tasks: BTreeMap::new(), variance }
+    }
+
+    /// Fold in the stats of a collection period before they are cleared
+    pub fn add(&mut self, stats_map: &HashMap<i32, TaskStats>) {
+        for (&tid, task_stats) in stats_map.iter() {
+            self.tasks.entry(tid).or_insert_with(|| TaskStats::new(task_stats.parent)).merge(task_stats);
+        }
+    }
+
+    /// Write CSV when `path` ends in .csv, JSON otherwise
+    pub fn write(&self, path: &str) -> Result<()> {
+        let tasks = self.tasks.iter().map(|(&tid, task_stats)| TaskSummary::new(tid, task_stats, self.variance));
+        let data = if path.ends_with(".csv") {
+            let mut data = format!("{}\n", COLUMNS.join(","));
+            for task in tasks {
+                data.push_str(&task.csv_record());
+            }
+            data
+        } else {
+            #[derive(Serialize)]
+            struct File<'a> {
+                format_version: u64,
+                tasks: Vec<TaskSummary<'a>>,
+            }
+            let file = File { format_version: SUMMARY_FORMAT_VERSION, tasks: tasks.collect() };
+            serde_json::to_string_pretty(&file).context("Failed to serialize the summary")?
+        };
+        std::fs::write(path, data).with_context(|| format!("Failed to write the summary to {}", path))
+    }
+}
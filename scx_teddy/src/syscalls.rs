@@ -0,0 +1,39 @@
+// SPDX-License-Identifier: GPL-2.0
+//! Names of the syscalls tasks commonly block in, for --syscall-sleeps
+
+use std::borrow::Cow;
+
+// Numbers differ per architecture; unlisted ones print as syscall_<nr>
+#[cfg(target_arch = "x86_64")]
+const NAMES: &[(i32, &str)] = &[
+    (0, "read"), (1, "write"), (3, "close"), (7, "poll"), (16, "ioctl"), (17, "pread64"),
+    (18, "pwrite64"), (19, "readv"), (20, "writev"), (23, "select"), (24, "sched_yield"),
+    (34, "pause"), (35, "nanosleep"), (42, "connect"), (43, "accept"), (44, "sendto"),
+    (45, "recvfrom"), (46, "sendmsg"), (47, "recvmsg"), (61, "wait4"), (72, "fcntl"),
+    (74, "fsync"), (75, "fdatasync"), (202, "futex"), (230, "clock_nanosleep"),
+    (232, "epoll_wait"), (247, "waitid"), (257, "openat"), (270, "pselect6"), (271, "ppoll"),
+    (281, "epoll_pwait"), (288, "accept4"), (299, "recvmmsg"), (307, "sendmmsg"),
+    (426, "io_uring_enter"), (441, "epoll_pwait2"),
+];
+
+#[cfg(target_arch = "aarch64")]
+const NAMES: &[(i32, &str)] = &[
+    (22, "epoll_pwait"), (25, "fcntl"), (29, "ioctl"), (56, "openat"), (57, "close"),
+    (63, "read"), (64, "write"), (65, "readv"), (66, "writev"), (67, "pread64"),
+    (68, "pwrite64"), (72, "pselect6"), (73, "ppoll"), (82, "fsync"), (83, "fdatasync"),
+    (95, "waitid"), (98, "futex"), (101, "nanosleep"), (115, "clock_nanosleep"),
+    (124, "sched_yield"), (202, "accept"), (203, "connect"), (206, "sendto"),
+    (207, "recvfrom"), (211, "sendmsg"), (212, "recvmsg"), (242, "accept4"),
+    (243, "recvmmsg"), (260, "wait4"), (269, "sendmmsg"), (426, "io_uring_enter"),
+    (441, "epoll_pwait2"),
+];
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+const NAMES: &[(i32, &str)] = &[];
+
+pub fn name(nr: i32) -> Cow<'static, str> {
+    match NAMES.iter().find(|&&(known, _)| known == nr) {
+        Some(&(_, name)) => Cow::Borrowed(name),
+        None => Cow::Owned(format!("syscall_{}", nr)),
+    }
+}
@@ -0,0 +1,113 @@
+// SPDX-License-Identifier: GPL-2.0
+//! Resolution of the configured targets against /proc, printed by --list-targets
+
+use std::collections::BTreeMap;
+use std::fs;
+
+use anyhow::{bail, Result};
+
+// A TID selected by the config or the flags, as found in /proc
+#[derive(Debug, Clone)]
+pub struct Target {
+    pub tid: i32,
+    // None when the thread does not exist
+    pub tgid: Option<i32>,
+    pub comm: Option<String>,
+    // What selected the TID, e.g. "--tgid 1200"
+    pub source: String,
+}
+
+pub fn read_comm(tid: i32) -> Option<String> {
+    let comm = fs::read_to_string(format!("/proc/{}/comm", tid)).ok()?;
+    Some(comm.trim_end().to_string())
+}
+
+pub fn read_tgid(tid: i32) -> Option<i32> {
+    let status = fs::read_to_string(format!("/proc/{}/status", tid)).ok()?;
+    let line = status.lines().find(|line| line.starts_with("Tgid:"))?;
+    line.split_whitespace().nth(1)?.parse().ok()
+}
+
+/// CPUs the task may run on
+pub fn read_allowed_cpus(tid: i32) -> Option<Vec<usize>> {
+    let status = fs::read_to_string(format!("/proc/{}/status", tid)).ok()?;
+    let list = status.lines().find_map(|line| line.strip_prefix("Cpus_allowed_list:"))?;
+    crate::topology::parse_cpulist(list)
+}
+
+// Threads of a process, empty if it does not exist
+fn threads_of(tgid: i32) -> Vec<i32> {
+    let Ok(tasks) = fs::read_dir(format!("/proc/{}/task", tgid)) else {
+        return Vec::new();
+    };
+    let mut tids: Vec<i32> = tasks
+        .flatten()
+        .filter_map(|task| task.file_name().to_str().and_then(|n| n.parse().ok()))
+        .collect();
+    tids.sort_unstable();
+    tids
+}
+
+/// Targets selected by TIDs and by every thread of TGIDs, each paired with a
+/// description of where it came from, minus the excluded TIDs
+#[derive(Debug, Default)]
+pub struct Resolution {
+    pub targets: BTreeMap<i32, Target>,
+    // TGIDs without a /proc entry, with their source
+    pub missing_tgids: Vec<(i32, String)>,
+    pub excluded: Vec<i32>,
+}
+
+impl Resolution {
+    pub fn add_tid(&mut self, tid: i32, source: String) {
+        self.targets.entry(tid).or_insert_with(|| Target {
+            tid,
+            tgid: read_tgid(tid),
+            comm: read_comm(tid),
+            source,
+        });
+    }
+
+    pub fn add_tgid(&mut self, tgid: i32, source: String) {
+        let threads = threads_of(tgid);
+        if threads.is_empty() {
+            self.missing_tgids.push((tgid, source));
+            return;
+        }
+        for tid in threads {
+            self.add_tid(tid, source.clone());
+        }
+    }
+
+    pub fn exclude(&mut self, tid: i32) {
+        if self.targets.remove(&tid).is_some() {
+            self.excluded.push(tid);
+        }
+    }
+
+    /// Print the resolved TIDs, failing if none of them is running
+    pub fn print(&self) -> Result<()> {
+        println!("{:>8} {:>8}  {:<16} FROM", "TID", "TGID", "COMM");
+        for target in self.targets.values() {
+            println!("{:>8} {:>8}  {:<16} {}",
+                target.tid,
+                target.tgid.map_or("-".to_string(), |tgid| tgid.to_string()),
+                target.comm.as_deref().unwrap_or("(not running)"),
+                target.source);
+        }
+        for (tgid, source) in &self.missing_tgids {
+            println!("Warning: tgid {} from {} does not exist", tgid, source);
+        }
+        if !self.excluded.is_empty() {
+            let excluded: Vec<String> = self.excluded.iter().map(|tid| tid.to_string()).collect();
+            println!("Excluded: {}", excluded.join(", "));
+        }
+
+        let running = self.targets.values().filter(|target| target.tgid.is_some()).count();
+        if running == 0 {
+            bail!("No running task matches the targets; check the TIDs/TGIDs given");
+        }
+        println!("\n{} target(s), {} running", self.targets.len(), running);
+        Ok(())
+    }
+}
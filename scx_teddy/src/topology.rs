@@ -0,0 +1,203 @@
+// SPDX-License-Identifier: GPL-2.0
+//! CPU topology detection from sysfs, used to tell performance and efficiency
+//! cores apart and to map CPUs to NUMA nodes
+
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+const CPU_SYSFS: &str = "/sys/devices/system/cpu";
+const NODE_SYSFS: &str = "/sys/devices/system/node";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoreType {
+    Performance,
+    Efficiency,
+}
+
+impl CoreType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            CoreType::Performance => "performance",
+            CoreType::Efficiency => "efficiency",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Cpu {
+    pub id: usize,
+    pub core_id: usize,
+    pub package_id: usize,
+    /// cpuinfo_max_freq in kHz, 0 when cpufreq is not available
+    pub max_freq_khz: u64,
+    pub core_type: CoreType,
+}
+
+#[derive(Debug, Clone)]
+pub struct Topology {
+    pub cpus: Vec<Cpu>,
+}
+
+fn read_usize(path: &Path) -> Option<usize> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+impl Topology {
+    /// Read the online CPUs from sysfs. Cores whose max frequency is below the
+    /// fastest core are classified as efficiency cores; on non-hybrid machines
+    /// (or without cpufreq) every core ends up as performance.
+    pub fn detect() -> Result<Self> {
+        let mut cpus = Vec::new();
+
+        for entry in fs::read_dir(CPU_SYSFS).with_context(|| format!("Failed to read {}", CPU_SYSFS))? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let Some(id) = name
+                .to_str()
+                .and_then(|n| n.strip_prefix("cpu"))
+                .and_then(|n| n.parse::<usize>().ok())
+            else {
+                continue;
+            };
+
+            // Offline CPUs have no topology directory
+            let dir = entry.path();
+            let Some(core_id) = read_usize(&dir.join("topology/core_id")) else {
+                continue;
+            };
+            let package_id = read_usize(&dir.join("topology/physical_package_id")).unwrap_or(0);
+            let max_freq_khz = read_usize(&dir.join("cpufreq/cpuinfo_max_freq")).unwrap_or(0) as u64;
+
+            cpus.push(Cpu {
+                id,
+                core_id,
+                package_id,
+                max_freq_khz,
+                core_type: CoreType::Performance,
+            });
+        }
+        cpus.sort_by_key(|cpu| cpu.id);
+
+        let top_freq = cpus.iter().map(|cpu| cpu.max_freq_khz).max().unwrap_or(0);
+        for cpu in cpus.iter_mut() {
+            if cpu.max_freq_khz > 0 && cpu.max_freq_khz < top_freq {
+                cpu.core_type = CoreType::Efficiency;
+            }
+        }
+
+        Ok(Self { cpus })
+    }
+
+    pub fn is_hybrid(&self) -> bool {
+        self.cpus.iter().any(|cpu| cpu.core_type == CoreType::Efficiency)
+    }
+
+    pub fn cpus_of(&self, core_type: CoreType) -> impl Iterator<Item = &Cpu> {
+        self.cpus.iter().filter(move |cpu| cpu.core_type == core_type)
+    }
+
+    /// Relative power cost of a ns on each CPU, indexed by CPU id: max
+    /// frequency over the fastest core's, 1.0 without cpufreq. A crude proxy
+    /// that ignores voltage, the current frequency and idle states.
+    pub fn power_weights(&self) -> Vec<f64> {
+        let top_freq = self.cpus.iter().map(|cpu| cpu.max_freq_khz).max().unwrap_or(0);
+        let mut weights = vec![1.0; self.cpus.iter().map(|cpu| cpu.id + 1).max().unwrap_or(0)];
+        for cpu in self.cpus.iter().filter(|cpu| cpu.max_freq_khz > 0) {
+            weights[cpu.id] = cpu.max_freq_khz as f64 / top_freq as f64;
+        }
+        weights
+    }
+
+    pub fn print(&self) {
+        println!("{:>4} {:>5} {:>4} {:>9}  TYPE", "CPU", "CORE", "PKG", "MAX_MHZ");
+        for cpu in &self.cpus {
+            println!("{:>4} {:>5} {:>4} {:>9}  {}",
+                cpu.id, cpu.core_id, cpu.package_id, cpu.max_freq_khz / 1000, cpu.core_type.name());
+        }
+
+        if self.is_hybrid() {
+            println!("\nHybrid topology: {} performance, {} efficiency CPUs",
+                self.cpus_of(CoreType::Performance).count(),
+                self.cpus_of(CoreType::Efficiency).count());
+        } else {
+            println!("\nNon-hybrid topology: all {} CPUs are one class", self.cpus.len());
+        }
+    }
+}
+
+/// Expand a sysfs CPU list such as "0-3,8-11"
+pub fn parse_cpulist(list: &str) -> Option<Vec<usize>> {
+    let mut cpus = Vec::new();
+    for range in list.trim().split(',').filter(|range| !range.is_empty()) {
+        match range.split_once('-') {
+            Some((first, last)) => cpus.extend(first.parse::<usize>().ok()?..=last.parse().ok()?),
+            None => cpus.push(range.parse().ok()?),
+        }
+    }
+    Some(cpus)
+}
+
+/// Inverse of parse_cpulist, e.g. "0-3,8" for sorted CPU ids
+pub fn format_cpulist(cpus: &[usize]) -> String {
+    let mut ranges: Vec<String> = Vec::new();
+    let mut i = 0;
+    while i < cpus.len() {
+        let first = cpus[i];
+        while i + 1 < cpus.len() && cpus[i + 1] == cpus[i] + 1 {
+            i += 1;
+        }
+        ranges.push(if cpus[i] == first { first.to_string() } else { format!("{}-{}", first, cpus[i]) });
+        i += 1;
+    }
+    ranges.join(",")
+}
+
+/// CPU to NUMA node lookup, read once from sysfs at startup
+#[derive(Debug, Clone, Default)]
+pub struct NumaNodes {
+    // Indexed by CPU id
+    node_of: Vec<Option<u32>>,
+    pub nr_nodes: usize,
+}
+
+impl NumaNodes {
+    /// Kernels built without CONFIG_NUMA have no node directory, they are
+    /// treated as a single node
+    pub fn detect() -> Result<Self> {
+        let mut numa = Self::default();
+        let Ok(entries) = fs::read_dir(NODE_SYSFS) else {
+            numa.nr_nodes = 1;
+            return Ok(numa);
+        };
+        for entry in entries {
+            let entry = entry?;
+            let name = entry.file_name();
+            let Some(node) = name
+                .to_str()
+                .and_then(|n| n.strip_prefix("node"))
+                .and_then(|n| n.parse::<u32>().ok())
+            else {
+                continue;
+            };
+            let path = entry.path().join("cpulist");
+            let list = fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+            let cpus = parse_cpulist(&list).with_context(|| format!("Cannot parse {}: {}", path.display(), list.trim()))?;
+            for cpu in cpus {
+                if numa.node_of.len() <= cpu {
+                    numa.node_of.resize(cpu + 1, None);
+                }
+                numa.node_of[cpu] = Some(node);
+            }
+            numa.nr_nodes += 1;
+        }
+        numa.nr_nodes = numa.nr_nodes.max(1);
+        Ok(numa)
+    }
+
+    /// None for CPUs listed under no node, e.g. without CONFIG_NUMA
+    pub fn node_of(&self, cpu: u32) -> Option<u32> {
+        self.node_of.get(cpu as usize).copied().flatten()
+    }
+}
@@ -0,0 +1,105 @@
+// SPDX-License-Identifier: GPL-2.0
+//! Chrome Trace Event output, viewable in Perfetto or chrome://tracing
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::TaskEvent;
+
+#[derive(Debug, Serialize)]
+struct TraceEvent {
+    name: &'static str,
+    ph: &'static str,
+    // Microseconds, as the format expects
+    ts: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dur: Option<f64>,
+    pid: i32,
+    tid: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    args: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TraceFile<'a> {
+    trace_events: &'a [TraceEvent],
+    display_time_unit: &'static str,
+}
+
+/// Buffers run/sleep spans until shutdown, one track per thread
+#[derive(Debug, Default)]
+pub struct Trace {
+    events: Vec<TraceEvent>,
+    threads: HashMap<i32, (i32, String)>,
+}
+
+fn ns_to_us(ns: u64) -> f64 {
+    ns as f64 / 1000.0
+}
+
+impl Trace {
+    pub fn record(&mut self, event: &TaskEvent) {
+        self.threads.insert(event.tid, (event.tgid, event.comm().into_owned()));
+
+        if event.sleep_start > 0 && event.sleep_end > event.sleep_start {
+            self.events.push(TraceEvent {
+                name: "sleep",
+                ph: "X",
+                ts: ns_to_us(event.sleep_start),
+                dur: Some(ns_to_us(event.sleep_end - event.sleep_start)),
+                pid: event.tgid,
+                tid: event.tid,
+                args: None,
+            });
+        }
+
+        // Runtime-only events carry no wakeup time to anchor the slice to.
+        // The slice starts at wakeup and spans the CPU time used, so any
+        // preemption before the next sleep is folded into it.
+        if event.sleep_end > 0 && event.runtime_ns > 0 {
+            self.events.push(TraceEvent {
+                name: "running",
+                ph: "X",
+                ts: ns_to_us(event.sleep_end),
+                dur: Some(ns_to_us(event.runtime_ns)),
+                pid: event.tgid,
+                tid: event.tid,
+                args: Some(serde_json::json!({
+                    "cpu": event.cpu,
+                    "wait_us": ns_to_us(event.wait_ns),
+                    "preempted": event.nr_preempted,
+                })),
+            });
+        }
+    }
+
+    pub fn write(&mut self, path: &str) -> Result<()> {
+        // Name each track after the thread
+        for (&tid, (tgid, comm)) in self.threads.iter() {
+            self.events.push(TraceEvent {
+                name: "thread_name",
+                ph: "M",
+                ts: 0.0,
+                dur: None,
+                pid: *tgid,
+                tid,
+                args: Some(serde_json::json!({ "name": comm })),
+            });
+        }
+
+        let file = File::create(path).with_context(|| format!("Failed to create trace file {}", path))?;
+        let trace = TraceFile {
+            trace_events: &self.events,
+            display_time_unit: "ns",
+        };
+        // Flushed here rather than on drop, where a failed write goes unnoticed
+        let mut out = BufWriter::new(file);
+        serde_json::to_writer(&mut out, &trace).with_context(|| format!("Failed to write trace file {}", path))?;
+        out.flush().with_context(|| format!("Failed to write trace file {}", path))
+    }
+}
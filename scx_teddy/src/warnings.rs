@@ -0,0 +1,32 @@
+// SPDX-License-Identifier: GPL-2.0
+//! Warnings that --strict turns into a failed run, for CI gating
+
+pub struct Warnings {
+    strict: bool,
+    raised: Vec<String>,
+}
+
+impl Warnings {
+    pub fn new(strict: bool) -> Self {
+        Self { strict, raised: Vec::new() }
+    }
+
+    /// Print a warning and keep it for the next fatal() check
+    pub fn warn(&mut self, message: String) {
+        eprintln!("Warning: {}", message);
+        self.raised.push(message);
+    }
+
+    /// At a decision point: with --strict, whether a warning was raised so
+    /// far, listing them as the reason the run fails
+    pub fn fatal(&self) -> bool {
+        if !self.strict || self.raised.is_empty() {
+            return false;
+        }
+        eprintln!("Error: --strict treats {} warning(s) as errors:", self.raised.len());
+        for message in &self.raised {
+            eprintln!("  {}", message);
+        }
+        true
+    }
+}
@@ -0,0 +1,84 @@
+// SPDX-License-Identifier: GPL-2.0
+//! What each interval report covers, see --window-mode
+
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+use crate::stats::TaskStats;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum WindowMode {
+    /// Only the interval that just ended
+    #[default]
+    Reset,
+    /// The last --window-intervals intervals
+    Sliding,
+    /// Everything since the start, reported every interval
+    Cumulative,
+}
+
+/// Stats reported for an interval in place of the interval's own
+pub struct View {
+    pub stats: HashMap<i32, TaskStats>,
+    // Time the stats span, and how many intervals that is
+    pub period: Duration,
+    pub intervals: usize,
+}
+
+pub struct Window {
+    mode: WindowMode,
+    // Sliding: the stats of each kept interval, oldest first, with its length
+    intervals: VecDeque<(HashMap<i32, TaskStats>, Duration)>,
+    keep: usize,
+    // Cumulative: every interval so far, merged
+    total: HashMap<i32, TaskStats>,
+    total_period: Duration,
+    total_intervals: usize,
+}
+
+// Fold `from` into `into`, starting tasks new to `into` from scratch
+fn merge_into(into: &mut HashMap<i32, TaskStats>, from: &HashMap<i32, TaskStats>) {
+    for (&tid, task_stats) in from {
+        into.entry(tid).or_insert_with(|| TaskStats::new(task_stats.parent)).merge(task_stats);
+    }
+}
+
+impl Window {
+    pub fn new(mode: WindowMode, keep: usize) -> Self {
+        Self {
+            mode,
+            intervals: VecDeque::with_capacity(if mode == WindowMode::Sliding { keep } else { 0 }),
+            keep,
+            total: HashMap::new(),
+            total_period: Duration::ZERO,
+            total_intervals: 0,
+        }
+    }
+
+    /// Add the interval that just ended and return what to report instead of
+    /// it, None in reset mode where the interval itself is reported
+    pub fn push(&mut self, interval: &HashMap<i32, TaskStats>, period: Duration) -> Option<View> {
+        match self.mode {
+            WindowMode::Reset => None,
+            WindowMode::Sliding => {
+                if self.intervals.len() == self.keep {
+                    self.intervals.pop_front();
+                }
+                self.intervals.push_back((interval.clone(), period));
+
+                let mut merged = HashMap::new();
+                for (stats, _) in &self.intervals {
+                    merge_into(&mut merged, stats);
+                }
+                let span = self.intervals.iter().map(|(_, period)| *period).sum();
+                Some(View { stats: merged, period: span, intervals: self.intervals.len() })
+            }
+            WindowMode::Cumulative => {
+                merge_into(&mut self.total, interval);
+                self.total_period += period;
+                self.total_intervals += 1;
+                Some(View { stats: self.total.clone(), period: self.total_period, intervals: self.total_intervals })
+            }
+        }
+    }
+}